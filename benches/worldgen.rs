@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use minecraft_rs::{
+    config::{TerrainMode, WorldGenConfig},
+    world::{gen::WorldGenerator, registry, World},
+};
+
+const BLOCKS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config/blocks.toml");
+const ITEMS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config/items.toml");
+const WORLD_CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config/world.toml");
+
+fn generator(terrain_mode: TerrainMode) -> WorldGenerator {
+    let mut config = WorldGenConfig::load(WORLD_CONFIG_PATH).unwrap();
+    config.terrain_mode = terrain_mode;
+    WorldGenerator::new(1, config, Arc::new(World::new()))
+}
+
+/// Whole chunks, so the noise lattices and the biome grid are measured as they are used
+fn generate_chunk(c: &mut Criterion) {
+    registry::load(BLOCKS_PATH, ITEMS_PATH).unwrap();
+    for (name, terrain_mode) in [
+        ("heightmap", TerrainMode::Heightmap),
+        ("density", TerrainMode::Density),
+    ] {
+        let gen = generator(terrain_mode);
+        let mut chunk_x = 0;
+        c.bench_function(&format!("generate_chunk_{}", name), |b| {
+            b.iter(|| {
+                // A new chunk every time, so nothing is cached between iterations
+                chunk_x += 1;
+                // Boxed, as passing the whole chunk through black_box by value crashes LLVM
+                Box::new(gen.generate_chunk_standalone(chunk_x, 0))
+            })
+        });
+    }
+}
+
+criterion_group!(benches, generate_chunk);
+criterion_main!(benches);
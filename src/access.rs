@@ -0,0 +1,308 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::ErrorKind,
+    sync::{Arc, Mutex},
+};
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::io;
+use uuid::Uuid;
+
+use crate::{command::OP_PERMISSION_LEVEL, listener::GameListener, model::Player, world::BlockPos};
+
+/// A set of entries such as banned addresses, stored in a text file with one entry per line.
+/// Lines starting with `#` are comments. Changes are written back right away.
+pub struct AccessList {
+    path: String,
+    entries: Mutex<BTreeSet<String>>,
+    /// Held across a save, so an older list can't overwrite a newer one
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl AccessList {
+    /// Loads the list, a missing file is an empty list
+    pub fn load(path: &str) -> Result<AccessList, String> {
+        let entries = match fs::read_to_string(path) {
+            Ok(data) => data
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path, e)),
+        };
+
+        Ok(AccessList {
+            path: path.to_string(),
+            entries: Mutex::new(entries),
+            save_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn contains(&self, entry: &str) -> bool {
+        self.entries.lock().unwrap().contains(entry)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Adds an entry and returns false if it was already there
+    pub async fn add(&self, entry: &str) -> io::Result<bool> {
+        let added = self.entries.lock().unwrap().insert(entry.to_string());
+        if added {
+            self.save().await?;
+        }
+        Ok(added)
+    }
+
+    /// Removes an entry and returns false if it wasn't there
+    pub async fn remove(&self, entry: &str) -> io::Result<bool> {
+        let removed = self.entries.lock().unwrap().remove(entry);
+        if removed {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    async fn save(&self) -> io::Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let data: String = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|entry| format!("{}\n", entry))
+            .collect();
+        write_replacing(&self.path, data).await
+    }
+}
+
+/// Writes a file through a temporary one next to it, so a crash mid-write keeps the old file
+async fn write_replacing(path: &str, data: String) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    tokio::fs::write(&tmp_path, data).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+/// A banned player. The name is missing when the ban was given by UUID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub uuid: String,
+    pub name: Option<String>,
+    pub reason: String,
+    /// Unix time in seconds
+    pub created: u64,
+}
+
+impl Ban {
+    /// Whether the ban is for the player given by name or UUID
+    pub fn matches(&self, target: &str) -> bool {
+        self.uuid.eq_ignore_ascii_case(target)
+            || self
+                .name
+                .as_ref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(target))
+    }
+}
+
+/// Banned players, stored as JSON like the vanilla `banned-players.json`
+pub struct BanList {
+    path: String,
+    bans: Mutex<Vec<Ban>>,
+    /// Held across a save, so an older list can't overwrite a newer one
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl BanList {
+    /// Loads the list, a missing file is an empty list
+    pub fn load(path: &str) -> Result<BanList, String> {
+        let bans = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str::<Vec<Ban>>(&data)
+                .map_err(|e| format!("Failed to parse {}: {}", path, e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path, e)),
+        };
+
+        Ok(BanList {
+            path: path.to_string(),
+            bans: Mutex::new(bans),
+            save_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Finds the ban of a player. Names are checked too, as offline UUIDs change with the
+    /// case of the name.
+    pub fn find(&self, username: &str, uuid: Uuid) -> Option<Ban> {
+        self.bans
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|ban| ban.matches(username) || ban.matches(&uuid.to_string()))
+            .cloned()
+    }
+
+    /// Adds a ban, replacing an earlier one of the same player
+    pub async fn add(&self, ban: Ban) -> io::Result<()> {
+        {
+            let mut bans = self.bans.lock().unwrap();
+            bans.retain(|b| b.uuid != ban.uuid);
+            bans.push(ban);
+        }
+        self.save().await
+    }
+
+    /// Removes the ban of the player given by name or UUID
+    pub async fn remove(&self, target: &str) -> io::Result<Option<Ban>> {
+        let removed = {
+            let mut bans = self.bans.lock().unwrap();
+            let idx = bans.iter().position(|ban| ban.matches(target));
+            idx.map(|idx| bans.remove(idx))
+        };
+        if removed.is_some() {
+            self.save().await?;
+        }
+        Ok(removed)
+    }
+
+    pub fn bans(&self) -> Vec<Ban> {
+        self.bans.lock().unwrap().clone()
+    }
+
+    async fn save(&self) -> io::Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let data = serde_json::to_string_pretty(&*self.bans.lock().unwrap())?;
+        write_replacing(&self.path, data).await
+    }
+}
+
+/// A named box of blocks that only its owners may change. Both corners are inside.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Region {
+    pub name: String,
+    pub min: [i32; 3],
+    pub max: [i32; 3],
+    /// Usernames or UUIDs, like the ops in the config
+    pub owners: Vec<String>,
+}
+
+impl Region {
+    pub fn contains(&self, pos: BlockPos) -> bool {
+        (self.min[0]..=self.max[0]).contains(&pos.x)
+            && (self.min[1]..=self.max[1]).contains(&pos.y)
+            && (self.min[2]..=self.max[2]).contains(&pos.z)
+    }
+
+    pub fn is_owner(&self, username: &str, uuid: Uuid) -> bool {
+        self.owners.iter().any(|owner| {
+            owner.eq_ignore_ascii_case(username) || owner.parse::<Uuid>().is_ok_and(|u| u == uuid)
+        })
+    }
+}
+
+/// Protected regions, stored as JSON
+pub struct RegionList {
+    path: String,
+    regions: Mutex<Vec<Region>>,
+    /// Held across a save, so an older list can't overwrite a newer one
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl RegionList {
+    /// Loads the list, a missing file is an empty list
+    pub fn load(path: &str) -> Result<RegionList, String> {
+        let regions = match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str::<Vec<Region>>(&data)
+                .map_err(|e| format!("Failed to parse {}: {}", path, e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path, e)),
+        };
+
+        Ok(RegionList {
+            path: path.to_string(),
+            regions: Mutex::new(regions),
+            save_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Adds a region without owners between two corners in any order. Returns false if
+    /// there already is one with the name.
+    pub async fn define(&self, name: &str, a: BlockPos, b: BlockPos) -> io::Result<bool> {
+        {
+            let mut regions = self.regions.lock().unwrap();
+            if regions.iter().any(|r| r.name.eq_ignore_ascii_case(name)) {
+                return Ok(false);
+            }
+            regions.push(Region {
+                name: name.to_string(),
+                min: [a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)],
+                max: [a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)],
+                owners: Vec::new(),
+            });
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    /// Adds an owner to a region. Returns false if there is no region with the name.
+    pub async fn add_owner(&self, name: &str, owner: &str) -> io::Result<bool> {
+        {
+            let mut regions = self.regions.lock().unwrap();
+            let region = match regions
+                .iter_mut()
+                .find(|r| r.name.eq_ignore_ascii_case(name))
+            {
+                Some(region) => region,
+                None => return Ok(false),
+            };
+            if !region.owners.iter().any(|o| o.eq_ignore_ascii_case(owner)) {
+                region.owners.push(owner.to_string());
+            }
+        }
+        self.save().await?;
+        Ok(true)
+    }
+
+    /// Finds a region at the position that the player doesn't own. Where regions overlap
+    /// the player has to own all of them.
+    pub fn find_foreign(&self, pos: BlockPos, username: &str, uuid: Uuid) -> Option<Region> {
+        self.regions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|r| r.contains(pos) && !r.is_owner(username, uuid))
+            .cloned()
+    }
+
+    async fn save(&self) -> io::Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let data = serde_json::to_string_pretty(&*self.regions.lock().unwrap())?;
+        write_replacing(&self.path, data).await
+    }
+
+    fn check(&self, player: &Player, location: BlockPos) -> Result<(), String> {
+        if player.permission_level >= OP_PERMISSION_LEVEL {
+            return Ok(());
+        }
+        match self.find_foreign(location, &player.username, player.uuid) {
+            Some(region) => Err(format!("This area is protected by region {}", region.name)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl GameListener for Arc<RegionList> {
+    fn on_block_break(&self, player: &Player, location: BlockPos, _: u16) -> Result<(), String> {
+        self.check(player, location)
+    }
+
+    fn on_block_place(&self, player: &Player, location: BlockPos, _: u16) -> Result<(), String> {
+        self.check(player, location)
+    }
+}
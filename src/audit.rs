@@ -0,0 +1,214 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::error;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    select,
+    sync::mpsc,
+    time,
+};
+
+use crate::{server::BlockChange, world::BlockPos};
+
+/// Buffered changes are written at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Size after which the log is moved to `<path>.1`, replacing the older log there
+const ROTATE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// One block change in the log
+#[derive(Debug, Clone)]
+pub struct BlockLogEntry {
+    /// Unix time in seconds
+    pub time: u64,
+    pub player: String,
+    pub location: BlockPos,
+    pub old_state: u16,
+    pub new_state: u16,
+}
+
+impl BlockLogEntry {
+    pub fn action(&self) -> &'static str {
+        match (self.old_state, self.new_state) {
+            (0, _) => "place",
+            (_, 0) => "break",
+            _ => "replace",
+        }
+    }
+
+    /// Tab separated, so the log is easy to grep
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{} {} {}\t{}\t{}\t{}\n",
+            self.time,
+            self.player,
+            self.location.x,
+            self.location.y,
+            self.location.z,
+            self.old_state,
+            self.new_state,
+            self.action()
+        )
+    }
+
+    fn parse(line: &str) -> Option<BlockLogEntry> {
+        let mut fields = line.split('\t');
+        let time = fields.next()?.parse().ok()?;
+        let player = fields.next()?.to_string();
+        let mut coords = fields.next()?.split(' ').map(|c| c.parse::<i32>());
+        let location = BlockPos::new(
+            coords.next()?.ok()?,
+            coords.next()?.ok()?,
+            coords.next()?.ok()?,
+        );
+        Some(BlockLogEntry {
+            time,
+            player,
+            location,
+            old_state: fields.next()?.parse().ok()?,
+            new_state: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// Appends block changes to a log file, for finding out who griefed what with `/lookup`.
+/// Changes are queued without a limit rather than taken from the event bus, which drops
+/// events for subscribers that fall behind.
+pub struct BlockLog {
+    path: String,
+    tx: mpsc::UnboundedSender<Vec<BlockLogEntry>>,
+}
+
+impl BlockLog {
+    /// Opens the log and starts writing to it in the background
+    pub fn start(path: &str) -> Arc<BlockLog> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let block_log = Arc::new(BlockLog {
+            path: path.to_string(),
+            tx,
+        });
+
+        let log = block_log.clone();
+        tokio::spawn(async move {
+            log.run(rx).await;
+        });
+        block_log
+    }
+
+    /// Queues changes for writing. Nothing happens if writing already failed.
+    pub fn record(&self, player: &str, changes: &[BlockChange]) {
+        let time = unix_time();
+        let entries = changes
+            .iter()
+            .map(|change| BlockLogEntry {
+                time,
+                player: player.to_string(),
+                location: change.location,
+                old_state: change.old_state,
+                new_state: change.new_state,
+            })
+            .collect();
+        let _ = self.tx.send(entries);
+    }
+
+    async fn run(&self, mut entries: mpsc::UnboundedReceiver<Vec<BlockLogEntry>>) {
+        if let Err(e) = self.write_entries(&mut entries).await {
+            error!(
+                "Block log {} failed, changes are not logged: {}",
+                self.path, e
+            );
+        }
+    }
+
+    async fn write_entries(
+        &self,
+        entries: &mut mpsc::UnboundedReceiver<Vec<BlockLogEntry>>,
+    ) -> io::Result<()> {
+        if let Some(dir) = Path::new(&self.path).parent() {
+            fs::create_dir_all(dir).await?;
+        }
+        let mut writer = BufWriter::new(self.open().await?);
+        let mut flush_interval = time::interval(FLUSH_INTERVAL);
+
+        loop {
+            select! {
+                batch = entries.recv() => match batch {
+                    Some(batch) => {
+                        for entry in batch {
+                            writer.write_all(entry.to_line().as_bytes()).await?;
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush_interval.tick() => {
+                    writer.flush().await?;
+                    if fs::metadata(&self.path).await?.len() >= ROTATE_SIZE {
+                        fs::rename(&self.path, self.rotated_path()).await?;
+                        writer = BufWriter::new(self.open().await?);
+                    }
+                }
+            }
+        }
+        writer.flush().await
+    }
+
+    async fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+    }
+
+    fn rotated_path(&self) -> String {
+        format!("{}.1", self.path)
+    }
+
+    /// Finds the latest changes at a position, newest first. Changes from the last
+    /// second may not be written yet.
+    pub async fn lookup(&self, location: BlockPos, limit: usize) -> io::Result<Vec<BlockLogEntry>> {
+        // Read line by line and only keep the latest matches, the logs can be large
+        let mut entries = VecDeque::with_capacity(limit + 1);
+        for path in [self.rotated_path(), self.path.clone()] {
+            let file = match File::open(&path).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut lines = BufReader::new(file).lines();
+            while let Some(line) = lines.next_line().await? {
+                let entry = match BlockLogEntry::parse(&line) {
+                    Some(entry) if entry.location == location => entry,
+                    _ => continue,
+                };
+                entries.push_back(entry);
+                if entries.len() > limit {
+                    entries.pop_front();
+                }
+            }
+        }
+
+        Ok(entries.into_iter().rev().collect())
+    }
+}
+
+/// Rough age for humans, like `5 min`
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{} s", secs),
+        60..=3599 => format!("{} min", secs / 60),
+        3600..=86399 => format!("{} h", secs / 3600),
+        _ => format!("{} d", secs / 86400),
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -1,66 +1,172 @@
-use std::{ops::Add, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::IpAddr,
+    ops::Add,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 use dashmap::DashSet;
 use futures::{SinkExt, StreamExt};
-use indoc::indoc;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
+use rand::Rng;
 use serde_json::json;
 use tokio::{
-    io,
+    fs, io,
     net::TcpStream,
     select,
-    sync::mpsc,
+    sync::{mpsc, watch},
+    task,
     time::{self, Instant},
 };
 use tokio_util::codec::Framed;
 
 use crate::{
     block_id, block_meta, block_state, chat_packet,
-    command::Command,
+    command::{self, Command, OP_PERMISSION_LEVEL},
+    config::MAX_BORDER_RADIUS,
     mc::{
-        codec::MinecraftCodec,
+        codec::{MinecraftCodec, PACKET_SIZE_LIMIT},
         proto::{
-            AbilityFlags, DiggingStatus, EntityMetaData, EntityMetaEntry, GameStateReason, Packet,
+            AbilityFlags, BlockChangeRecord, ChunkData, ClientStatusAction, DiggingStatus,
+            EntityAction, EntityMetaEntry, GameStateReason, Packet, UseEntityAction,
+            PARTICLE_BLOCK_CRACK,
         },
-        proto::{PlayState, PlayerListItemAction},
+        proto::{PlayState, PlayerListItemAction, PROTOCOL_VERSION, VERSION_NAME},
+    },
+    model::{
+        DamageCause, Entity, GameMode, ItemStack, Player, Projectile, ProjectileKind, Vec3d,
+        WorldEntity,
     },
-    model::{GameMode, ItemStack, Player},
-    server::ServerHandler,
-    world::{BlockFace, BlockPos, Chunk, ChunkPos, MutexChunkRef},
+    server::{BlockChange, GameEvent, PlayerEvent, PlayerInfo, ServerHandler, PARTICLE_RANGE},
+    utils::{strip_colors, translate_color_codes, RateLimiter},
+    world::{
+        block,
+        coords::{world_to_chunk, world_to_local},
+        is_liquid, is_replaceable, is_solid,
+        light::ChunkLight,
+        lock_chunk,
+        registry::registry,
+        structure::Structure,
+        BlockFace, BlockPos, ChunkPos, MutexChunkRef,
+    },
+};
+
+const ATTACK_REACH: f64 = 6.0;
+const USE_REACH: f64 = 5.0;
+const EYE_HEIGHT: f64 = 1.62;
+const ENDER_PEARL_DAMAGE: f32 = 5.0;
+/// Distance in blocks up to which players see entities other than players
+const ENTITY_TRACKING_RANGE: f64 = 64.0;
+/// Bytes a chunk batch may use, leaving room for the packet header
+const CHUNK_BATCH_SIZE_LIMIT: usize = PACKET_SIZE_LIMIT - 64;
+/// Position and bitmask of each chunk in a batch
+const CHUNK_META_SIZE: usize = 10;
+/// Number of changed blocks in a chunk from which the chunk is sent as a whole
+const FULL_CHUNK_RESEND_THRESHOLD: usize = 64;
+const TICK_DURATION: Duration = Duration::from_millis(50);
+const EAT_DURATION: u32 = 32;
+/// Ticks between saves of the player data, five minutes
+const PLAYER_SAVE_INTERVAL: u64 = 6000;
+/// Vanilla shows at most this many names when hovering the player count
+const STATUS_SAMPLE_SIZE: usize = 12;
+/// Chat messages and commands a player can send at once, and per second after that
+const CHAT_BURST: f64 = 10.0;
+const CHAT_RATE: f64 = 1.0;
+/// Refused chat messages in a row after which the player is kicked
+const MAX_CHAT_VIOLATIONS: u32 = 5;
+/// Longest chat message the vanilla client sends
+const MAX_CHAT_LENGTH: usize = 100;
+/// Longest nickname without its color codes
+const MAX_NICK_LENGTH: usize = 32;
+/// Clients send a movement packet every tick, the burst leaves room for lag spikes
+const MOVEMENT_BURST: f64 = 40.0;
+const MOVEMENT_RATE: f64 = 20.0;
+const MIN_Y_COORD: f64 = -1024.0;
+const MAX_Y_COORD: f64 = 1024.0;
+/// Farther moves within one packet are undone, this is well above the fastest flight
+const MAX_MOVE_DISTANCE: f64 = 100.0;
+/// Keep-alives that may wait for an answer at once, older ones are forgotten
+const MAX_PENDING_KEEP_ALIVES: usize = 8;
+const INVALID_MOVE_REASON: &str = "Invalid move packet received";
+pub const SPAWN_POS: Vec3d = Vec3d {
+    x: 0.0,
+    y: 69.0,
+    z: 0.0,
 };
 
 pub struct ClientHandler {
+    address: IpAddr,
     msg_stream: Framed<TcpStream, MinecraftCodec>,
     unicast_rx: mpsc::Receiver<Packet>,
+    /// Movement and block updates, see `Packet::is_bulk`
+    bulk_rx: mpsc::Receiver<Packet>,
+    event_rx: mpsc::Receiver<PlayerEvent>,
     server: Arc<ServerHandler>,
     player: Player,
     known_chunks: DashSet<ChunkPos>,
+    /// World entities the client has been sent, see `update_tracked_entities`
+    known_entities: HashSet<i32>,
     current_chunk_pos: ChunkPos,
+    digging: Option<(BlockPos, Instant)>,
+    ticks: u64,
+    shutdown_rx: watch::Receiver<bool>,
+    chat_limit: RateLimiter,
+    chat_violations: u32,
+    movement_limit: RateLimiter,
+    /// Set once the player passed all login checks and joined the game
+    logged_in: bool,
+    /// Set by packet handlers to close the connection after the current packet
+    kick_reason: Option<String>,
+    /// When the client last answered a keep-alive
+    last_keep_alive: Instant,
+    /// Ids of the keep-alives sent but not answered yet, oldest first
+    pending_keep_alives: VecDeque<(i32, Instant)>,
 }
 
 impl ClientHandler {
     pub fn new(
         id: i32,
+        address: IpAddr,
         msg_stream: Framed<TcpStream, MinecraftCodec>,
         unicast_rx: mpsc::Receiver<Packet>,
         server: Arc<ServerHandler>,
     ) -> ClientHandler {
         let game_mode = server.config.game_mode;
+        let event_rx = server.add_player_events(id);
+        let bulk_rx = server.add_bulk_queue(id);
+        let shutdown_rx = server.subscribe_shutdown();
         ClientHandler {
+            address,
             msg_stream,
             unicast_rx,
+            bulk_rx,
+            event_rx,
             server,
             player: Player::new(id, game_mode),
             known_chunks: DashSet::new(),
+            known_entities: HashSet::new(),
             current_chunk_pos: ChunkPos::new(0, 0),
+            digging: None,
+            ticks: 0,
+            shutdown_rx,
+            chat_limit: RateLimiter::new(CHAT_BURST, CHAT_RATE),
+            chat_violations: 0,
+            movement_limit: RateLimiter::new(MOVEMENT_BURST, MOVEMENT_RATE),
+            logged_in: false,
+            kick_reason: None,
+            last_keep_alive: Instant::now(),
+            pending_keep_alives: VecDeque::new(),
         }
     }
 
     pub async fn loop_until_disconnect(&mut self) {
-        let mut keep_alive_interval = time::interval_at(
-            Instant::now().add(Duration::from_secs(5)),
-            Duration::from_secs(10),
-        );
+        let keep_alive_period = Duration::from_secs(self.server.config.keep_alive_interval_secs);
+        let keep_alive_timeout = Duration::from_secs(self.server.config.keep_alive_timeout_secs);
+        let mut keep_alive_interval =
+            time::interval_at(Instant::now().add(keep_alive_period), keep_alive_period);
+        let mut tick_interval = time::interval(TICK_DURATION);
 
         loop {
             select! {
@@ -71,9 +177,15 @@ impl ClientHandler {
 
                     match packet_in.unwrap() {
                         Ok(packet) => {
-                            self.handle_packet(packet)
-                                .await
-                                .expect("Packet handler failed");
+                            let result = match self.check_rate_limit(&packet).await {
+                                Ok(true) => self.handle_packet(packet).await,
+                                Ok(false) => Ok(()),
+                                Err(err) => Err(err),
+                            };
+                            if let Err(err) = result {
+                                error!("Packet handler failed: {}", err);
+                                break;
+                            }
                         }
                         Err(err) => {
                             error!("Client receive failed: {}", err);
@@ -83,29 +195,125 @@ impl ClientHandler {
 
                 },
                 packet_out = self.unicast_rx.recv() => {
-                    if packet_out.is_none() {
+                    let packet = match packet_out {
+                        Some(packet) => packet,
+                        None => break,
+                    };
+                    if let Err(err) = self.send_packet(packet).await {
+                        error!("Client send failed: {}", err);
                         break;
                     }
-
-                    self.msg_stream.send(packet_out.unwrap()).await.expect("Client send failed");
+                }
+                Some(packet) = self.bulk_rx.recv() => {
+                    if let Err(err) = self.send_packet(packet).await {
+                        error!("Client send failed: {}", err);
+                        break;
+                    }
+                }
+                Some(event) = self.event_rx.recv() => {
+                    if let Err(err) = self.handle_player_event(event).await {
+                        error!("Player event handler failed: {}", err);
+                        break;
+                    }
+                }
+                _ = tick_interval.tick() => {
+                    if self.logged_in {
+                        if let Err(err) = self.tick().await {
+                            error!("Player tick failed: {}", err);
+                            break;
+                        }
+                    }
+                }
+                _ = self.shutdown_rx.changed() => {
+                    self.disconnect("Server closed").await;
+                    break;
                 }
                 _ = keep_alive_interval.tick() => {
-                    self.msg_stream
-                        .send(Packet::S00KeepAlive { timestamp: 69 })
-                        .await
-                        .expect("Client keep-alive failed");
+                    // Keep-alives only exist in the play state. The timeout is checked here,
+                    // so it is noticed up to one interval late.
+                    let in_play = *self.msg_stream.codec().state() == PlayState::Play;
+                    if in_play && self.last_keep_alive.elapsed() > keep_alive_timeout {
+                        self.kick_reason = Some("Timed out".to_string());
+                    } else if in_play {
+                        let id = rand::thread_rng().gen();
+                        if self.pending_keep_alives.len() >= MAX_PENDING_KEEP_ALIVES {
+                            self.pending_keep_alives.pop_front();
+                        }
+                        self.pending_keep_alives.push_back((id, Instant::now()));
+                        if let Err(err) = self.msg_stream.send(Packet::S00KeepAlive { id }).await {
+                            error!("Client keep-alive failed: {}", err);
+                            break;
+                        }
+                    }
                 }
             }
+
+            if let Some(reason) = self.kick_reason.take() {
+                info!(
+                    "Kicking {} ({}): {}",
+                    self.player.username, self.address, reason
+                );
+                self.disconnect(&reason).await;
+                break;
+            }
         }
 
-        self.msg_stream.close().await.unwrap();
+        let _ = self.msg_stream.close().await;
         self.unicast_rx.close();
         self.server.remove_client(self.player.eid);
-        if self.player.is_logged_in() {
+        if self.logged_in {
+            self.server
+                .save_player_data(self.player.uuid, self.player.to_data())
+                .await;
+            self.server.remove_player(self.player.eid);
+            self.server.notify_leave(&self.player);
+            if let Err(e) = self.server.leave_team(&self.player.username).await {
+                error!(
+                    "Failed to remove {} from their team: {}",
+                    self.player.username, e
+                );
+            }
             self.server.change_num_players(-1);
         }
     }
 
+    /// Tells the client why the connection is closed. The caller stops the loop.
+    async fn disconnect(&mut self, reason: &str) {
+        let reason = json!({ "text": reason }).to_string();
+        let packet = match self.msg_stream.codec().state() {
+            PlayState::Login => Packet::S00LoginDisconnect { reason },
+            PlayState::Play => Packet::S40Disconnect { reason },
+            _ => return,
+        };
+        let _ = self.msg_stream.send(packet).await;
+    }
+
+    /// Whether the packet is within its rate limit. Chat over the limit is refused with
+    /// a warning and counts towards a kick, extra movement packets are dropped silently.
+    async fn check_rate_limit(&mut self, packet: &Packet) -> io::Result<bool> {
+        match packet {
+            Packet::C01ChatMessage { .. } => {
+                if self.chat_limit.try_acquire() {
+                    self.chat_violations = 0;
+                    return Ok(true);
+                }
+
+                self.chat_violations += 1;
+                if self.chat_violations >= MAX_CHAT_VIOLATIONS {
+                    self.kick_reason = Some("Kicked for spamming".to_string());
+                }
+                self.send_packet(chat_packet!(1, "§cYou are sending messages too quickly"))
+                    .await?;
+                Ok(false)
+            }
+            Packet::C03Player { .. }
+            | Packet::C04PlayerPos { .. }
+            | Packet::C05PlayerRot { .. }
+            | Packet::C06PlayerPosRot { .. } => Ok(self.movement_limit.try_acquire()),
+            _ => Ok(true),
+        }
+    }
+
     async fn handle_packet(&mut self, packet: Packet) -> io::Result<()> {
         trace!("Received {:?}", packet);
 
@@ -115,27 +323,35 @@ impl ClientHandler {
                 next_state,
                 ..
             } => {
-                if protocol_version != 47 {
-                    panic!("Unsupported protocol version");
-                }
-
                 self.msg_stream.codec_mut().set_state(next_state);
+
+                // Status requests are still answered, the client then shows the version
+                // mismatch in its server list
+                if protocol_version != PROTOCOL_VERSION && next_state == PlayState::Login {
+                    self.kick_reason =
+                        Some(format!("Outdated client! Please use {}", VERSION_NAME));
+                }
             }
 
             Packet::C00StatusRequest => {
+                let sample: Vec<_> = self
+                    .server
+                    .players
+                    .iter()
+                    .take(STATUS_SAMPLE_SIZE)
+                    .map(|p| json!({ "name": p.username, "id": p.uuid.to_string() }))
+                    .collect();
                 let status = json!({
                     "version": {
-                        "name": "1.8.0",
-                        "protocol": 47
+                        "name": VERSION_NAME,
+                        "protocol": PROTOCOL_VERSION
                     },
                     "players":{
                         "max": self.server.config.slots,
                         "online": self.server.num_players(),
-                        "sample": []
+                        "sample": sample
                     },
-                    "description": {
-                        "text": self.server.config.motd
-                    }
+                    "description": self.server.config.motd_component()
                 });
                 self.send_packet(Packet::S00StatusResponse {
                     status: status.to_string(),
@@ -148,7 +364,37 @@ impl ClientHandler {
             }
 
             Packet::C00LoginStart { username } => {
+                if self.server.is_ip_banned(self.address) {
+                    self.kick_reason = Some("You are banned from this server".to_string());
+                    return Ok(());
+                }
+
+                self.player.uuid = Player::offline_uuid(&username);
+                if let Some(ban) = self.server.banned_players.find(&username, self.player.uuid) {
+                    self.kick_reason =
+                        Some(format!("You are banned from this server: {}", ban.reason));
+                    return Ok(());
+                }
+                self.player.permission_level = self
+                    .server
+                    .config
+                    .permission_level(&username, self.player.uuid);
+                if self.player.permission_level < OP_PERMISSION_LEVEL
+                    && !self.server.is_whitelisted(&username, self.player.uuid)
+                {
+                    self.kick_reason = Some("You are not whitelisted on this server".to_string());
+                    return Ok(());
+                }
                 self.player.username = username;
+                self.player.position = SPAWN_POS;
+                if let Some(data) = self.server.load_player_data(self.player.uuid).await {
+                    self.player.restore(&data);
+                }
+                if let Err(reason) = self.server.check_join(&self.player) {
+                    self.kick_reason = Some(reason);
+                    return Ok(());
+                }
+                self.logged_in = true;
                 self.server.change_num_players(1);
 
                 // Enable compression
@@ -175,24 +421,52 @@ impl ClientHandler {
                     dimension: 0,
                     difficulty: self.server.config.difficulty,
                     player_list_size: 4,
-                    world_type: "default".to_string(),
-                    reduced_debug_info: false,
+                    world_type: self.server.config.world_type.clone(),
+                    reduced_debug_info: self.server.config.reduced_debug_info,
+                })
+                .await?;
+                self.send_packet(Packet::S41ServerDifficulty {
+                    difficulty: self.server.config.difficulty,
                 })
                 .await?;
+                self.send_packet(self.server.world_border().init_packet())
+                    .await?;
 
                 // Send world chunks
-                self.send_chunks(0, 0, self.server.config.view_dist).await?;
+                let position = self.player.position;
+                let center =
+                    ChunkPos::from_block_pos(position.x.floor() as i32, position.z.floor() as i32);
+                let r = self.server.config.view_dist;
+                self.current_chunk_pos = center;
+                self.server.gen.await_region(center.x, center.z, r).await;
+                self.send_chunks(center.x, center.z, r).await?;
 
                 // Spawn player into world
                 self.send_packet(Packet::S08SetPlayerPosition {
-                    x: 0.0,
-                    y: 69.0,
-                    z: 0.0,
-                    yaw: 0.0,
-                    pitch: 0.0,
+                    x: position.x,
+                    y: position.y,
+                    z: position.z,
+                    yaw: self.player.rotation.x,
+                    pitch: self.player.rotation.y,
                     flags: 0,
                 })
                 .await?;
+                self.send_packet(Packet::S30WindowItems {
+                    window_id: 0,
+                    slots: self.player.inventory.clone(),
+                })
+                .await?;
+                self.send_health().await?;
+
+                self.server.add_player(
+                    self.player.eid,
+                    PlayerInfo {
+                        username: self.player.username.clone(),
+                        uuid: self.player.uuid,
+                        position,
+                        address: self.address,
+                    },
+                );
 
                 // Announce player join
                 info!(
@@ -211,35 +485,96 @@ impl ClientHandler {
                         action: PlayerListItemAction::AddPlayer {
                             name: self.player.username.clone(),
                             game_mode: self.player.game_mode,
-                            display_name: None,
+                            display_name: self.display_name_component(),
                             ping: 0,
                         },
                     })
                     .await?;
+                for packet in self.server.team_packets() {
+                    self.send_packet(packet).await?;
+                }
             }
             Packet::C01ChatMessage { message } => {
+                if let Err(reason) = validate_chat(&message) {
+                    self.kick_reason = Some(reason.to_string());
+                    return Ok(());
+                }
+
+                // Only the server decides on colors in the chat
+                let message = strip_colors(&message);
                 let message = message.as_str();
                 if message.starts_with("/") {
                     self.handle_command(message).await?;
                 } else {
+                    if let Err(reason) = self.server.check_chat(&self.player, message) {
+                        if !reason.is_empty() {
+                            self.send_packet(chat_packet!(1, format!("§c{}", reason)))
+                                .await?;
+                        }
+                        return Ok(());
+                    }
                     info!("Chat message: <{}> {}", self.player.username, message);
 
-                    let formatted_message = format!("§b{}§r: {}", self.player.username, message);
+                    let formatted_message = self.server.config.format_chat(
+                        &self.player.username,
+                        self.player.display_name(),
+                        message,
+                    );
                     self.server
                         .send_broadcast(chat_packet!(0, formatted_message))
                         .await?;
                 }
             }
-            Packet::C04PlayerPos { x, y, z, .. } => {
-                self.player.position.x = x;
-                self.player.position.y = y;
-                self.player.position.z = z;
-                self.update_chunks(ChunkPos::from_block_pos(x as i32, z as i32))
+            Packet::C02UseEntity {
+                target,
+                action: UseEntityAction::Attack,
+                ..
+            } => {
+                self.attack(target).await?;
+            }
+            Packet::C00KeepAlive { id } => {
+                let idx = match self.pending_keep_alives.iter().position(|(p, _)| *p == id) {
+                    Some(idx) => idx,
+                    None => {
+                        debug!(
+                            "Dropping unsolicited keep-alive {} from {}",
+                            id, self.address
+                        );
+                        return Ok(());
+                    }
+                };
+
+                // Older keep-alives won't be answered anymore
+                let (_, sent_at) = self.pending_keep_alives[idx];
+                self.pending_keep_alives.drain(..=idx);
+                self.last_keep_alive = Instant::now();
+
+                let ping = sent_at.elapsed().as_millis() as i32;
+                self.server
+                    .send_broadcast(Packet::S38PlayerListItem {
+                        uuid: self.player.uuid,
+                        action: PlayerListItemAction::UpdateLatency { ping },
+                    })
                     .await?;
             }
-            Packet::C05PlayerRot { yaw, pitch, .. } => {
+            Packet::C03Player { on_ground } => {
+                self.update_fall(on_ground).await?;
+            }
+            Packet::C04PlayerPos { x, y, z, on_ground } => {
+                self.move_player(Vec3d { x, y, z }, on_ground).await?;
+            }
+            Packet::C05PlayerRot {
+                yaw,
+                pitch,
+                on_ground,
+            } => {
+                if !yaw.is_finite() || !pitch.is_finite() {
+                    self.kick_reason = Some(INVALID_MOVE_REASON.to_string());
+                    return Ok(());
+                }
                 self.player.rotation.x = yaw;
                 self.player.rotation.y = pitch;
+                self.update_fall(on_ground).await?;
             }
             Packet::C06PlayerPosRot {
                 x,
@@ -247,97 +582,143 @@ impl ClientHandler {
                 z,
                 yaw,
                 pitch,
-                ..
+                on_ground,
             } => {
-                self.player.position.x = x;
-                self.player.position.y = y;
-                self.player.position.z = z;
+                if !yaw.is_finite() || !pitch.is_finite() {
+                    self.kick_reason = Some(INVALID_MOVE_REASON.to_string());
+                    return Ok(());
+                }
                 self.player.rotation.x = yaw;
                 self.player.rotation.y = pitch;
-                self.update_chunks(ChunkPos::from_block_pos(x as i32, z as i32))
-                    .await?;
+                self.move_player(Vec3d { x, y, z }, on_ground).await?;
+            }
+            Packet::C07PlayerDigging {
+                status: DiggingStatus::FinishAction,
+                ..
+            } => {
+                // Released the use button before finishing
+                self.player.eating_ticks = 0;
+            }
+            Packet::C07PlayerDigging {
+                status: DiggingStatus::DropItem | DiggingStatus::DropStack,
+                ..
+            } => {
+                // Not a dig, the location is always the origin. Dropping items from the
+                // inventory isn't supported yet.
             }
             Packet::C07PlayerDigging {
                 location, status, ..
             } => {
+                if !self.server.world_border().contains_block(location) {
+                    let block_state = self
+                        .server
+                        .world
+                        .get_block(location.x, location.y, location.z);
+                    self.reject_dig(location, block_state).await?;
+                    return Ok(());
+                }
+
                 let is_creative = self.player.game_mode == GameMode::Creative;
-                if (is_creative && status == DiggingStatus::StartDigging)
-                    || (!is_creative && status == DiggingStatus::FinishDigging)
+                if is_creative && status == DiggingStatus::StartDigging
+                    || !is_creative && self.check_dig(location, status).await?
                 {
                     let block_state = self
                         .server
                         .world
                         .get_block(location.x, location.y, location.z);
                     if block_state != 0 {
-                        self.change_block(location, 0).await?;
+                        let block_state = match self.break_block(location, block_state).await? {
+                            Some(block_state) => block_state,
+                            None => return Ok(()),
+                        };
                         if !is_creative {
-                            let block_id = block_id!(block_state);
-                            let block_meta = block_meta!(block_state);
-
-                            // Create item entity
-                            let eid = self.server.new_id();
-                            self.server
-                                .send_broadcast(Packet::S0ESpawnObject {
-                                    entity_id: eid,
-                                    kind: 2,
-                                    x: location.x as f32 + 0.5,
-                                    y: location.y as f32 + 0.5,
-                                    z: location.z as f32 + 0.5,
-                                    pitch: 0.0,
-                                    yaw: 0.0,
-                                    data: 0,
-                                })
-                                .await?;
-
-                            // Update item entity metadata
-                            self.server
-                                .send_broadcast(Packet::S1CEntityMeta {
-                                    entity_id: eid,
-                                    entries: vec![EntityMetaEntry::new(
-                                        10,
-                                        EntityMetaData::Slot(ItemStack {
-                                            id: block_id as i16,
-                                            count: 1,
-                                            damage: block_meta,
-                                        }),
-                                    )],
-                                })
-                                .await?;
+                            // Create item entities
+                            let position = Vec3d {
+                                x: location.x as f64 + 0.5,
+                                y: location.y as f64 + 0.5,
+                                z: location.z as f64 + 0.5,
+                            };
+                            let tool = *self.player.item_stack_in_hotbar(self.player.selected_slot);
+                            for stack in block::drops(block_state, tool) {
+                                self.server.spawn_item(position, stack);
+                            }
                         }
                     }
                 }
             }
-            Packet::C08PlayerBlockPlacement { location, face } => {
-                if face != BlockFace::Special {
-                    let block_state = self
-                        .server
-                        .world
-                        .get_block(location.x, location.y, location.z);
-
-                    // Tall grass is replaced, therefore the offset is ignored
-                    let new_loc = if block_id!(block_state) == 31 {
-                        location
-                    } else {
-                        location.offset(face)
-                    };
-
-                    // Set the corresponding block, if the held item allows it
-                    let held_item_stack =
-                        self.player.item_stack_in_hotbar(self.player.selected_slot);
-                    if held_item_stack.is_present() && held_item_stack.is_block() {
-                        let new_state = block_state!(held_item_stack.id, held_item_stack.damage);
-                        self.change_block(new_loc, new_state).await?;
-                    }
+            Packet::C08PlayerBlockPlacement {
+                location,
+                face,
+                cursor,
+            } => {
+                // Right-clicking air, or a block that the held item doesn't act on, arrives
+                // without a target block
+                if face == BlockFace::Special {
+                    self.use_item().await?;
+                } else {
+                    self.place_block(location, face, cursor).await?;
                 }
             }
             Packet::C09HeldItemChange { slot } => {
                 self.player.selected_slot = slot;
+                self.player.eating_ticks = 0;
             }
             Packet::C0AAnimation { .. } => {}
+            Packet::C0BEntityAction { action, .. } => {
+                match action {
+                    EntityAction::StartSneaking => self.player.sneaking = true,
+                    EntityAction::StopSneaking => self.player.sneaking = false,
+                    EntityAction::StartSprinting => self.player.sprinting = true,
+                    EntityAction::StopSprinting => self.player.sprinting = false,
+                    _ => return Ok(()),
+                }
+
+                // Show the new pose to the other players
+                self.server
+                    .send_broadcast(Packet::S1CEntityMeta {
+                        entity_id: self.player.eid,
+                        entries: vec![EntityMetaEntry::status(self.player.status_flags())],
+                    })
+                    .await?;
+            }
             Packet::C10SetCreativeSlot { slot_id, item } => {
                 debug!("Set slot {:?} to {:?}", slot_id, item);
-                let stack = self.player.item_stack_at(slot_id);
-                *stack = item;
+                if self.player.game_mode != GameMode::Creative
+                    || slot_id < 0
+                    || slot_id as usize >= self.player.inventory.len()
+                {
+                    return Ok(());
+                }
+
+                // Picked blocks may arrive as block states that have no item of their own,
+                // such as doors or lit furnaces, so this goes by block id and not by item
+                let mut stored = item;
+                let is_block_id = (0..4096).contains(&item.id)
+                    && item.damage <= 15
+                    && registry().info(block_state!(item.id, 0)).is_some();
+                if is_block_id {
+                    stored = block::pick_item(block_state!(item.id, item.damage));
+                    stored.count = item.count;
+                }
+                stored.count = stored.count.min(stored.max_stack_size());
+
+                *self.player.item_stack_at(slot_id) = stored;
+                if stored != item {
+                    self.send_packet(Packet::S2FSetSlot {
+                        window_id: 0,
+                        slot: slot_id,
+                        item: stored,
+                    })
+                    .await?;
+                }
+            }
+            Packet::C13PlayerAbilities { flags, .. } => {
+                self.player.flying = flags.is_flying;
+            }
+            Packet::C16ClientStatus {
+                action: ClientStatusAction::PerformRespawn,
+            } => {
+                self.respawn().await?;
             }
             _ => {
                 trace!("Received unhandled packet: {:?}", packet);
@@ -363,19 +744,24 @@ impl ClientHandler {
 
     async fn exec_command(&mut self, command: &str) -> Result<Option<String>, String> {
         let command = Command::parse(command);
-        match command.name() {
-            "help" => {
-                let help_msg = indoc! {"
-                == §aHelp§r ==
-                §9 /help§r: Show command overview
-                §9 /gm §7<mode>§r: Change gamemode
-                §9 /flyspeed §7<speed>§r: Set flying speed multiplier
-                §9 /walkspeed §7<speed>§r: Set walking speed multiplier
-                "};
-                return Ok(Some(help_msg.trim().to_string()));
+        let info = command::find_command(command.name());
+        if let Some(info) = info {
+            if info.permission_level > self.player.permission_level {
+                return Err("You do not have permission to use this command".to_string());
             }
+        }
+
+        // Everything that doesn't need the player is shared with the console
+        if !info.is_some_and(|info| info.needs_player) {
+            return self
+                .server
+                .exec_command(&command, self.player.permission_level)
+                .await;
+        }
+
+        match command.name() {
             "gm" => {
-                self.change_game_mode(GameMode::from(command.arg::<u8>(0)?))
+                self.change_game_mode(command.arg::<GameMode>(0)?)
                     .await
                     .expect("Failed to change game mode");
 
@@ -404,85 +790,1309 @@ impl ClientHandler {
                     self.player.walk_speed
                 )));
             }
+            "fill" => {
+                let (min, max) = self.edit_region(command.pos_arg(0)?, command.pos_arg(3)?)?;
+                self.check_border(min, max)?;
+                let block_state = command.block_arg(6)?;
+
+                let mut changes = Vec::new();
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        for x in min.x..=max.x {
+                            changes.push((BlockPos::new(x, y, z), block_state));
+                        }
+                    }
+                }
+
+                let changed = self
+                    .change_blocks(&changes)
+                    .await
+                    .map_err(|e| format!("Failed to change blocks: {}", e))?;
+                let message = format!("{} blocks filled", changed.len());
+                return Ok(Some(self.record_edit(changed, message)));
+            }
+            "clone" => {
+                let (min, max) = self.edit_region(command.pos_arg(0)?, command.pos_arg(3)?)?;
+                let dest = command.pos_arg(6)?;
+                if dest.y < 0 || dest.y + (max.y - min.y) > 255 {
+                    return Err("Destination is outside of the world".to_string());
+                }
+                self.check_border(
+                    dest,
+                    BlockPos::new(
+                        dest.x + max.x - min.x,
+                        dest.y + max.y - min.y,
+                        dest.z + max.z - min.z,
+                    ),
+                )?;
+
+                // Read the whole source before writing, so overlapping regions work
+                let mut changes = Vec::new();
+                for y in min.y..=max.y {
+                    for z in min.z..=max.z {
+                        for x in min.x..=max.x {
+                            let block_state = self.server.world.get_block(x, y, z);
+                            let target = BlockPos::new(
+                                dest.x + x - min.x,
+                                dest.y + y - min.y,
+                                dest.z + z - min.z,
+                            );
+                            changes.push((target, block_state));
+                        }
+                    }
+                }
+
+                let changed = self
+                    .change_blocks(&changes)
+                    .await
+                    .map_err(|e| format!("Failed to change blocks: {}", e))?;
+                let message = format!("{} blocks cloned", changed.len());
+                return Ok(Some(self.record_edit(changed, message)));
+            }
+            "struct" => {
+                let action = command.arg::<String>(0)?;
+                let name = command.arg::<String>(1)?;
+                if !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    return Err(format!("{} is not a valid structure name", name));
+                }
+                let path =
+                    Path::new(&self.server.config.structure_dir).join(format!("{}.struct", name));
+
+                match action.as_str() {
+                    "save" => {
+                        let (min, max) =
+                            self.edit_region(command.pos_arg(2)?, command.pos_arg(5)?)?;
+                        let structure = Structure::capture(&self.server.world, min, max);
+
+                        fs::create_dir_all(&self.server.config.structure_dir)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        fs::write(&path, structure.to_bytes())
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        return Ok(Some(format!(
+                            "Saved structure {} with {} blocks",
+                            name,
+                            structure.volume()
+                        )));
+                    }
+                    "load" => {
+                        let origin = match command.pos_arg(2) {
+                            Ok(pos) => pos,
+                            Err(_) => self.player.block_pos(),
+                        };
+                        let data = fs::read(&path)
+                            .await
+                            .map_err(|_| format!("Structure {} not found", name))?;
+                        let structure = Structure::from_bytes(&data)?;
+
+                        let max = BlockPos::new(
+                            origin.x + structure.size_x - 1,
+                            origin.y + structure.size_y - 1,
+                            origin.z + structure.size_z - 1,
+                        );
+                        self.edit_region(origin, max)?;
+                        if origin.y < 0 || max.y > 255 {
+                            return Err("Structure does not fit into the world".to_string());
+                        }
+                        self.check_border(origin, max)?;
+
+                        self.server.gen.generate_area(origin, max).await;
+                        let changed = self
+                            .change_blocks(&structure.blocks_at(origin))
+                            .await
+                            .map_err(|e| format!("Failed to change blocks: {}", e))?;
+                        let message = format!(
+                            "Placed structure {} ({} blocks changed)",
+                            name,
+                            changed.len()
+                        );
+                        return Ok(Some(self.record_edit(changed, message)));
+                    }
+                    _ => return Err(format!("Unknown action {}", action)),
+                }
+            }
+            "msg" => {
+                let target = command.arg::<String>(0)?;
+                let target_id = self
+                    .server
+                    .find_player(&target)
+                    .ok_or_else(|| format!("Player {} is not online", target))?;
+                return self.whisper(target_id, &command.rest(1)?).await;
+            }
+            "nick" => {
+                let nick = match command.rest(0) {
+                    Ok(nick) => Some(translate_color_codes(&nick)),
+                    Err(_) => None,
+                };
+                if nick
+                    .as_ref()
+                    .is_some_and(|nick| strip_colors(nick).chars().count() > MAX_NICK_LENGTH)
+                {
+                    return Err(format!(
+                        "Nicknames can have at most {} characters",
+                        MAX_NICK_LENGTH
+                    ));
+                }
+
+                self.player.display_name = nick;
+                self.server
+                    .send_broadcast(Packet::S38PlayerListItem {
+                        uuid: self.player.uuid,
+                        action: PlayerListItemAction::UpdateDisplayName {
+                            display_name: self.display_name_component(),
+                        },
+                    })
+                    .await
+                    .map_err(|e| format!("Failed to update display name: {}", e))?;
+                Ok(Some(format!(
+                    "Your nickname is now {}",
+                    self.player.display_name()
+                )))
+            }
+            "r" => {
+                let target_id = self
+                    .server
+                    .reply_target(self.player.eid)
+                    .ok_or_else(|| "Nobody has messaged you yet".to_string())?;
+                return self.whisper(target_id, &command.rest(0)?).await;
+            }
+            "undo" => {
+                let previous = match self.player.edit_history.pop() {
+                    Some(previous) => previous,
+                    None => return Err("Nothing to undo".to_string()),
+                };
+
+                let restored = self
+                    .change_blocks(&previous)
+                    .await
+                    .map_err(|e| format!("Failed to change blocks: {}", e))?;
+                return Ok(Some(format!("{} blocks restored", restored.len())));
+            }
             _ => return Err(format!("{}: Unknown command.", command.name())),
         }
     }
 
-    async fn change_block(&mut self, location: BlockPos, block_state: u16) -> io::Result<()> {
-        self.server
+    /// Sends a private message to another player and returns the echo for the sender
+    async fn whisper(&self, target_id: i32, message: &str) -> Result<Option<String>, String> {
+        let target = match self.server.players.get(&target_id) {
+            Some(info) => info.username.clone(),
+            None => return Err("That player is no longer online".to_string()),
+        };
+
+        let packet = chat_packet!(
+            0,
+            format!("§7§o{} whispers to you: {}", self.player.username, message)
+        );
+        if !self.server.send_to(target_id, packet).await {
+            return Err(format!("Player {} is not online", target));
+        }
+
+        info!("[{} -> {}] {}", self.player.username, target, message);
+        self.server.set_reply_target(target_id, self.player.eid);
+        Ok(Some(format!("§7§oYou whisper to {}: {}", target, message)))
+    }
+
+    /// Tracks survival digging and returns whether the block may be broken now.
+    /// Finishing too early for the block and the held tool is rejected.
+    async fn check_dig(&mut self, location: BlockPos, status: DiggingStatus) -> io::Result<bool> {
+        let block_state = self
+            .server
             .world
-            .set_block(location.x, location.y, location.z, block_state);
-        self.server
-            .send_broadcast(Packet::S23BlockChange {
-                location,
-                block_state,
-            })
-            .await
+            .get_block(location.x, location.y, location.z);
+        let tool = *self.player.item_stack_in_hotbar(self.player.selected_slot);
+        let required_ticks = match block::dig_ticks(block_state, tool) {
+            Some(ticks) => ticks,
+            None => return self.reject_dig(location, block_state).await,
+        };
+
+        match status {
+            DiggingStatus::StartDigging => {
+                // The client does not send a finish for blocks that break instantly
+                if required_ticks == 0 {
+                    self.digging = None;
+                    return Ok(true);
+                }
+                self.digging = Some((location, Instant::now()));
+                Ok(false)
+            }
+            DiggingStatus::CancelDigging => {
+                self.digging = None;
+                Ok(false)
+            }
+            DiggingStatus::FinishDigging => {
+                let started = match self.digging.take() {
+                    Some((pos, started)) if pos == location => started,
+                    _ => return self.reject_dig(location, block_state).await,
+                };
+
+                // Allow some slack for latency, like vanilla does
+                let elapsed_ticks = started.elapsed().as_millis() / TICK_DURATION.as_millis();
+                if (elapsed_ticks as f32) < required_ticks as f32 * 0.7 {
+                    debug!(
+                        "{} finished digging too early ({}/{} ticks)",
+                        self.player.username, elapsed_ticks, required_ticks
+                    );
+                    return self.reject_dig(location, block_state).await;
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
     }
 
-    async fn change_game_mode(&mut self, game_mode: GameMode) -> io::Result<()> {
-        self.player.game_mode = game_mode;
-        self.send_packet(Packet::S2BChangeGameState {
-            reason: GameStateReason::ChangeGameMode,
-            value: game_mode as i32 as f32,
+    /// Restores a block on the client that it thought it had broken
+    async fn reject_dig(&mut self, location: BlockPos, block_state: u16) -> io::Result<bool> {
+        self.send_packet(Packet::S23BlockChange {
+            location,
+            block_state,
         })
         .await?;
-        self.send_abilities().await?;
-        self.server
-            .send_broadcast(Packet::S38PlayerListItem {
-                uuid: self.player.uuid,
-                action: PlayerListItemAction::UpdateGameMode { game_mode },
-            })
-            .await?;
-        Ok(())
+        Ok(false)
     }
 
-    async fn update_chunks(&mut self, center: ChunkPos) -> io::Result<()> {
-        if self.current_chunk_pos != center {
-            self.current_chunk_pos = center;
+    async fn place_block(
+        &mut self,
+        location: BlockPos,
+        face: BlockFace,
+        cursor: Vec3d,
+    ) -> io::Result<()> {
+        let held_item_stack = *self.player.item_stack_in_hotbar(self.player.selected_slot);
+        let placed_state = match held_item_stack.block_state() {
+            Some(block_state) => block_state,
+            None => return Ok(()),
+        };
 
-            let r = self.server.config.view_dist;
-            self.server.gen.request_region(center.x, center.z, r);
-            self.server.gen.await_region(center.x, center.z, r).await;
-            self.send_chunks(center.x, center.z, r).await?;
+        // Replaceable blocks such as grass or water are placed into, therefore the offset is ignored
+        let block_state = self
+            .server
+            .world
+            .get_block(location.x, location.y, location.z);
+        let new_loc = if is_replaceable(block_state) {
+            location
+        } else {
+            location.offset(face)
+        };
 
-            let min_x = center.x - r;
-            let min_z = center.z - r;
-            let max_x = center.x + r;
-            let max_z = center.z + r;
+        let new_state = block::oriented_state(placed_state, face, cursor, self.player.rotation.x);
 
-            let removed = self
-                .known_chunks
-                .iter()
-                .filter(|k| k.x < min_x || k.z < min_z || k.x > max_x || k.z > max_z)
-                .map(|k| *k)
-                .collect::<Vec<ChunkPos>>();
+        // Doors and double plants also get their upper half
+        let mut changes = vec![(new_loc, new_state)];
+        if block::is_two_high(new_state) {
+            let upper_state = block_state!(block_id!(new_state), 8);
+            changes.push((new_loc.offset(BlockFace::PosY), upper_state));
+        }
 
-            for r in removed {
-                self.send_packet(Packet::S21ChunkData { x: r.x, z: r.z })
-                    .await?;
-                self.known_chunks.remove(&r);
+        // The client may already show the block, so undo that if a target is occupied,
+        // a solid block would end up inside a player or a door has nothing to stand on
+        let below = new_loc.offset(BlockFace::NegY);
+        let mut allowed = !block::is_door(new_state)
+            || is_solid(self.server.world.get_block(below.x, below.y, below.z));
+        let border = self.server.world_border();
+        for &(pos, state) in &changes {
+            let old_state = self.server.world.get_block(pos.x, pos.y, pos.z);
+            let blocks_player = is_solid(state)
+                && self
+                    .server
+                    .players
+                    .iter()
+                    .any(|p| Player::hitbox_intersects(p.position, pos));
+            allowed &= is_replaceable(old_state) && !blocks_player && border.contains_block(pos);
+        }
+
+        if !allowed {
+            for (pos, _) in changes {
+                let old_state = self.server.world.get_block(pos.x, pos.y, pos.z);
+                self.send_packet(Packet::S23BlockChange {
+                    location: pos,
+                    block_state: old_state,
+                })
+                .await?;
             }
+            return Ok(());
         }
 
+        // Set the corresponding blocks
+        self.change_block_group(&changes).await?;
         Ok(())
     }
 
-    async fn send_packet(&mut self, packet: Packet) -> io::Result<()> {
-        self.msg_stream.send(packet).await
-    }
+    /// Breaks a block and the other half of doors and double plants.
+    /// Returns the state that determines the drops, or `None` if a listener cancelled it.
+    async fn break_block(
+        &mut self,
+        location: BlockPos,
+        block_state: u16,
+    ) -> io::Result<Option<u16>> {
+        if !block::is_two_high(block_state) {
+            return Ok(self.change_block(location, 0).await?.then_some(block_state));
+        }
 
-    async fn send_abilities(&mut self) -> io::Result<()> {
-        self.send_packet(Packet::S39PlayerAbilities {
-            flags: AbilityFlags::from_game_mode(self.player.game_mode),
-            flying_speed: self.player.fly_speed,
-            walking_speed: self.player.walk_speed,
-        })
-        .await
-    }
+        let is_upper = block_meta!(block_state) & 8 != 0;
+        let other = if is_upper {
+            location.offset(BlockFace::NegY)
+        } else {
+            location.offset(BlockFace::PosY)
+        };
+        let other_state = self.server.world.get_block(other.x, other.y, other.z);
+        if block_id!(other_state) != block_id!(block_state) {
+            return Ok(self.change_block(location, 0).await?.then_some(block_state));
+        }
+
+        if !self
+            .change_block_group(&[(location, 0), (other, 0)])
+            .await?
+        {
+            return Ok(None);
+        }
+        Ok(Some(if is_upper { other_state } else { block_state }))
+    }
+
+    /// Uses the held item without a target block, depending on what it is
+    async fn use_item(&mut self) -> io::Result<()> {
+        let stack = *self.player.item_stack_in_hotbar(self.player.selected_slot);
+        if let Some(kind) = ProjectileKind::from_item(stack.id) {
+            return self.throw_projectile(kind).await;
+        }
+
+        match stack.id {
+            // Buckets
+            325..=327 => self.use_bucket().await?,
+            // Food, unless already full
+            _ if stack.food_value().is_some()
+                && self.player.food < 20
+                && self.player.is_vulnerable() =>
+            {
+                self.player.eating_ticks = EAT_DURATION;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn throw_projectile(&mut self, kind: ProjectileKind) -> io::Result<()> {
+        let eye = Vec3d {
+            y: self.player.position.y + EYE_HEIGHT,
+            ..self.player.position
+        };
+        let projectile = Projectile::thrown(
+            self.server.new_id(),
+            kind,
+            self.player.eid,
+            eye,
+            self.player.rotation,
+        );
+        self.server
+            .spawn_entity(WorldEntity::Projectile(projectile));
+
+        if self.player.game_mode == GameMode::Creative {
+            return Ok(());
+        }
+        let slot = 36 + self.player.selected_slot;
+        let stack = self.player.item_stack_at(slot);
+        if stack.count > 1 {
+            stack.count -= 1;
+        } else {
+            *stack = ItemStack::default();
+        }
+        let stack = *stack;
+        self.send_packet(Packet::S2FSetSlot {
+            window_id: 0,
+            slot,
+            item: stack,
+        })
+        .await
+    }
+
+    /// Fills an empty bucket from a liquid source or empties a full one in front of
+    /// the block the player looks at
+    async fn use_bucket(&mut self) -> io::Result<()> {
+        let (target, in_front) = match self.trace_line_of_sight() {
+            Some(hit) => hit,
+            None => return Ok(()),
+        };
+        let target_state = self.server.world.get_block(target.x, target.y, target.z);
+        let slot = 36 + self.player.selected_slot;
+        let held = *self.player.item_stack_at(slot);
+
+        let (location, block_state, result_id) = match held.id {
+            325 => match target_state {
+                s if s == block_state!(8, 0) || s == block_state!(9, 0) => (target, 0, 326),
+                s if s == block_state!(10, 0) || s == block_state!(11, 0) => (target, 0, 327),
+                _ => return Ok(()),
+            },
+            326 => (in_front, block_state!(9, 0), 325),
+            327 => (in_front, block_state!(11, 0), 325),
+            _ => return Ok(()),
+        };
+        if !self.server.world_border().contains_block(location) {
+            return Ok(());
+        }
+        if !self.change_block(location, block_state).await? {
+            return Ok(());
+        }
+
+        if self.player.game_mode == GameMode::Creative {
+            return Ok(());
+        }
+
+        let result = ItemStack {
+            id: result_id,
+            count: 1,
+            damage: 0,
+        };
+        if held.count > 1 {
+            // Empty buckets stack, so the filled one goes to a free slot
+            self.player.item_stack_at(slot).count -= 1;
+            self.send_slot(slot).await?;
+            match (9..45).find(|&i| !self.player.inventory[i].is_present()) {
+                Some(free_slot) => {
+                    self.player.inventory[free_slot] = result;
+                    self.send_slot(free_slot as i16).await?;
+                }
+                // Like vanilla, a full inventory drops the filled bucket
+                None => {
+                    let position = self.player.position;
+                    let drop_pos = Vec3d {
+                        y: position.y + 1.0,
+                        ..position
+                    };
+                    self.server.spawn_item(drop_pos, result);
+                }
+            }
+        } else {
+            *self.player.item_stack_at(slot) = result;
+            self.send_slot(slot).await?;
+        }
+        Ok(())
+    }
+
+    /// Walks along the player's line of sight and returns the first block that is
+    /// not air, together with the block in front of it
+    fn trace_line_of_sight(&self) -> Option<(BlockPos, BlockPos)> {
+        let yaw = (self.player.rotation.x as f64).to_radians();
+        let pitch = (self.player.rotation.y as f64).to_radians();
+        let (dx, dy, dz) = (
+            -yaw.sin() * pitch.cos(),
+            -pitch.sin(),
+            yaw.cos() * pitch.cos(),
+        );
+
+        let eye = self.player.position;
+        let eye_y = eye.y + EYE_HEIGHT;
+        let mut previous = BlockPos::new(
+            eye.x.floor() as i32,
+            eye_y.floor() as i32,
+            eye.z.floor() as i32,
+        );
+        let mut distance = 0.0;
+        while distance < USE_REACH {
+            let pos = BlockPos::new(
+                (eye.x + dx * distance).floor() as i32,
+                (eye_y + dy * distance).floor() as i32,
+                (eye.z + dz * distance).floor() as i32,
+            );
+            if pos != previous {
+                if self.server.world.get_block(pos.x, pos.y, pos.z) != 0 {
+                    return Some((pos, previous));
+                }
+                previous = pos;
+            }
+            distance += 0.05;
+        }
+        None
+    }
+
+    /// Kicks up particles of the ground block while sprinting
+    async fn sprint_particles(&self) -> io::Result<()> {
+        if !self.player.sprinting || !self.player.on_ground {
+            return Ok(());
+        }
+
+        let pos = self.player.position;
+        let ground = self.server.world.get_block(
+            pos.x.floor() as i32,
+            (pos.y - 0.2).floor() as i32,
+            pos.z.floor() as i32,
+        );
+        if ground == 0 {
+            return Ok(());
+        }
+
+        let packet = Packet::S2AParticle {
+            particle_id: PARTICLE_BLOCK_CRACK,
+            long_distance: false,
+            x: pos.x as f32,
+            y: pos.y as f32 + 0.1,
+            z: pos.z as f32,
+            offset_x: 0.3,
+            offset_y: 0.0,
+            offset_z: 0.3,
+            speed: 0.0,
+            count: 1,
+            data: vec![block_id!(ground) as i32 | (block_meta!(ground) as i32) << 12],
+        };
+        self.server.send_nearby(pos, PARTICLE_RANGE, packet).await
+    }
+
+    async fn send_slot(&mut self, slot: i16) -> io::Result<()> {
+        let item = *self.player.item_stack_at(slot);
+        self.send_packet(Packet::S2FSetSlot {
+            window_id: 0,
+            slot,
+            item,
+        })
+        .await
+    }
+
+    async fn change_block(&mut self, location: BlockPos, block_state: u16) -> io::Result<bool> {
+        self.change_block_group(&[(location, block_state)]).await
+    }
+
+    /// Sets blocks that belong together, such as both halves of a door, unless a listener
+    /// cancels any of them. Then none is changed and the player is shown the old blocks
+    /// again. Returns whether the blocks were changed.
+    async fn change_block_group(&mut self, changes: &[(BlockPos, u16)]) -> io::Result<bool> {
+        let old_states: Vec<u16> = changes
+            .iter()
+            .map(|(pos, _)| self.server.world.get_block(pos.x, pos.y, pos.z))
+            .collect();
+        let verdict = changes
+            .iter()
+            .zip(&old_states)
+            .try_for_each(|(&(pos, state), &old)| {
+                self.server
+                    .check_block_change(&self.player, pos, old, state)
+            });
+        if let Err(reason) = verdict {
+            for (&(location, _), &block_state) in changes.iter().zip(&old_states) {
+                self.send_packet(Packet::S23BlockChange {
+                    location,
+                    block_state,
+                })
+                .await?;
+            }
+            if !reason.is_empty() {
+                self.send_packet(chat_packet!(1, format!("§c{}", reason)))
+                    .await?;
+            }
+            return Ok(false);
+        }
+
+        let old_light = self.chunk_light(changes).await?;
+        let mut events = Vec::new();
+        for (&(location, block_state), &old_state) in changes.iter().zip(&old_states) {
+            self.server
+                .world
+                .set_block(location.x, location.y, location.z, block_state);
+            self.server
+                .send_broadcast(Packet::S23BlockChange {
+                    location,
+                    block_state,
+                })
+                .await?;
+            events.push(BlockChange {
+                location,
+                old_state,
+                new_state: block_state,
+            });
+        }
+        self.server.emit(GameEvent::BlocksChanged {
+            player: self.player.username.clone(),
+            changes: events.into(),
+        });
+        let sections = old_light.into_iter().map(|(pos, light)| (pos, light, 0));
+        self.resend_sections(sections.collect()).await?;
+        Ok(true)
+    }
+
+    /// Sets many blocks at once and broadcasts them as one multi block change per chunk.
+    /// Returns the previous state of every block that was actually changed.
+    async fn change_blocks(
+        &mut self,
+        changes: &[(BlockPos, u16)],
+    ) -> io::Result<Vec<(BlockPos, u16)>> {
+        let old_light = self.chunk_light(changes).await?;
+        let mut previous = Vec::new();
+        let mut events = Vec::new();
+        let mut records = HashMap::<ChunkPos, Vec<BlockChangeRecord>>::new();
+
+        for &(location, block_state) in changes {
+            let old_state = self
+                .server
+                .world
+                .get_block(location.x, location.y, location.z);
+            if old_state == block_state {
+                continue;
+            }
+
+            self.server
+                .world
+                .set_block(location.x, location.y, location.z, block_state);
+            previous.push((location, old_state));
+            events.push(BlockChange {
+                location,
+                old_state,
+                new_state: block_state,
+            });
+
+            records
+                .entry(ChunkPos::from_block_pos(location.x, location.z))
+                .or_default()
+                .push(BlockChangeRecord {
+                    x: world_to_local(location.x) as u8,
+                    y: location.y as u8,
+                    z: world_to_local(location.z) as u8,
+                    block_state,
+                });
+        }
+
+        let mut sections = Vec::new();
+        for (chunk_pos, light) in old_light {
+            let records = match records.remove(&chunk_pos) {
+                Some(records) => records,
+                None => continue,
+            };
+
+            // Like vanilla, resend the changed sections when many blocks changed
+            if records.len() >= FULL_CHUNK_RESEND_THRESHOLD {
+                let section_mask = records
+                    .iter()
+                    .fold(0u16, |m, r| m | 1 << world_to_chunk(r.y as i32));
+                sections.push((chunk_pos, light, section_mask));
+            } else {
+                self.server
+                    .send_broadcast(Packet::S22MultiBlockChange {
+                        chunk_x: chunk_pos.x,
+                        chunk_z: chunk_pos.z,
+                        records,
+                    })
+                    .await?;
+                sections.push((chunk_pos, light, 0));
+            }
+        }
+        self.resend_sections(sections).await?;
+
+        // Only after the packets, so listeners that send their own updates come last
+        if !events.is_empty() {
+            self.server.emit(GameEvent::BlocksChanged {
+                player: self.player.username.clone(),
+                changes: events.into(),
+            });
+        }
+        Ok(previous)
+    }
+
+    /// The current light of the chunks that blocks are about to change in, to find out what
+    /// the change does to it. Chunks that don't exist are left out.
+    async fn chunk_light(
+        &self,
+        changes: &[(BlockPos, u16)],
+    ) -> io::Result<Vec<(ChunkPos, Arc<ChunkLight>)>> {
+        let world = self.server.world.clone();
+        let chunks: HashSet<ChunkPos> = changes
+            .iter()
+            .map(|(pos, _)| ChunkPos::from_block_pos(pos.x, pos.z))
+            .collect();
+        task::spawn_blocking(move || {
+            chunks
+                .into_iter()
+                .filter_map(|pos| {
+                    let chunk_ref = world.get_chunk(pos)?;
+                    let light = lock_chunk(&chunk_ref).light();
+                    Some((pos, light))
+                })
+                .collect()
+        })
+        .await
+        .map_err(io::Error::other)
+    }
+
+    /// Sends sections of changed chunks again. These are the sections in the mask, and the
+    /// ones whose light differs from the light before the change, as the client doesn't
+    /// relight beyond the changed blocks.
+    async fn resend_sections(
+        &self,
+        chunks: Vec<(ChunkPos, Arc<ChunkLight>, u16)>,
+    ) -> io::Result<()> {
+        let world = self.server.world.clone();
+        let hide_ores = self.server.config.anti_xray;
+        // Relighting and copying the sections may block, so keep it off the async executor
+        let packets = task::spawn_blocking(move || {
+            chunks
+                .into_iter()
+                .filter_map(|(pos, old_light, section_mask)| {
+                    let chunk_ref = world.get_chunk(pos)?;
+                    let chunk = lock_chunk(&chunk_ref);
+                    let section_mask = section_mask | old_light.changed_sections(&chunk.light());
+                    let packet = Packet::S21ChunkData {
+                        chunk: ChunkData::from_chunk(&chunk, false, section_mask, hide_ores),
+                        ground_up: false,
+                    };
+                    (section_mask != 0).then_some((pos, packet))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        // Only clients that have the chunk can apply its sections
+        for (pos, packet) in packets {
+            self.server.send_to_chunk_viewers(pos, packet);
+        }
+        Ok(())
+    }
+
+    /// Adds an edit to the undo history and returns the message for it, which tells the
+    /// player if the edit is too large to undo
+    fn record_edit(&mut self, changed: Vec<(BlockPos, u16)>, message: String) -> String {
+        if self.player.edit_history.push(changed) {
+            message
+        } else {
+            format!("{}. This edit is too large to be undone.", message)
+        }
+    }
+
+    /// Orders the corners of an edit region and checks it against the volume limit
+    fn edit_region(&self, a: BlockPos, b: BlockPos) -> Result<(BlockPos, BlockPos), String> {
+        let min = BlockPos::new(a.x.min(b.x), a.y.min(b.y).max(0), a.z.min(b.z));
+        let max = BlockPos::new(a.x.max(b.x), a.y.max(b.y).min(255), a.z.max(b.z));
+        if min.y > max.y {
+            return Err("Region is outside of the world".to_string());
+        }
+
+        let size_x = max.x as i64 - min.x as i64 + 1;
+        let size_y = max.y as i64 - min.y as i64 + 1;
+        let size_z = max.z as i64 - min.z as i64 + 1;
+        let volume = size_x.saturating_mul(size_y).saturating_mul(size_z);
+        if volume > self.server.config.max_edit_volume {
+            return Err(format!(
+                "Region contains {} blocks, the limit is {}",
+                volume, self.server.config.max_edit_volume
+            ));
+        }
+
+        Ok((min, max))
+    }
+
+    /// Edits must stay within the world border, like players do
+    fn check_border(&self, min: BlockPos, max: BlockPos) -> Result<(), String> {
+        let border = self.server.world_border();
+        if !border.contains_block(min) || !border.contains_block(max) {
+            return Err("Region is outside of the world border".to_string());
+        }
+        Ok(())
+    }
+
+    /// The nickname as chat component for the player list, which shows the username without one
+    fn display_name_component(&self) -> Option<String> {
+        self.player
+            .display_name
+            .as_ref()
+            .map(|name| json!({ "text": name }).to_string())
+    }
+
+    async fn change_game_mode(&mut self, game_mode: GameMode) -> io::Result<()> {
+        self.player.game_mode = game_mode;
+        self.send_packet(Packet::S2BChangeGameState {
+            reason: GameStateReason::ChangeGameMode,
+            value: game_mode as i32 as f32,
+        })
+        .await?;
+        self.send_abilities().await?;
+        self.server
+            .send_broadcast(Packet::S38PlayerListItem {
+                uuid: self.player.uuid,
+                action: PlayerListItemAction::UpdateGameMode { game_mode },
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn update_chunks(&mut self, center: ChunkPos) -> io::Result<()> {
+        if self.current_chunk_pos != center {
+            self.current_chunk_pos = center;
+
+            let r = self.server.config.view_dist;
+            self.server.gen.await_region(center.x, center.z, r).await;
+            self.send_chunks(center.x, center.z, r).await?;
+
+            let min_x = center.x - r;
+            let min_z = center.z - r;
+            let max_x = center.x + r;
+            let max_z = center.z + r;
+
+            let removed = self
+                .known_chunks
+                .iter()
+                .filter(|k| k.x < min_x || k.z < min_z || k.x > max_x || k.z > max_z)
+                .map(|k| *k)
+                .collect::<Vec<ChunkPos>>();
+
+            for r in removed {
+                self.send_packet(Packet::S21UnloadChunk { x: r.x, z: r.z })
+                    .await?;
+                self.known_chunks.remove(&r);
+                self.server.untrack_chunk(self.player.eid, r);
+            }
+            self.update_tracked_entities().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Spawns the entities that came into range and destroys the ones that left it or are
+    /// gone. Entities are only shown in chunks the client has.
+    async fn update_tracked_entities(&mut self) -> io::Result<()> {
+        let visible: HashSet<i32> = self
+            .server
+            .entities_near(self.player.position, ENTITY_TRACKING_RANGE)
+            .into_iter()
+            .filter(|(_, chunk_pos)| self.known_chunks.contains(chunk_pos))
+            .map(|(eid, _)| eid)
+            .collect();
+
+        let left: Vec<i32> = self.known_entities.difference(&visible).copied().collect();
+        if !left.is_empty() {
+            for eid in &left {
+                self.known_entities.remove(eid);
+                self.server.untrack_entity(self.player.eid, *eid);
+            }
+            self.send_packet(Packet::S13DestroyEntities { entity_ids: left })
+                .await?;
+        }
+
+        let entered: Vec<i32> = visible.difference(&self.known_entities).copied().collect();
+        for eid in entered {
+            // Tracked first, so no update queued after the spawn packets is missed
+            self.server.track_entity(self.player.eid, eid);
+            for packet in self.server.entity_spawn_packets(eid) {
+                self.send_packet(packet).await?;
+            }
+            self.known_entities.insert(eid);
+        }
+        Ok(())
+    }
+
+    /// Sends the chunks and entities again after the server dropped bulk packets for the
+    /// client. Waits until the bulk queue is drained, so the resync is the latest state.
+    async fn resync(&mut self) -> io::Result<()> {
+        if !self.bulk_rx.is_empty() || !self.server.take_out_of_sync(self.player.eid) {
+            return Ok(());
+        }
+
+        for pos in self.known_chunks.iter() {
+            self.server.untrack_chunk(self.player.eid, *pos);
+        }
+        self.known_chunks.clear();
+        let center = self.current_chunk_pos;
+        self.send_chunks(center.x, center.z, self.server.config.view_dist)
+            .await?;
+
+        let entity_ids: Vec<i32> = self.known_entities.drain().collect();
+        for eid in &entity_ids {
+            self.server.untrack_entity(self.player.eid, *eid);
+        }
+        if !entity_ids.is_empty() {
+            self.send_packet(Packet::S13DestroyEntities { entity_ids })
+                .await?;
+        }
+        self.update_tracked_entities().await
+    }
+
+    async fn tick(&mut self) -> io::Result<()> {
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(PLAYER_SAVE_INTERVAL) {
+            self.server
+                .save_player_data(self.player.uuid, self.player.to_data())
+                .await;
+        }
+
+        self.resync().await?;
+        self.update_tracked_entities().await?;
+        self.tick_hunger().await?;
+        self.tick_eating().await
+    }
+
+    async fn tick_hunger(&mut self) -> io::Result<()> {
+        let prev_state = (self.player.health, self.player.food, self.player.saturation);
+
+        let health_change = self.player.tick_hunger(self.server.config.difficulty);
+        if health_change > 0.0 {
+            self.player.health = (self.player.health + health_change).min(20.0);
+        } else if health_change < 0.0 {
+            return self.damage(-health_change, DamageCause::Starvation).await;
+        }
+
+        let state = (self.player.health, self.player.food, self.player.saturation);
+        if state != prev_state {
+            self.send_health().await?;
+        }
+        Ok(())
+    }
+
+    async fn tick_eating(&mut self) -> io::Result<()> {
+        if self.player.eating_ticks == 0 {
+            return Ok(());
+        }
+
+        self.player.eating_ticks -= 1;
+        if self.player.eating_ticks > 0 {
+            return Ok(());
+        }
+
+        let slot = 36 + self.player.selected_slot;
+        let (food, saturation) = match self.player.item_stack_at(slot).food_value() {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        if self.player.food >= 20 {
+            return Ok(());
+        }
+        self.player.eat(food, saturation);
+
+        // Use up the item, stews leave their bowl behind
+        let stack = self.player.item_stack_at(slot);
+        if matches!(stack.id, 282 | 413) {
+            *stack = ItemStack {
+                id: 281,
+                count: 1,
+                damage: 0,
+            };
+        } else if stack.count > 1 {
+            stack.count -= 1;
+        } else {
+            *stack = ItemStack::default();
+        }
+        let stack = *stack;
+
+        // Tell the client that eating is done
+        self.send_packet(Packet::S1AEntityStatus {
+            entity_id: self.player.eid,
+            status: 9,
+        })
+        .await?;
+        self.send_health().await?;
+        self.send_packet(Packet::S2FSetSlot {
+            window_id: 0,
+            slot,
+            item: stack,
+        })
+        .await
+    }
+
+    async fn handle_player_event(&mut self, event: PlayerEvent) -> io::Result<()> {
+        match event {
+            PlayerEvent::Attacked {
+                attacker,
+                damage,
+                knockback,
+            } => {
+                if self.player.is_dead() || !self.player.is_vulnerable() {
+                    return Ok(());
+                }
+
+                debug!(
+                    "{} was attacked by {} for {} damage",
+                    self.player.eid, attacker, damage
+                );
+                self.server
+                    .send_broadcast(Packet::S0BAnimation {
+                        entity_id: self.player.eid,
+                        animation: 1,
+                    })
+                    .await?;
+                self.server
+                    .send_broadcast(Packet::S12EntityVelocity {
+                        entity_id: self.player.eid,
+                        velocity: knockback,
+                    })
+                    .await?;
+                let attacker_name = match self.server.players.get(&attacker) {
+                    Some(info) => info.username.clone(),
+                    None => return Ok(()),
+                };
+                self.lose_health(damage, DamageCause::Player(attacker_name))
+                    .await?;
+            }
+            PlayerEvent::Kick { reason } => self.kick_reason = Some(reason),
+            PlayerEvent::Teleport { position } => {
+                if self.player.is_dead() {
+                    return Ok(());
+                }
+
+                self.player.position = self.server.world_border().clamp(position);
+                self.player.fall_start = None;
+                self.server
+                    .update_player_pos(self.player.eid, self.player.position);
+                self.resync_position().await?;
+                self.update_chunks(ChunkPos::from_block_pos(
+                    self.player.position.x.floor() as i32,
+                    self.player.position.z.floor() as i32,
+                ))
+                .await?;
+                if self.player.is_vulnerable() {
+                    self.lose_health(ENDER_PEARL_DAMAGE, DamageCause::Fall)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn attack(&mut self, target: i32) -> io::Result<()> {
+        if self.player.is_dead() || self.player.game_mode == GameMode::Spectator {
+            return Ok(());
+        }
+        if target == self.player.eid {
+            debug!("{} tried to attack itself", self.player.eid);
+            return Ok(());
+        }
+
+        let target_pos = match self.server.players.get(&target) {
+            Some(info) => info.position,
+            None => return Ok(()),
+        };
+
+        let dx = target_pos.x - self.player.position.x;
+        let dy = target_pos.y - self.player.position.y;
+        let dz = target_pos.z - self.player.position.z;
+        if (dx * dx + dy * dy + dz * dz).sqrt() > ATTACK_REACH {
+            debug!("{} is out of reach for {}", target, self.player.eid);
+            return Ok(());
+        }
+
+        // Push the target away from the attacker
+        let horizontal_dist = (dx * dx + dz * dz).sqrt().max(0.01);
+        let knockback = Vec3d {
+            x: dx / horizontal_dist * 0.4,
+            y: 0.4,
+            z: dz / horizontal_dist * 0.4,
+        };
+
+        let damage = self
+            .player
+            .item_stack_in_hotbar(self.player.selected_slot)
+            .attack_damage();
+        self.player.add_exhaustion(0.3);
+        self.server.send_player_event(
+            target,
+            PlayerEvent::Attacked {
+                attacker: self.player.eid,
+                damage,
+                knockback,
+            },
+        );
+        Ok(())
+    }
+
+    async fn update_fall(&mut self, on_ground: bool) -> io::Result<()> {
+        let pos = self.player.position;
+        let block_state = self.server.world.get_block(
+            pos.x.floor() as i32,
+            pos.y.floor() as i32,
+            pos.z.floor() as i32,
+        );
+
+        let distance = self.player.update_fall(on_ground, is_liquid(block_state));
+        let damage = (distance - 3.0).ceil();
+        if damage > 0.0 {
+            self.damage(damage as f32, DamageCause::Fall).await?;
+        }
+        Ok(())
+    }
+
+    /// Hurts the player and plays the hurt animation for everyone
+    async fn damage(&mut self, amount: f32, cause: DamageCause) -> io::Result<()> {
+        self.server
+            .send_broadcast(Packet::S1AEntityStatus {
+                entity_id: self.player.eid,
+                status: 2,
+            })
+            .await?;
+        self.lose_health(amount, cause).await
+    }
+
+    async fn lose_health(&mut self, amount: f32, cause: DamageCause) -> io::Result<()> {
+        if self.player.is_dead() {
+            return Ok(());
+        }
+
+        self.player.health = (self.player.health - amount).max(0.0);
+        self.player.add_exhaustion(0.3);
+        self.send_health().await?;
+        if self.player.is_dead() {
+            self.die(cause).await?;
+        }
+        Ok(())
+    }
+
+    async fn die(&mut self, cause: DamageCause) -> io::Result<()> {
+        let message = cause.death_message(&self.player.username);
+        info!("{}", message);
+        self.server.send_broadcast(chat_packet!(1, message)).await?;
+
+        if !self.server.config.keep_inventory {
+            let drops: Vec<(Vec3d, ItemStack)> = {
+                let mut rng = rand::thread_rng();
+                let position = self.player.position;
+                self.player
+                    .inventory
+                    .iter_mut()
+                    .map(std::mem::take)
+                    .filter(|stack| stack.is_present())
+                    .map(|stack| {
+                        let offset_x = rng.gen_range(-0.5..0.5);
+                        let offset_z = rng.gen_range(-0.5..0.5);
+                        let drop_pos = Vec3d {
+                            x: position.x + offset_x,
+                            y: position.y + 1.0,
+                            z: position.z + offset_z,
+                        };
+                        (drop_pos, stack)
+                    })
+                    .collect()
+            };
+
+            for (position, stack) in drops {
+                self.server.spawn_item(position, stack);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a position sent by the client. Positions that aren't numbers get the player
+    /// kicked, positions outside the world are clamped and moves that are too far for a
+    /// single packet are undone.
+    async fn move_player(&mut self, position: Vec3d, on_ground: bool) -> io::Result<()> {
+        if !position.x.is_finite() || !position.y.is_finite() || !position.z.is_finite() {
+            self.kick_reason = Some(INVALID_MOVE_REASON.to_string());
+            return Ok(());
+        }
+
+        let clamped = Vec3d {
+            x: position.x.clamp(-MAX_BORDER_RADIUS, MAX_BORDER_RADIUS),
+            y: position.y.clamp(MIN_Y_COORD, MAX_Y_COORD),
+            z: position.z.clamp(-MAX_BORDER_RADIUS, MAX_BORDER_RADIUS),
+        };
+        let previous = self.player.position;
+        if clamped.distance_to(previous) > MAX_MOVE_DISTANCE {
+            warn!(
+                "{} moved too quickly, from {:?} to {:?}",
+                self.player.username, previous, position
+            );
+            return self.resync_position().await;
+        }
+
+        // Also pulls back players the border shrank past
+        let clamped = self.server.world_border().clamp(clamped);
+        self.player.position = clamped;
+        self.server
+            .update_player_pos(self.player.eid, self.player.position);
+        self.player.track_movement(previous, on_ground);
+        self.update_fall(on_ground).await?;
+        self.sprint_particles().await?;
+        self.update_chunks(ChunkPos::from_block_pos(
+            clamped.x.floor() as i32,
+            clamped.z.floor() as i32,
+        ))
+        .await?;
+
+        if clamped != position {
+            self.resync_position().await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the client back to where the server thinks the player is
+    async fn resync_position(&mut self) -> io::Result<()> {
+        self.send_packet(Packet::S08SetPlayerPosition {
+            x: self.player.position.x,
+            y: self.player.position.y,
+            z: self.player.position.z,
+            yaw: self.player.rotation.x,
+            pitch: self.player.rotation.y,
+            flags: 0,
+        })
+        .await
+    }
+
+    async fn respawn(&mut self) -> io::Result<()> {
+        if !self.player.is_dead() {
+            return Ok(());
+        }
+
+        self.player.health = 20.0;
+        self.player.food = 20;
+        self.player.saturation = 5.0;
+        self.player.exhaustion = 0.0;
+        self.player.food_timer = 0;
+        self.player.eating_ticks = 0;
+        self.player.fall_start = None;
+        self.player.position = SPAWN_POS;
+        self.server
+            .update_player_pos(self.player.eid, self.player.position);
+
+        self.send_packet(Packet::S07Respawn {
+            dimension: 0,
+            difficulty: self.server.config.difficulty,
+            game_mode: self.player.game_mode,
+            world_type: self.server.config.world_type.clone(),
+        })
+        .await?;
+        self.update_chunks(ChunkPos::from_block_pos(
+            SPAWN_POS.x as i32,
+            SPAWN_POS.z as i32,
+        ))
+        .await?;
+        self.send_packet(Packet::S08SetPlayerPosition {
+            x: SPAWN_POS.x,
+            y: SPAWN_POS.y,
+            z: SPAWN_POS.z,
+            yaw: 0.0,
+            pitch: 0.0,
+            flags: 0,
+        })
+        .await?;
+        self.send_packet(Packet::S30WindowItems {
+            window_id: 0,
+            slots: self.player.inventory.clone(),
+        })
+        .await?;
+        self.send_health().await?;
+        self.send_abilities().await
+    }
+
+    async fn send_health(&mut self) -> io::Result<()> {
+        self.send_packet(Packet::S06UpdateHealth {
+            health: self.player.health,
+            food: self.player.food,
+            saturation: self.player.saturation,
+        })
+        .await
+    }
+
+    async fn send_packet(&mut self, packet: Packet) -> io::Result<()> {
+        self.msg_stream.send(packet).await
+    }
+
+    async fn send_abilities(&mut self) -> io::Result<()> {
+        self.send_packet(Packet::S39PlayerAbilities {
+            flags: AbilityFlags::from_game_mode(self.player.game_mode),
+            flying_speed: self.player.fly_speed,
+            walking_speed: self.player.walk_speed,
+        })
+        .await
+    }
 
     async fn send_chunks(&mut self, center_x: i32, center_z: i32, r: i32) -> io::Result<()> {
         let mut chunk_refs = Vec::<MutexChunkRef>::new();
+        let mut new_chunks = Vec::<ChunkPos>::new();
 
         // Collect chunks to be sent
         for z in -r..=r {
@@ -492,32 +2102,116 @@ impl ClientHandler {
                 if chunk_opt.is_some() && !self.known_chunks.contains(&chunk_pos) {
                     chunk_refs.push(chunk_opt.unwrap());
                     self.known_chunks.insert(chunk_pos);
+                    // Tracked first, so no update queued after the chunk data is missed
+                    self.server.track_chunk(self.player.eid, chunk_pos);
+                    new_chunks.push(chunk_pos);
                 }
             }
         }
 
-        // Split into packets
-        let chunks_per_packet = 5;
-        let mut packets: Vec<_> = Vec::new();
-        for packet_chunk_refs in chunk_refs.chunks(chunks_per_packet) {
-            // Lock and copy chunks for the network
-            let mut chunks = Vec::<Chunk>::new();
-            for chunk_ref in packet_chunk_refs {
-                chunks.push(chunk_ref.lock().unwrap().clone())
-            }
+        let hide_ores = self.server.config.anti_xray;
+        // Locking and copying may block, so keep it off the async executor
+        let packets = task::spawn_blocking(move || {
+            // Serialize chunks for the network, only locking each one briefly
+            batch_chunks(chunk_refs.iter().map(|chunk_ref| {
+                ChunkData::from_chunk(&lock_chunk(chunk_ref), true, 0xffff, hide_ores)
+            }))
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        // Send the packets
+        for packet in packets {
+            self.send_packet(packet).await?;
+        }
 
-            // Collect chunk packets
+        Ok(())
+    }
+}
+
+/// Splits chunks into bulk packets that stay below the packet size limit
+fn batch_chunks(chunks: impl IntoIterator<Item = ChunkData>) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
+    for chunk in chunks {
+        let chunk_size = CHUNK_META_SIZE + chunk.data.len();
+        if !batch.is_empty() && batch_size + chunk_size > CHUNK_BATCH_SIZE_LIMIT {
             packets.push(Packet::S26MapChunkBulk {
                 skylight: true,
-                chunks,
+                chunks: std::mem::take(&mut batch),
             });
+            batch_size = 0;
         }
+        batch_size += chunk_size;
+        batch.push(chunk);
+    }
+    if !batch.is_empty() {
+        packets.push(Packet::S26MapChunkBulk {
+            skylight: true,
+            chunks: batch,
+        });
+    }
+    packets
+}
 
-        // Send the packets
+/// Vanilla clients never send longer messages or control characters, so these
+/// get the player kicked
+fn validate_chat(message: &str) -> Result<(), &'static str> {
+    if message.chars().count() > MAX_CHAT_LENGTH {
+        return Err("Chat message too long");
+    }
+    if message.chars().any(char::is_control) {
+        return Err("Illegal characters in chat");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+    use crate::world::{registry, World};
+
+    #[test]
+    fn full_chunk_batch_fits_in_a_packet() {
+        registry::load_for_tests();
+        let world = World::new();
+        let stone = registry::state("stone", 0);
+        let chunks: Vec<_> = (0..25)
+            .map(|x| {
+                // A block in every section, so every section is sent
+                for y in (0..256).step_by(16) {
+                    world.set_block(x * 16, y, 0, stone);
+                }
+                let chunk_ref = world.get_chunk(ChunkPos::new(x, 0)).unwrap();
+                let chunk = ChunkData::from_chunk(&lock_chunk(&chunk_ref), true, 0xffff, false);
+                chunk
+            })
+            .collect();
+        let chunk_size = CHUNK_META_SIZE + chunks[0].data.len();
+
+        let packets = batch_chunks(chunks);
+        let mut codec = MinecraftCodec::new();
+        let mut sent = 0;
         for packet in packets {
-            self.send_packet(packet).await?;
-        }
+            let Packet::S26MapChunkBulk { chunks, .. } = &packet else {
+                panic!("Unexpected packet {:?}", packet.kind());
+            };
+            // Batches are filled up until another chunk would exceed the limit
+            let batch_size = chunks.len() * chunk_size;
+            if sent + chunks.len() < 25 {
+                assert!(batch_size + chunk_size > CHUNK_BATCH_SIZE_LIMIT);
+            }
+            sent += chunks.len();
 
-        Ok(())
+            let mut buf = BytesMut::new();
+            codec.encode(packet, &mut buf).unwrap();
+            // The length prefix is not part of the limit
+            assert!(buf.len() <= PACKET_SIZE_LIMIT + 4);
+        }
+        assert_eq!(sent, 25);
     }
 }
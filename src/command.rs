@@ -1,5 +1,310 @@
 use std::str::FromStr;
 
+use crate::{block_state, world::BlockPos};
+
+/// Permission level of ops, regular players have level 0
+pub const OP_PERMISSION_LEVEL: u8 = 4;
+/// The console may use every command
+pub const CONSOLE_PERMISSION_LEVEL: u8 = u8::MAX;
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    /// Shown in the help, `§7` starts the arguments
+    pub syntax: &'static str,
+    pub description: &'static str,
+    pub permission_level: u8,
+    /// Commands that act on the player who runs them are not available on the console
+    pub needs_player: bool,
+}
+
+/// All commands with their help entries. Commands with several forms have one entry per form.
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "help",
+        syntax: "help",
+        description: "Show command overview",
+        permission_level: 0,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "gm",
+        syntax: "gm §7<mode>",
+        description: "Change gamemode",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "flyspeed",
+        syntax: "flyspeed §7<speed>",
+        description: "Set flying speed multiplier",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "walkspeed",
+        syntax: "walkspeed §7<speed>",
+        description: "Set walking speed multiplier",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "reloadgen",
+        syntax: "reloadgen",
+        description: "Reload the world generator config",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "fill",
+        syntax: "fill §7<x1 y1 z1> <x2 y2 z2> <block[:meta]>",
+        description: "Fill a region",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "clone",
+        syntax: "clone §7<x1 y1 z1> <x2 y2 z2> <x y z>",
+        description: "Copy a region",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "undo",
+        syntax: "undo",
+        description: "Undo the last fill or clone",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "struct",
+        syntax: "struct save §7<name> <x1 y1 z1> <x2 y2 z2>",
+        description: "Save a structure",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "struct",
+        syntax: "struct load §7<name> [x y z]",
+        description: "Place a structure",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "particle",
+        syntax: "particle §7<name> <x y z> [count]",
+        description: "Spawn particles",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "playsound",
+        syntax: "playsound §7<name> <x y z> [volume] [pitch]",
+        description: "Play a sound",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "title",
+        syntax: "title §7<player> <title|subtitle> <json>",
+        description: "Show a title",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "title",
+        syntax: "title §7<player> times <fade in> <stay> <fade out>",
+        description: "Set title timing",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "title",
+        syntax: "title §7<player> <clear|reset>",
+        description: "Hide or reset the title",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "actionbar",
+        syntax: "actionbar §7<player> <text>",
+        description: "Show text above the hotbar",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "team",
+        syntax: "team create §7<name> [color]",
+        description: "Create a team, members' names are shown in its color",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "team",
+        syntax: "team remove §7<name>",
+        description: "Remove a team",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "team",
+        syntax: "team color §7<name> <color>",
+        description: "Change the color of a team",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "team",
+        syntax: "team join §7<name> <player>",
+        description: "Add a player to a team",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "team",
+        syntax: "team leave §7<player>",
+        description: "Remove a player from their team",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "ban",
+        syntax: "ban §7<player> [reason]",
+        description: "Ban a player by name or UUID and kick them",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "pardon",
+        syntax: "pardon §7<player>",
+        description: "Lift the ban of a player",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "banlist",
+        syntax: "banlist",
+        description: "Show banned players",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "whitelist",
+        syntax: "whitelist §7<add|remove> <player>",
+        description: "Change who may join while the whitelist is on",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "whitelist",
+        syntax: "whitelist §7<list|on|off>",
+        description: "Show the whitelist or turn it on or off until restart",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "ban-ip",
+        syntax: "ban-ip §7<address|player>",
+        description: "Ban an address and kick everyone connected from it",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "pardon-ip",
+        syntax: "pardon-ip §7<address>",
+        description: "Lift the ban of an address",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "region",
+        syntax: "region define §7<name> <x1 y1 z1> <x2 y2 z2>",
+        description: "Protect a region, only its owners and ops may build in it",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "region",
+        syntax: "region addowner §7<name> <player>",
+        description: "Let a player build in a region",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "lookup",
+        syntax: "lookup §7<x y z>",
+        description: "Show who changed a block recently",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "worldborder",
+        syntax: "worldborder §7<get|set <radius>|center <x> <z>>",
+        description: "Show or change the world border",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "list",
+        syntax: "list",
+        description: "Show online players",
+        permission_level: 0,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "msg",
+        syntax: "msg §7<player> <message>",
+        description: "Send a private message, also /tell and /w",
+        permission_level: 0,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "nick",
+        syntax: "nick §7[name]",
+        description: "Set your nickname, & starts a color code, none resets it",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "r",
+        syntax: "r §7<message>",
+        description: "Reply to the last private message",
+        permission_level: 0,
+        needs_player: true,
+    },
+    CommandInfo {
+        name: "say",
+        syntax: "say §7<message>",
+        description: "Announce a message, & starts a color code",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+    CommandInfo {
+        name: "stop",
+        syntax: "stop",
+        description: "Stop the server",
+        permission_level: OP_PERMISSION_LEVEL,
+        needs_player: false,
+    },
+];
+
+/// Alternative names of commands, mapped to the name in `COMMANDS`
+const ALIASES: &[(&str, &str)] = &[("tell", "msg"), ("w", "msg")];
+
+pub fn find_command(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Lists the commands that can be used with the given permission level
+pub fn help_text(permission_level: u8) -> String {
+    let mut text = "== §aHelp§r ==".to_string();
+    for command in COMMANDS {
+        if command.permission_level <= permission_level {
+            text += &format!("\n§9 /{}§r: {}", command.syntax, command.description);
+        }
+    }
+    text
+}
+
 pub struct Command<'a> {
     parts: Vec<&'a str>,
 }
@@ -11,7 +316,10 @@ impl<'a> Command<'a> {
     }
 
     pub fn name(&self) -> &'a str {
-        self.parts[0]
+        ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == self.parts[0])
+            .map_or(self.parts[0], |(_, name)| name)
     }
 
     pub fn arg<T: FromStr>(&self, idx: usize) -> Result<T, String> {
@@ -25,4 +333,36 @@ impl<'a> Command<'a> {
             Err(_) => Err(format!("Argument {} is not valid", arg_no)),
         }
     }
+
+    /// Joins all arguments starting at `idx`, e.g. for messages containing spaces
+    pub fn rest(&self, idx: usize) -> Result<String, String> {
+        let arg_no = idx + 1;
+        if arg_no >= self.parts.len() {
+            return Err(format!("Missing argument {}", arg_no));
+        }
+        Ok(self.parts[arg_no..].join(" "))
+    }
+
+    /// Reads a block position from the three arguments starting at `idx`
+    pub fn pos_arg(&self, idx: usize) -> Result<BlockPos, String> {
+        Ok(BlockPos::new(
+            self.arg::<i32>(idx)?,
+            self.arg::<i32>(idx + 1)?,
+            self.arg::<i32>(idx + 2)?,
+        ))
+    }
+
+    /// Reads a block state given as `id` or `id:meta`
+    pub fn block_arg(&self, idx: usize) -> Result<u16, String> {
+        let arg = self.arg::<String>(idx)?;
+        let (id, meta) = match arg.split_once(':') {
+            Some((id, meta)) => (id, meta),
+            None => (arg.as_str(), "0"),
+        };
+
+        match (id.parse::<u16>(), meta.parse::<u8>()) {
+            (Ok(id), Ok(meta)) if id < 4096 && meta < 16 => Ok(block_state!(id, meta)),
+            _ => Err(format!("{} is not a valid block", arg)),
+        }
+    }
 }
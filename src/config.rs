@@ -1,8 +1,38 @@
-use std::{collections::HashMap, fs};
+use std::{collections::BTreeMap, fs};
 
+use log::warn;
+use serde::de::DeserializeOwned;
 use serde_derive::Deserialize;
+use serde_json::{json, Value};
 
-use crate::model::GameMode;
+use uuid::Uuid;
+
+use crate::{
+    command::OP_PERMISSION_LEVEL,
+    model::{Difficulty, GameMode, WorldBorder},
+    world::{gen::FEATURES, registry::registry},
+};
+
+pub const SERVER_CONFIG_PATH: &str = "config/server.toml";
+pub const WORLD_CONFIG_PATH: &str = "config/world.toml";
+pub const BANNED_IPS_PATH: &str = "config/banned-ips.txt";
+pub const BANNED_PLAYERS_PATH: &str = "config/banned-players.json";
+pub const WHITELIST_PATH: &str = "config/whitelist.txt";
+pub const REGIONS_PATH: &str = "config/regions.json";
+pub const BLOCKS_PATH: &str = "config/blocks.toml";
+pub const ITEMS_PATH: &str = "config/items.toml";
+
+/// World types the 1.8 client knows. `flat` moves the horizon down to the bottom of the world.
+pub const WORLD_TYPES: [&str; 5] = ["default", "flat", "largeBiomes", "amplified", "default_1_1"];
+
+/// Players can't go further than this from the origin, like in vanilla
+pub const MAX_BORDER_RADIUS: f64 = 30_000_000.0;
+
+/// Most features a biome may place per successful roll
+const MAX_FEATURE_CLUSTER: u32 = 16;
+
+/// Upper bound for view distances, above this spawn prep and chunk streaming get out of hand
+pub const MAX_VIEW_DIST: i32 = 32;
 
 #[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum BiomeLayer {
@@ -11,6 +41,17 @@ pub enum BiomeLayer {
     Land,
 }
 
+/// How the world generator shapes the land
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TerrainMode {
+    /// One surface height per column
+    #[default]
+    Heightmap,
+    /// 3D noise around the heightmap surface, which allows overhangs and arches. Sea
+    /// biomes still use the heightmap.
+    Density,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct BiomeConfig {
     pub id: u8,
@@ -24,7 +65,32 @@ pub struct BiomeConfig {
     pub blocks: Vec<u8>,
     pub surface_layer: Option<u8>,
     #[serde(default)]
-    pub features: HashMap<String, f64>,
+    pub features: BTreeMap<String, FeatureConfig>,
+}
+
+/// How often a biome has a feature: either just the chance per column, or a table that
+/// also places several of them around the column at once
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum FeatureConfig {
+    Chance(f64),
+    Cluster { chance: f64, min: u32, max: u32 },
+}
+
+impl FeatureConfig {
+    pub fn chance(&self) -> f64 {
+        match self {
+            FeatureConfig::Chance(chance) | FeatureConfig::Cluster { chance, .. } => *chance,
+        }
+    }
+
+    /// Smallest and largest number placed per successful roll
+    pub fn count(&self) -> (u32, u32) {
+        match self {
+            FeatureConfig::Chance(_) => (1, 1),
+            FeatureConfig::Cluster { min, max, .. } => (*min, *max),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -55,34 +121,475 @@ pub struct WorldGenConfig {
     pub cave_lac: f64,
     pub cave_grad_base: f64,
     pub cave_grad_scale: f64,
-    pub biomes: HashMap<String, BiomeConfig>,
-    pub ores: HashMap<String, OreConfig>,
+    /// River noise below which the terrain is lowered into a channel
+    #[serde(default = "WorldGenConfig::default_river_width")]
+    pub river_width: f64,
+    /// Blocks between the water surface and the middle of a river bed
+    #[serde(default = "WorldGenConfig::default_river_depth")]
+    pub river_depth: i32,
+    /// Land with an elevation up to this much above `ocean_level` can have beaches
+    #[serde(default = "WorldGenConfig::default_beach_width")]
+    pub beach_width: f64,
+    /// Blocks above the water surface up to which coasts become beaches
+    #[serde(default = "WorldGenConfig::default_beach_height")]
+    pub beach_height: i32,
+    /// Name of the block that fills seas and rivers, e.g. `lava` for lava oceans
+    #[serde(default = "WorldGenConfig::default_sea_block")]
+    pub sea_block: String,
+    #[serde(default = "WorldGenConfig::default_seabed_scale")]
+    pub seabed_scale: f64,
+    /// Blocks that the seabed rises and falls around its height from the elevation
+    #[serde(default = "WorldGenConfig::default_seabed_variation")]
+    pub seabed_variation: f64,
+    /// Blocks that trenches reach below the seabed
+    #[serde(default = "WorldGenConfig::default_trench_depth")]
+    pub trench_depth: f64,
+    #[serde(default)]
+    pub terrain_mode: TerrainMode,
+    #[serde(default = "WorldGenConfig::default_density_scale")]
+    pub density_scale: f64,
+    #[serde(default = "WorldGenConfig::default_density_lac")]
+    pub density_lac: f64,
+    /// Blocks above and below the heightmap surface that the density noise can reach
+    #[serde(default = "WorldGenConfig::default_density_squash")]
+    pub density_squash: f64,
+    #[serde(default)]
+    pub structure_chance: f64,
+    /// Multiplies the chance of every feature, to thin out or densify vegetation
+    #[serde(default = "WorldGenConfig::default_feature_density")]
+    pub feature_density: f64,
+    pub biomes: BTreeMap<String, BiomeConfig>,
+    pub ores: BTreeMap<String, OreConfig>,
 }
 
 impl WorldGenConfig {
-    pub fn load(path: &str) -> WorldGenConfig {
-        let data = fs::read_to_string(path).expect("World generator config not found");
-        toml::from_str::<WorldGenConfig>(data.as_str())
-            .expect("Failed to parse world generator config")
+    pub fn load(path: &str) -> Result<WorldGenConfig, String> {
+        let config = load_toml::<WorldGenConfig>(path)?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config {}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.octaves < 1 {
+            return Err("octaves must be at least 1".to_string());
+        }
+        if self.biome_smoothing < 0 {
+            return Err("biome_smoothing must not be negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.structure_chance) {
+            return Err("structure_chance must be in [0, 1]".to_string());
+        }
+        if self.feature_density < 0.0 {
+            return Err("feature_density must not be negative".to_string());
+        }
+        if self.river_width <= 0.0 {
+            return Err("river_width must be positive".to_string());
+        }
+        if !(0..=32).contains(&self.river_depth) {
+            return Err("river_depth must be in [0, 32]".to_string());
+        }
+        if self.beach_width < 0.0 {
+            return Err("beach_width must not be negative".to_string());
+        }
+        if !(0..=32).contains(&self.beach_height) {
+            return Err("beach_height must be in [0, 32]".to_string());
+        }
+        if registry().id(&self.sea_block).is_none() {
+            return Err(format!(
+                "sea_block '{}' is not a known block",
+                self.sea_block
+            ));
+        }
+        if self.seabed_variation < 0.0 || self.trench_depth < 0.0 {
+            return Err("seabed_variation and trench_depth must not be negative".to_string());
+        }
+        // Squash is added to the terrain height in blocks, so more than the world height
+        // makes no sense
+        if !(self.density_squash > 0.0 && self.density_squash <= 256.0) {
+            return Err("density_squash must be in (0, 256]".to_string());
+        }
+        if !(self.density_scale > 0.0 && self.density_lac > 0.0) {
+            return Err("density_scale and density_lac must be positive".to_string());
+        }
+        if !self.biomes.contains_key("beach") || !self.biomes.contains_key("forest") {
+            return Err("biomes 'beach' and 'forest' are required".to_string());
+        }
+
+        for (name, biome) in &self.biomes {
+            if biome.blocks.len() != 3 {
+                return Err(format!(
+                    "biome '{}' must have exactly 3 blocks (surface, top, filler), found {}",
+                    name,
+                    biome.blocks.len()
+                ));
+            }
+
+            for (feature, feature_config) in &biome.features {
+                if !FEATURES.contains(&feature.as_str()) {
+                    return Err(format!(
+                        "biome '{}' uses unknown feature '{}', known features are: {}",
+                        name,
+                        feature,
+                        FEATURES.join(", ")
+                    ));
+                }
+                let prob = feature_config.chance();
+                if !(0.0..=1.0).contains(&prob) {
+                    return Err(format!(
+                        "biome '{}' has probability {} for feature '{}', must be in [0, 1]",
+                        name, prob, feature
+                    ));
+                }
+                let (min, max) = feature_config.count();
+                if min < 1 || min > max || max > MAX_FEATURE_CLUSTER {
+                    return Err(format!(
+                        "biome '{}' must place between 1 and {} of feature '{}' with min <= max",
+                        name, MAX_FEATURE_CLUSTER, feature
+                    ));
+                }
+            }
+        }
+
+        for (name, ore) in &self.ores {
+            if ore.spread <= 0.0 {
+                return Err(format!("ore '{}' must have a positive spread", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn default_feature_density() -> f64 {
+        1.0
+    }
+
+    fn default_river_width() -> f64 {
+        0.04
+    }
+
+    fn default_river_depth() -> i32 {
+        4
+    }
+
+    fn default_beach_width() -> f64 {
+        0.05
+    }
+
+    fn default_beach_height() -> i32 {
+        3
+    }
+
+    fn default_sea_block() -> String {
+        "water".to_string()
+    }
+
+    fn default_seabed_scale() -> f64 {
+        0.003
+    }
+
+    fn default_seabed_variation() -> f64 {
+        4.0
+    }
+
+    fn default_trench_depth() -> f64 {
+        16.0
+    }
+
+    fn default_density_scale() -> f64 {
+        0.04
+    }
+
+    fn default_density_lac() -> f64 {
+        2.0
+    }
+
+    fn default_density_squash() -> f64 {
+        48.0
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
-    pub motd: String,
+    pub motd: Value,
     pub slots: i32,
     pub game_mode: GameMode,
-    pub difficulty: u8,
+    pub difficulty: Difficulty,
     pub net_endpoint: String,
     pub net_compression: usize,
-    pub generator_threads: u32,
+    /// Number of world generator threads, `0` or `"auto"` use one per core
+    #[serde(default = "ServerConfig::default_generator_threads")]
+    generator_threads: ThreadCount,
     pub view_dist: i32,
-    pub seed: Option<u32>,
+    /// Radius of chunks generated before the server accepts players, defaults to `view_dist`
+    #[serde(default)]
+    pub spawn_prep_dist: Option<i32>,
+    pub seed: Option<Seed>,
+    /// Half the side length of the world border, defaults to the largest possible world
+    #[serde(default)]
+    pub border_radius: Option<f64>,
+    #[serde(default)]
+    pub border_center: [f64; 2],
+    /// World type shown to clients, it doesn't change the generator
+    #[serde(default = "ServerConfig::default_world_type")]
+    pub world_type: String,
+    /// Hides coordinates, facing and biome from the F3 screen
+    #[serde(default)]
+    pub reduced_debug_info: bool,
+    /// Blocks around the spawn that only ops may change, 0 turns the protection off
+    #[serde(default)]
+    pub spawn_protection: i32,
+    /// Sends ores that are enclosed by solid blocks as stone, so x-ray clients can't find them
+    #[serde(default)]
+    pub anti_xray: bool,
+    /// Seconds between two keep-alives sent to each player
+    #[serde(default = "ServerConfig::default_keep_alive_interval_secs")]
+    pub keep_alive_interval_secs: u64,
+    /// Seconds without a keep-alive response after which a player is disconnected
+    #[serde(default = "ServerConfig::default_keep_alive_timeout_secs")]
+    pub keep_alive_timeout_secs: u64,
+    #[serde(default = "ServerConfig::default_max_edit_volume")]
+    pub max_edit_volume: i64,
+    #[serde(default = "ServerConfig::default_structure_dir")]
+    pub structure_dir: String,
+    #[serde(default = "ServerConfig::default_mob_cap_per_chunk")]
+    pub mob_cap_per_chunk: usize,
+    #[serde(default)]
+    pub keep_inventory: bool,
+    #[serde(default = "ServerConfig::default_player_data_dir")]
+    pub player_data_dir: String,
+    /// File that block changes are logged to for `/lookup`, none turns the log off
+    #[serde(default)]
+    pub block_log: Option<String>,
+    /// Usernames or UUIDs of players that may use privileged commands
+    #[serde(default)]
+    pub ops: Vec<String>,
+    /// Only let players on the whitelist and ops join
+    #[serde(default)]
+    pub whitelist: bool,
+    /// Template for chat messages with the `{username}`, `{displayname}` and `{message}`
+    /// placeholders
+    #[serde(default = "ServerConfig::default_chat_format")]
+    pub chat_format: String,
+}
+
+/// A world seed, either given as a number or as a string that is hashed like vanilla does
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Seed {
+    Number(i64),
+    Text(String),
+}
+
+/// A number of threads, or `"auto"`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ThreadCount {
+    Number(u32),
+    Text(String),
+}
+
+impl Seed {
+    pub fn value(&self) -> u64 {
+        match self {
+            Seed::Number(n) => *n as u64,
+            Seed::Text(text) => match text.parse::<i64>() {
+                Ok(n) => n as u64,
+                Err(_) => Self::java_hash(text) as i64 as u64,
+            },
+        }
+    }
+
+    fn java_hash(text: &str) -> i32 {
+        text.encode_utf16()
+            .fold(0i32, |h, c| h.wrapping_mul(31).wrapping_add(c as i32))
+    }
 }
 
 impl ServerConfig {
-    pub fn load(path: &str) -> ServerConfig {
-        let data = fs::read_to_string(path).expect("Server config not found");
-        toml::from_str::<ServerConfig>(data.as_str()).expect("Failed to parse server config")
+    pub fn load(path: &str) -> Result<ServerConfig, String> {
+        let mut config = load_toml::<ServerConfig>(path)?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config {}: {}", path, e))?;
+        Ok(config)
+    }
+
+    fn validate(&mut self) -> Result<(), String> {
+        if self.view_dist < 1 {
+            return Err("view_dist must be at least 1".to_string());
+        }
+        if self.view_dist > MAX_VIEW_DIST {
+            warn!(
+                "view_dist {} is too large, clamping to {}",
+                self.view_dist, MAX_VIEW_DIST
+            );
+            self.view_dist = MAX_VIEW_DIST;
+        }
+
+        if let Some(dist) = self.spawn_prep_dist {
+            if dist < 0 {
+                return Err("spawn_prep_dist must not be negative".to_string());
+            }
+            if dist > MAX_VIEW_DIST {
+                warn!(
+                    "spawn_prep_dist {} is too large, clamping to {}",
+                    dist, MAX_VIEW_DIST
+                );
+                self.spawn_prep_dist = Some(MAX_VIEW_DIST);
+            }
+        }
+
+        if let Some(radius) = self.border_radius {
+            if !(1.0..=MAX_BORDER_RADIUS).contains(&radius) {
+                return Err(format!(
+                    "border_radius must be between 1 and {}",
+                    MAX_BORDER_RADIUS
+                ));
+            }
+        }
+
+        if let ThreadCount::Text(text) = &self.generator_threads {
+            if text != "auto" {
+                return Err(format!(
+                    "generator_threads must be a number or \"auto\", not \"{}\"",
+                    text
+                ));
+            }
+        }
+        let cores = available_cores();
+        if self.generator_threads() > cores {
+            warn!(
+                "generator_threads {} is more than the {} available cores",
+                self.generator_threads(),
+                cores
+            );
+        }
+
+        if self.spawn_protection < 0 {
+            return Err("spawn_protection must not be negative".to_string());
+        }
+
+        if !WORLD_TYPES.contains(&self.world_type.as_str()) {
+            return Err(format!(
+                "world_type '{}' is unknown, known types are: {}",
+                self.world_type,
+                WORLD_TYPES.join(", ")
+            ));
+        }
+
+        if self.keep_alive_interval_secs == 0 {
+            return Err("keep_alive_interval_secs must be at least 1".to_string());
+        }
+        if self.keep_alive_interval_secs >= self.keep_alive_timeout_secs {
+            return Err(
+                "keep_alive_interval_secs must be less than keep_alive_timeout_secs".to_string(),
+            );
+        }
+
+        if !self.chat_format.contains("{message}") {
+            return Err("chat_format must contain {message}".to_string());
+        }
+        if !self.chat_format.contains("{username}") && !self.chat_format.contains("{displayname}") {
+            return Err("chat_format must contain {username} or {displayname}".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn permission_level(&self, username: &str, uuid: Uuid) -> u8 {
+        let is_op = self.ops.iter().any(|op| {
+            op.eq_ignore_ascii_case(username) || op.parse::<Uuid>().is_ok_and(|u| u == uuid)
+        });
+        if is_op {
+            OP_PERMISSION_LEVEL
+        } else {
+            0
+        }
+    }
+
+    pub fn world_border(&self) -> WorldBorder {
+        WorldBorder {
+            center_x: self.border_center[0],
+            center_z: self.border_center[1],
+            radius: self.border_radius.unwrap_or(MAX_BORDER_RADIUS),
+        }
+    }
+
+    pub fn spawn_prep_dist(&self) -> i32 {
+        self.spawn_prep_dist.unwrap_or(self.view_dist)
     }
+
+    /// Always at least 1, as generation would hang without any threads
+    pub fn generator_threads(&self) -> u32 {
+        match self.generator_threads {
+            ThreadCount::Number(0) | ThreadCount::Text(_) => available_cores(),
+            ThreadCount::Number(n) => n,
+        }
+    }
+
+    fn default_generator_threads() -> ThreadCount {
+        ThreadCount::Text("auto".to_string())
+    }
+
+    fn default_keep_alive_interval_secs() -> u64 {
+        10
+    }
+
+    fn default_keep_alive_timeout_secs() -> u64 {
+        30
+    }
+
+    fn default_max_edit_volume() -> i64 {
+        32768
+    }
+
+    fn default_structure_dir() -> String {
+        "structures".to_string()
+    }
+
+    fn default_mob_cap_per_chunk() -> usize {
+        2
+    }
+
+    fn default_player_data_dir() -> String {
+        "playerdata".to_string()
+    }
+
+    fn default_world_type() -> String {
+        "default".to_string()
+    }
+
+    fn default_chat_format() -> String {
+        "§b{displayname}§r: {message}".to_string()
+    }
+
+    /// Fills in the chat format. The message goes in last so that placeholders typed by
+    /// the player stay as they are.
+    pub fn format_chat(&self, username: &str, display_name: &str, message: &str) -> String {
+        self.chat_format
+            .replace("{username}", username)
+            .replace("{displayname}", display_name)
+            .replace("{message}", message)
+    }
+
+    /// The MOTD as a chat component. A plain string may contain `§` color
+    /// codes and a newline for the second line, a table is used verbatim.
+    pub fn motd_component(&self) -> Value {
+        match &self.motd {
+            Value::String(text) => json!({ "text": text }),
+            component => component.clone(),
+        }
+    }
+}
+
+pub fn load_toml<T: DeserializeOwned>(path: &str) -> Result<T, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    toml::from_str::<T>(data.as_str()).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// Cores that threads can run on, or 1 if that is unknown
+fn available_cores() -> u32 {
+    std::thread::available_parallelism().map_or(1, |n| n.get() as u32)
 }
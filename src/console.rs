@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+use crate::{
+    command::{self, Command, CONSOLE_PERMISSION_LEVEL},
+    server::ServerHandler,
+    utils::strip_colors,
+};
+
+/// Reads commands from stdin and runs them with full permissions
+pub fn start(server: Arc<ServerHandler>) {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // The leading slash is optional on the console
+            let line = match line.starts_with('/') {
+                true => line.to_string(),
+                false => format!("/{}", line),
+            };
+            let command = Command::parse(&line);
+            let result = match command::find_command(command.name()) {
+                Some(info) if info.needs_player => {
+                    Err(format!("/{} can only be used by players", command.name()))
+                }
+                _ => {
+                    server
+                        .exec_command(&command, CONSOLE_PERMISSION_LEVEL)
+                        .await
+                }
+            };
+
+            match result {
+                Ok(Some(message)) => info!("{}", strip_colors(&message)),
+                Ok(None) => {}
+                Err(e) => warn!("{}", strip_colors(&e)),
+            }
+        }
+    });
+}
@@ -0,0 +1,12 @@
+pub mod access;
+pub mod audit;
+pub mod client;
+pub mod command;
+pub mod config;
+pub mod console;
+pub mod listener;
+pub mod mc;
+pub mod model;
+pub mod server;
+pub mod utils;
+pub mod world;
@@ -0,0 +1,122 @@
+use crate::{command::OP_PERMISSION_LEVEL, model::Player, world::BlockPos};
+
+/// Hooks that run while a player acts, before the action takes effect. Returning an
+/// error cancels the action and the message is shown to the player, unless it is empty.
+/// Every method allows by default, so listeners only implement what they care about.
+///
+/// Register listeners with [`ServerHandler::register_listener`](crate::server::ServerHandler::register_listener).
+/// They run on the client tasks, so they must not block.
+pub trait GameListener: Send + Sync {
+    fn on_block_break(
+        &self,
+        _player: &Player,
+        _location: BlockPos,
+        _block_state: u16,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn on_block_place(
+        &self,
+        _player: &Player,
+        _location: BlockPos,
+        _block_state: u16,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Chat messages, not commands
+    fn on_chat(&self, _player: &Player, _message: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Cancelling a join kicks the player with the message
+    fn on_join(&self, _player: &Player) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Leaving can't be cancelled
+    fn on_leave(&self, _player: &Player) {}
+}
+
+/// Keeps players that aren't ops from changing blocks within a square around the spawn
+pub struct SpawnProtection {
+    pub center: BlockPos,
+    pub radius: i32,
+}
+
+impl SpawnProtection {
+    fn check(&self, player: &Player, location: BlockPos) -> Result<(), String> {
+        let protected = (location.x - self.center.x).abs() <= self.radius
+            && (location.z - self.center.z).abs() <= self.radius;
+        if protected && player.permission_level < OP_PERMISSION_LEVEL {
+            return Err("You can't build this close to the spawn".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl GameListener for SpawnProtection {
+    fn on_block_break(&self, player: &Player, location: BlockPos, _: u16) -> Result<(), String> {
+        self.check(player, location)
+    }
+
+    fn on_block_place(&self, player: &Player, location: BlockPos, _: u16) -> Result<(), String> {
+        self.check(player, location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::GameMode;
+
+    /// An example listener, as a plugin would write it: refuses chat messages that
+    /// shout in capitals and leaves everything else to the defaults
+    struct NoShouting;
+
+    impl GameListener for NoShouting {
+        fn on_chat(&self, _player: &Player, message: &str) -> Result<(), String> {
+            let letters: Vec<char> = message.chars().filter(|c| c.is_alphabetic()).collect();
+            if letters.len() >= 5 && letters.iter().all(|c| c.is_uppercase()) {
+                return Err("Please don't shout".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    fn player(permission_level: u8) -> Player {
+        let mut player = Player::new(1, GameMode::Survival);
+        player.permission_level = permission_level;
+        player
+    }
+
+    #[test]
+    fn example_listener_cancels_only_what_it_handles() {
+        let listener: Box<dyn GameListener> = Box::new(NoShouting);
+        let player = player(0);
+        assert!(listener.on_chat(&player, "HELLO EVERYONE").is_err());
+        assert!(listener.on_chat(&player, "Hello everyone").is_ok());
+        assert!(listener.on_join(&player).is_ok());
+        assert!(listener
+            .on_block_break(&player, BlockPos::new(0, 64, 0), 1 << 4)
+            .is_ok());
+    }
+
+    #[test]
+    fn spawn_protection_only_lets_ops_build() {
+        let protection = SpawnProtection {
+            center: BlockPos::new(0, 64, 0),
+            radius: 16,
+        };
+        let near = BlockPos::new(-16, 70, 16);
+        let far = BlockPos::new(17, 70, 0);
+
+        assert!(protection.on_block_place(&player(0), near, 1 << 4).is_err());
+        assert!(protection.on_block_break(&player(0), near, 1 << 4).is_err());
+        assert!(protection.on_block_place(&player(0), far, 1 << 4).is_ok());
+        assert!(protection
+            .on_block_place(&player(OP_PERMISSION_LEVEL), near, 1 << 4)
+            .is_ok());
+    }
+}
@@ -1,31 +1,33 @@
-mod client;
-mod command;
-mod config;
-mod mc;
-mod model;
-mod server;
-mod utils;
-mod world;
-
-use std::sync::Arc;
-
-use log::{debug, info};
+use std::{sync::Arc, time::Duration};
+
+use log::{debug, error, info, warn};
 use stopwatch::Stopwatch;
 use tokio::io;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
 use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
 use tokio_util::codec::Framed;
 
-use crate::client::ClientHandler;
-use crate::config::{ServerConfig, WorldGenConfig};
-use crate::mc::{codec::MinecraftCodec, proto::Packet};
-use crate::server::ServerHandler;
-use crate::world::random_seed;
-use crate::world::sched::GenerationScheduler;
-use crate::world::{gen::WorldGenerator, World};
-
-const SERVER_CONFIG_PATH: &str = "config/server.toml";
-const WORLD_CONFIG_PATH: &str = "config/world.toml";
+use minecraft_rs::access::{AccessList, BanList, RegionList};
+use minecraft_rs::client::ClientHandler;
+use minecraft_rs::config::{
+    ServerConfig, WorldGenConfig, BANNED_IPS_PATH, BANNED_PLAYERS_PATH, BLOCKS_PATH, ITEMS_PATH,
+    REGIONS_PATH, SERVER_CONFIG_PATH, WHITELIST_PATH, WORLD_CONFIG_PATH,
+};
+use minecraft_rs::console;
+use minecraft_rs::mc::{
+    codec::MinecraftCodec,
+    proto::{check_packet_ids, Packet},
+};
+use minecraft_rs::server::ServerHandler;
+use minecraft_rs::world::random_seed;
+use minecraft_rs::world::sched::GenerationScheduler;
+use minecraft_rs::world::{gen::WorldGenerator, registry, World};
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the progress of preparing the spawn region is logged
+const SPAWN_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
@@ -33,58 +35,114 @@ async fn main() -> io::Result<()> {
 
     info!("Starting server...");
     let startup_sw = Stopwatch::start_new();
-    let server = create_server();
+    let server = match create_server() {
+        Ok(server) => server,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     info!("Preparing spawn region...");
     let gen_sw = Stopwatch::start_new();
-    server.gen.request_region(0, 0, server.config.view_dist);
-    server.gen.await_region(0, 0, server.config.view_dist).await;
+    let spawn_prep_dist = server.config.spawn_prep_dist();
+    let mut last_progress_log = Instant::now();
+    server
+        .gen
+        .await_region_progress(0, 0, spawn_prep_dist, |progress| {
+            if last_progress_log.elapsed() >= SPAWN_PROGRESS_INTERVAL {
+                info!(
+                    "Preparing spawn region: {:.0}% ({}/{} chunks)",
+                    progress.percent(),
+                    progress.done,
+                    progress.total
+                );
+                last_progress_log = Instant::now();
+            }
+        })
+        .await;
     info!("Spawn region prepared in {:?}", gen_sw.elapsed());
 
     info!("Binding TCP listener...");
     let listener = TcpListener::bind(server.config.net_endpoint.as_str()).await?;
 
     info!("Done. Server started in {:?}", startup_sw.elapsed());
+    console::start(server.clone());
 
+    let mut shutdown_rx = server.subscribe_shutdown();
     loop {
-        let (stream, _) = listener.accept().await?;
-        let client_id = server.new_id();
-        handle_client(
-            client_id,
-            stream,
-            server.add_client(client_id),
-            server.clone(),
-        );
+        select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let client_id = server.new_id();
+                handle_client(
+                    client_id,
+                    stream,
+                    server.add_client(client_id),
+                    server.clone(),
+                );
+            }
+            _ = shutdown_rx.changed() => break,
+        }
+    }
+
+    // Give the clients a moment to disconnect and save their players
+    info!("Stopping server...");
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while server.num_players() > 0 && Instant::now() < deadline {
+        time::sleep(Duration::from_millis(50)).await;
     }
+    info!("Server stopped");
+    Ok(())
 }
 
-fn create_server() -> Arc<ServerHandler> {
-    let config = Arc::new(ServerConfig::load(SERVER_CONFIG_PATH));
+fn create_server() -> Result<Arc<ServerHandler>, String> {
+    check_packet_ids()?;
+    registry::load(BLOCKS_PATH, ITEMS_PATH)?;
+    let config = Arc::new(ServerConfig::load(SERVER_CONFIG_PATH)?);
     debug!("Loaded config: {:?}", config);
 
+    let banned_ips = AccessList::load(BANNED_IPS_PATH)?;
+    let banned_players = BanList::load(BANNED_PLAYERS_PATH)?;
+    let whitelist = AccessList::load(WHITELIST_PATH)?;
+    let regions = RegionList::load(REGIONS_PATH)?;
+    if config.whitelist && whitelist.is_empty() && config.ops.is_empty() {
+        warn!(
+            "The whitelist is on but {} is empty and there are no ops, nobody can join!",
+            WHITELIST_PATH
+        );
+    }
     let world = Arc::new(World::new());
-    let gen = create_world_gen(&config, &world);
-    ServerHandler::start(config, world, gen)
+    let gen = create_world_gen(&config, &world)?;
+    Ok(ServerHandler::start(
+        config,
+        world,
+        gen,
+        banned_ips,
+        banned_players,
+        whitelist,
+        regions,
+    ))
 }
 
 fn create_world_gen(
     server_conf: &Arc<ServerConfig>,
     world: &Arc<World>,
-) -> Arc<GenerationScheduler> {
-    let config = WorldGenConfig::load(WORLD_CONFIG_PATH);
+) -> Result<Arc<GenerationScheduler>, String> {
+    let config = WorldGenConfig::load(WORLD_CONFIG_PATH)?;
     debug!("Loaded config: {:?}", config);
 
-    let seed = match server_conf.seed {
-        Some(seed) => seed,
+    let seed = match &server_conf.seed {
+        Some(seed) => seed.value(),
         None => random_seed(),
     };
     debug!("Initializing world generator with seed {}", seed);
 
-    Arc::new(GenerationScheduler::new(
+    Ok(Arc::new(GenerationScheduler::new(
         world.clone(),
         Arc::new(WorldGenerator::new(seed, config, world.clone())),
-        server_conf.generator_threads,
-    ))
+        server_conf.generator_threads(),
+    )))
 }
 
 fn handle_client(
@@ -100,7 +158,7 @@ fn handle_client(
         let codec = MinecraftCodec::new();
         let msg_stream = Framed::new(in_stream, codec);
 
-        let mut handler = ClientHandler::new(id, msg_stream, unicast_rx, server);
+        let mut handler = ClientHandler::new(id, client_addr.ip(), msg_stream, unicast_rx, server);
         handler.loop_until_disconnect().await;
 
         debug!("Client {:?} disconnected", client_addr);
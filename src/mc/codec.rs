@@ -1,4 +1,4 @@
-use std::{f32::consts::PI, io};
+use std::io;
 
 use bytes::{Buf, BufMut, BytesMut};
 use log::{debug, trace};
@@ -6,14 +6,18 @@ use tokio_util::codec::{Decoder, Encoder};
 
 use crate::{
     mc::{
-        proto::{DiggingStatus, EntityMetaData, Packet, PlayState, PlayerListItemAction},
+        proto::{
+            AbilityFlags, ClientStatusAction, DiggingStatus, Direction, EntityAction,
+            EntityMetaData, EntityMetaEntry, Packet, PacketKind, PlayState, PlayerListItemAction,
+            TeamAction, TeamInfo, TitleAction, UseEntityAction, WorldBorderAction,
+        },
         zlib,
     },
-    model::ItemStack,
+    model::{ItemStack, Vec3d},
     world::{BlockFace, BlockPos},
 };
 
-const PACKET_SIZE_LIMIT: usize = 2 * 1024 * 1024;
+pub const PACKET_SIZE_LIMIT: usize = 2 * 1024 * 1024;
 
 pub trait MinecraftBufExt {
     fn has_complete_var_int(&mut self) -> bool;
@@ -21,6 +25,7 @@ pub trait MinecraftBufExt {
     fn get_string(&mut self) -> String;
     fn get_bool(&mut self) -> bool;
     fn put_var_int(&mut self, value: i32);
+    fn put_var_long(&mut self, value: i64);
     fn put_string(&mut self, value: &str);
     fn put_bool(&mut self, value: bool);
     fn put_angle(&mut self, value: f32);
@@ -75,6 +80,21 @@ impl MinecraftBufExt for BytesMut {
         }
     }
 
+    fn put_var_long(&mut self, value: i64) {
+        let mut value = value as u64;
+        loop {
+            let mut cur_byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value != 0 {
+                cur_byte |= 0x80;
+            }
+            self.put_u8(cur_byte);
+            if value == 0 {
+                break;
+            }
+        }
+    }
+
     fn put_string(&mut self, value: &str) {
         self.put_var_int(value.len() as i32);
         self.extend_from_slice(value.as_bytes());
@@ -85,8 +105,8 @@ impl MinecraftBufExt for BytesMut {
     }
 
     fn put_angle(&mut self, value: f32) {
-        let scaled = value / (2.0 * PI) * 255.0;
-        self.put_u8(scaled as u8);
+        let scaled = value / 360.0 * 256.0;
+        self.put_u8(scaled as i32 as u8);
     }
 }
 
@@ -122,6 +142,10 @@ impl MinecraftCodec {
         }
     }
 
+    pub fn state(&self) -> &PlayState {
+        &self.play_state
+    }
+
     pub fn set_state(&mut self, next_state: PlayState) {
         debug!("Changing to state {:?}", next_state);
         self.play_state = next_state;
@@ -135,61 +159,78 @@ impl MinecraftCodec {
         self.compression_threshold = compression_threshold;
     }
 
-    fn decode_handshake_packet(&self, packet_id: i32, buf: &mut BytesMut) -> Option<Packet> {
-        match packet_id {
-            0x00 => Some(Packet::C00Handshake {
-                protocol_version: buf.get_var_int(),
-                server_address: buf.get_string(),
-                server_port: buf.get_u16(),
-                next_state: PlayState::try_from(buf.get_var_int())
-                    .expect("Requested invalid state"),
-            }),
-            _ => None,
-        }
-    }
-
-    fn decode_status_packet(&self, packet_id: i32, buf: &mut BytesMut) -> Option<Packet> {
-        match packet_id {
-            0x00 => Some(Packet::C00StatusRequest),
-            0x01 => Some(Packet::C01StatusPing {
+    fn decode_packet(&self, kind: PacketKind, buf: &mut BytesMut) -> io::Result<Option<Packet>> {
+        let packet = match kind {
+            PacketKind::C00Handshake => {
+                let protocol_version = buf.get_var_int();
+                let server_address = buf.get_string();
+                let server_port = buf.get_u16();
+                let next_state = buf.get_var_int();
+                let next_state = PlayState::from_next_state(next_state).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid next state {} in handshake", next_state),
+                    )
+                })?;
+                Some(Packet::C00Handshake {
+                    protocol_version,
+                    server_address,
+                    server_port,
+                    next_state,
+                })
+            }
+            PacketKind::C00StatusRequest => Some(Packet::C00StatusRequest),
+            PacketKind::C01StatusPing => Some(Packet::C01StatusPing {
                 timestamp: buf.get_i64(),
             }),
-            _ => None,
-        }
-    }
-
-    fn decode_login_packet(&self, packet_id: i32, buf: &mut BytesMut) -> Option<Packet> {
-        match packet_id {
-            0x00 => Some(Packet::C00LoginStart {
+            PacketKind::C00LoginStart => Some(Packet::C00LoginStart {
                 username: buf.get_string(),
             }),
-            _ => None,
-        }
-    }
-
-    fn decode_play_packet(&self, packet_id: i32, buf: &mut BytesMut) -> Option<Packet> {
-        match packet_id {
-            0x00 => Some(Packet::C00KeepAlive {
+            PacketKind::C00KeepAlive => Some(Packet::C00KeepAlive {
                 id: buf.get_var_int(),
             }),
-            0x01 => Some(Packet::C01ChatMessage {
+            PacketKind::C01ChatMessage => Some(Packet::C01ChatMessage {
                 message: buf.get_string(),
             }),
-            0x03 => Some(Packet::C03Player {
+            PacketKind::C02UseEntity => {
+                let target = buf.get_var_int();
+                let action = buf.get_var_int();
+                let action = UseEntityAction::from_id(action).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid use entity action {}", action),
+                    )
+                })?;
+                let hit_pos = if action == UseEntityAction::InteractAt {
+                    Some(Vec3d {
+                        x: buf.get_f32() as f64,
+                        y: buf.get_f32() as f64,
+                        z: buf.get_f32() as f64,
+                    })
+                } else {
+                    None
+                };
+                Some(Packet::C02UseEntity {
+                    target,
+                    action,
+                    hit_pos,
+                })
+            }
+            PacketKind::C03Player => Some(Packet::C03Player {
                 on_ground: buf.get_bool(),
             }),
-            0x04 => Some(Packet::C04PlayerPos {
+            PacketKind::C04PlayerPos => Some(Packet::C04PlayerPos {
                 x: buf.get_f64(),
                 y: buf.get_f64(),
                 z: buf.get_f64(),
                 on_ground: buf.get_bool(),
             }),
-            0x05 => Some(Packet::C05PlayerRot {
+            PacketKind::C05PlayerRot => Some(Packet::C05PlayerRot {
                 yaw: buf.get_f32(),
                 pitch: buf.get_f32(),
                 on_ground: buf.get_bool(),
             }),
-            0x06 => Some(Packet::C06PlayerPosRot {
+            PacketKind::C06PlayerPosRot => Some(Packet::C06PlayerPosRot {
                 x: buf.get_f64(),
                 y: buf.get_f64(),
                 z: buf.get_f64(),
@@ -197,37 +238,86 @@ impl MinecraftCodec {
                 pitch: buf.get_f32(),
                 on_ground: buf.get_bool(),
             }),
-            0x07 => Some(Packet::C07PlayerDigging {
+            PacketKind::C07PlayerDigging => Some(Packet::C07PlayerDigging {
                 status: DiggingStatus::from(buf.get_u8()),
                 location: BlockPos::from(buf.get_u64()),
                 face: buf.get_u8(),
             }),
-            0x08 => Some(Packet::C08PlayerBlockPlacement {
-                location: BlockPos::from(buf.get_u64()),
-                face: BlockFace::from(buf.get_u8()),
-            }),
-            0x09 => Some(Packet::C09HeldItemChange {
+            PacketKind::C08PlayerBlockPlacement => {
+                let location = BlockPos::from(buf.get_u64());
+                let face = BlockFace::from(buf.get_u8());
+
+                // The held item may carry NBT data, so the cursor is read from the end
+                if buf.len() < 3 {
+                    return Ok(None);
+                }
+                let cursor = &buf[buf.len() - 3..];
+                Some(Packet::C08PlayerBlockPlacement {
+                    location,
+                    face,
+                    cursor: Vec3d {
+                        x: cursor[0] as f64 / 16.0,
+                        y: cursor[1] as f64 / 16.0,
+                        z: cursor[2] as f64 / 16.0,
+                    },
+                })
+            }
+            PacketKind::C09HeldItemChange => Some(Packet::C09HeldItemChange {
                 slot: buf.get_i16(),
             }),
-            0x0A => Some(Packet::C0AAnimation),
-            0x10 => Some(Packet::C10SetCreativeSlot {
+            PacketKind::C0AAnimation => Some(Packet::C0AAnimation),
+            PacketKind::C0BEntityAction => {
+                let entity_id = buf.get_var_int();
+                let action = buf.get_var_int();
+                let action = EntityAction::from_id(action).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid entity action {}", action),
+                    )
+                })?;
+                Some(Packet::C0BEntityAction {
+                    entity_id,
+                    action,
+                    jump_boost: buf.get_var_int(),
+                })
+            }
+            PacketKind::C10SetCreativeSlot => Some(Packet::C10SetCreativeSlot {
                 slot_id: buf.get_i16(),
                 item: ItemStack::read(buf),
             }),
+            PacketKind::C13PlayerAbilities => Some(Packet::C13PlayerAbilities {
+                flags: AbilityFlags::from(buf.get_u8()),
+                flying_speed: buf.get_f32(),
+                walking_speed: buf.get_f32(),
+            }),
+            PacketKind::C16ClientStatus => {
+                let action = buf.get_var_int();
+                let action = ClientStatusAction::from_id(action).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid client status action {}", action),
+                    )
+                })?;
+                Some(Packet::C16ClientStatus { action })
+            }
             _ => None,
-        }
+        };
+        Ok(packet)
     }
 
-    fn encode_packet(&self, packet: Packet, buf: &mut BytesMut) {
+    /// Writes the packet body. Fails for packets the server doesn't send, so a missing
+    /// encoder surfaces as an error instead of taking down the client task.
+    fn encode_packet(&self, packet: Packet, buf: &mut BytesMut) -> io::Result<()> {
         match packet {
             Packet::S00StatusResponse { status } => buf.put_string(status.as_str()),
             Packet::S01StatusPong { timestamp } => buf.put_i64(timestamp),
+            Packet::S00LoginDisconnect { reason } => buf.put_string(&reason),
             Packet::S02LoginSuccess { uuid, username } => {
                 buf.put_string(uuid.as_str());
                 buf.put_string(username.as_str());
             }
             Packet::S03LoginCompression { threshold } => buf.put_var_int(threshold),
-            Packet::S00KeepAlive { timestamp } => buf.put_var_int(timestamp),
+            Packet::S00KeepAlive { id } => buf.put_var_int(id),
             Packet::S01JoinGame {
                 entity_id,
                 game_mode,
@@ -240,7 +330,7 @@ impl MinecraftCodec {
                 buf.put_i32(entity_id);
                 buf.put_u8(game_mode as u8);
                 buf.put_u8(dimension);
-                buf.put_u8(difficulty);
+                buf.put_u8(difficulty as u8);
                 buf.put_u8(player_list_size);
                 buf.put_string(world_type.as_str());
                 buf.put_bool(reduced_debug_info);
@@ -252,6 +342,26 @@ impl MinecraftCodec {
                 buf.put_string(&json_data);
                 buf.put_u8(position);
             }
+            Packet::S06UpdateHealth {
+                health,
+                food,
+                saturation,
+            } => {
+                buf.put_f32(health);
+                buf.put_var_int(food);
+                buf.put_f32(saturation);
+            }
+            Packet::S07Respawn {
+                dimension,
+                difficulty,
+                game_mode,
+                world_type,
+            } => {
+                buf.put_i32(dimension);
+                buf.put_u8(difficulty as u8);
+                buf.put_u8(game_mode as u8);
+                buf.put_string(world_type.as_str());
+            }
             Packet::S08SetPlayerPosition {
                 x,
                 y,
@@ -267,13 +377,35 @@ impl MinecraftCodec {
                 buf.put_f32(pitch);
                 buf.put_u8(flags);
             }
-            Packet::S21ChunkData { x, z } => {
+            Packet::S21ChunkData { chunk, ground_up } => {
+                buf.put_i32(chunk.x);
+                buf.put_i32(chunk.z);
+                buf.put_bool(ground_up);
+                buf.put_u16(chunk.bitmask);
+                buf.put_var_int(chunk.data.len() as i32);
+                buf.extend_from_slice(&chunk.data[..]);
+            }
+            Packet::S21UnloadChunk { x, z } => {
                 buf.put_i32(x);
                 buf.put_i32(z);
                 buf.put_bool(true);
                 buf.put_u16(0);
                 buf.put_var_int(0);
             }
+            Packet::S22MultiBlockChange {
+                chunk_x,
+                chunk_z,
+                records,
+            } => {
+                buf.put_i32(chunk_x);
+                buf.put_i32(chunk_z);
+                buf.put_var_int(records.len() as i32);
+                for record in records {
+                    buf.put_u8(record.x << 4 | record.z & 0x0f);
+                    buf.put_u8(record.y);
+                    buf.put_var_int(record.block_state as i32);
+                }
+            }
             Packet::S23BlockChange {
                 location,
                 block_state,
@@ -285,45 +417,22 @@ impl MinecraftCodec {
                 buf.put_bool(skylight);
                 buf.put_var_int(chunks.len() as i32);
 
-                // Estimate size of final chunk data array to reduce reallocations
-                let avg_section_size = 4096 + 2 * 4096;
-                let estimated_chunk_array_len = chunks.len() * (256 + 4 * avg_section_size);
-                let mut chunk_buf = BytesMut::with_capacity(estimated_chunk_array_len);
-
-                for chunk in chunks {
-                    let mut bitmask: u16 = 0;
-                    let mut num_sections = 0;
-
-                    // Write blocks and bitmask to data buffer
-                    for i in 0..chunk.sections.len() {
-                        let section = &chunk.sections[i];
-                        if section.is_some() {
-                            bitmask |= 1 << i;
-                            num_sections += 1;
-
-                            let section = section.as_ref().unwrap();
-                            for block_state in section.data {
-                                chunk_buf.put_u16_le(block_state);
-                            }
-                        }
-                    }
-
-                    // Write dummy lighting (Max value everywhere) to data buffer
-                    for _ in 0..(4096 * num_sections / 8) {
-                        chunk_buf.put_u64(0xffffffffffffffff);
-                    }
-
-                    // Write biomes to data buffer
-                    chunk_buf.extend_from_slice(&chunk.biomes[..]);
-
-                    // Write metadata to main buffer
+                // Metadata of all chunks comes first, followed by their data
+                for chunk in &chunks {
                     buf.put_i32(chunk.x);
                     buf.put_i32(chunk.z);
-                    buf.put_u16(bitmask);
+                    buf.put_u16(chunk.bitmask);
+                }
+                for chunk in &chunks {
+                    buf.extend_from_slice(&chunk.data[..]);
                 }
-
-                // Copy data buffer to main buffer
-                buf.extend_from_slice(&chunk_buf[..]);
+            }
+            Packet::S0BAnimation {
+                entity_id,
+                animation,
+            } => {
+                buf.put_var_int(entity_id);
+                buf.put_u8(animation);
             }
             Packet::S0ESpawnObject {
                 entity_id,
@@ -337,51 +446,174 @@ impl MinecraftCodec {
             } => {
                 buf.put_var_int(entity_id);
                 buf.put_u8(kind);
-                buf.put_i32((x * 32.0) as i32);
-                buf.put_i32((y * 32.0) as i32);
-                buf.put_i32((z * 32.0) as i32);
+                buf.put_i32((x * 32.0).floor() as i32);
+                buf.put_i32((y * 32.0).floor() as i32);
+                buf.put_i32((z * 32.0).floor() as i32);
                 buf.put_angle(pitch);
                 buf.put_angle(yaw);
                 buf.put_i32(data);
             }
+            Packet::S0FSpawnMob {
+                entity_id,
+                kind,
+                x,
+                y,
+                z,
+                yaw,
+                pitch,
+                head_pitch,
+                velocity,
+                metadata,
+            } => {
+                buf.put_var_int(entity_id);
+                buf.put_u8(kind);
+                buf.put_i32((x * 32.0).floor() as i32);
+                buf.put_i32((y * 32.0).floor() as i32);
+                buf.put_i32((z * 32.0).floor() as i32);
+                buf.put_angle(yaw);
+                buf.put_angle(pitch);
+                buf.put_angle(head_pitch);
+                put_velocity(buf, velocity);
+                put_entity_meta(buf, metadata);
+            }
+            Packet::S12EntityVelocity {
+                entity_id,
+                velocity,
+            } => {
+                buf.put_var_int(entity_id);
+                put_velocity(buf, velocity);
+            }
+            Packet::S13DestroyEntities { entity_ids } => {
+                buf.put_var_int(entity_ids.len() as i32);
+                for entity_id in entity_ids {
+                    buf.put_var_int(entity_id);
+                }
+            }
+            Packet::S15EntityRelativeMove {
+                entity_id,
+                dx,
+                dy,
+                dz,
+                on_ground,
+            } => {
+                buf.put_var_int(entity_id);
+                buf.put_i8((dx * 32.0).round() as i8);
+                buf.put_i8((dy * 32.0).round() as i8);
+                buf.put_i8((dz * 32.0).round() as i8);
+                buf.put_bool(on_ground);
+            }
+            Packet::S16EntityLook {
+                entity_id,
+                yaw,
+                pitch,
+                on_ground,
+            } => {
+                buf.put_var_int(entity_id);
+                buf.put_angle(yaw);
+                buf.put_angle(pitch);
+                buf.put_bool(on_ground);
+            }
+            Packet::S18EntityTeleport {
+                entity_id,
+                x,
+                y,
+                z,
+                yaw,
+                pitch,
+                on_ground,
+            } => {
+                buf.put_var_int(entity_id);
+                buf.put_i32((x * 32.0).floor() as i32);
+                buf.put_i32((y * 32.0).floor() as i32);
+                buf.put_i32((z * 32.0).floor() as i32);
+                buf.put_angle(yaw);
+                buf.put_angle(pitch);
+                buf.put_bool(on_ground);
+            }
+            Packet::S19EntityHeadLook {
+                entity_id,
+                head_yaw,
+            } => {
+                buf.put_var_int(entity_id);
+                buf.put_angle(head_yaw);
+            }
+            Packet::S1AEntityStatus { entity_id, status } => {
+                buf.put_i32(entity_id);
+                buf.put_u8(status);
+            }
             Packet::S1CEntityMeta { entity_id, entries } => {
                 if entries.is_empty() {
-                    panic!("At least one entity meta entry is required!");
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "At least one entity meta entry is required",
+                    ));
                 }
 
                 buf.put_var_int(entity_id);
-                for entry in entries {
-                    buf.put_u8(entry.data.type_id() << 5 | entry.index & 0x1f);
-                    match entry.data {
-                        EntityMetaData::Byte(v) => buf.put_u8(v),
-                        EntityMetaData::Short(v) => buf.put_i16(v),
-                        EntityMetaData::Int(v) => buf.put_i32(v),
-                        EntityMetaData::Float(v) => buf.put_f32(v),
-                        EntityMetaData::String(v) => buf.put_string(v.as_str()),
-                        EntityMetaData::Slot(itm) => {
-                            buf.put_i16(itm.id);
-                            buf.put_u8(itm.count);
-                            buf.put_u16(itm.damage);
-                            buf.put_u8(0);
-                        }
-                        EntityMetaData::Vec3i { x, y, z } => {
-                            buf.put_i32(x);
-                            buf.put_i32(y);
-                            buf.put_i32(z);
-                        }
-                        EntityMetaData::Vec3f { x, y, z } => {
-                            buf.put_f32(x);
-                            buf.put_f32(y);
-                            buf.put_f32(z);
-                        }
-                    }
+                put_entity_meta(buf, entries);
+            }
+            Packet::S29NamedSoundEffect {
+                name,
+                x,
+                y,
+                z,
+                volume,
+                pitch,
+            } => {
+                buf.put_string(&name);
+                buf.put_i32((x * 8.0).floor() as i32);
+                buf.put_i32((y * 8.0).floor() as i32);
+                buf.put_i32((z * 8.0).floor() as i32);
+                buf.put_f32(volume);
+                buf.put_u8((pitch * 63.0).clamp(0.0, 255.0) as u8);
+            }
+            Packet::S2AParticle {
+                particle_id,
+                long_distance,
+                x,
+                y,
+                z,
+                offset_x,
+                offset_y,
+                offset_z,
+                speed,
+                count,
+                data,
+            } => {
+                buf.put_i32(particle_id);
+                buf.put_bool(long_distance);
+                buf.put_f32(x);
+                buf.put_f32(y);
+                buf.put_f32(z);
+                buf.put_f32(offset_x);
+                buf.put_f32(offset_y);
+                buf.put_f32(offset_z);
+                buf.put_f32(speed);
+                buf.put_i32(count);
+                for value in data {
+                    buf.put_var_int(value);
                 }
-                buf.put_u8(0x7f);
             }
             Packet::S2BChangeGameState { reason, value } => {
                 buf.put_u8(reason as u8);
                 buf.put_f32(value);
             }
+            Packet::S2FSetSlot {
+                window_id,
+                slot,
+                item,
+            } => {
+                buf.put_i8(window_id);
+                buf.put_i16(slot);
+                put_item_stack(buf, item);
+            }
+            Packet::S30WindowItems { window_id, slots } => {
+                buf.put_u8(window_id);
+                buf.put_i16(slots.len() as i16);
+                for stack in slots {
+                    put_item_stack(buf, stack);
+                }
+            }
             Packet::S38PlayerListItem { uuid, action } => {
                 buf.put_var_int(action.id());
                 buf.put_var_int(1);
@@ -439,11 +671,149 @@ impl MinecraftCodec {
                 buf.put_f32(flying_speed);
                 buf.put_f32(walking_speed);
             }
-            _ => panic!("Invalid packet direction!"),
+            Packet::S3ETeams { name, action } => {
+                buf.put_string(&name);
+                buf.put_u8(action.id());
+                match action {
+                    TeamAction::Create { info, players } => {
+                        put_team_info(buf, &info);
+                        put_team_players(buf, &players);
+                    }
+                    TeamAction::Remove => {}
+                    TeamAction::UpdateInfo(info) => put_team_info(buf, &info),
+                    TeamAction::AddPlayers(players) | TeamAction::RemovePlayers(players) => {
+                        put_team_players(buf, &players);
+                    }
+                }
+            }
+            Packet::S40Disconnect { reason } => buf.put_string(&reason),
+            Packet::S41ServerDifficulty { difficulty } => buf.put_u8(difficulty as u8),
+            Packet::S44WorldBorder { action } => {
+                buf.put_var_int(action.id());
+                match action {
+                    WorldBorderAction::SetSize { diameter } => buf.put_f64(diameter),
+                    WorldBorderAction::LerpSize {
+                        old_diameter,
+                        new_diameter,
+                        speed,
+                    } => {
+                        buf.put_f64(old_diameter);
+                        buf.put_f64(new_diameter);
+                        buf.put_var_long(speed);
+                    }
+                    WorldBorderAction::SetCenter { x, z } => {
+                        buf.put_f64(x);
+                        buf.put_f64(z);
+                    }
+                    WorldBorderAction::Initialize {
+                        x,
+                        z,
+                        old_diameter,
+                        new_diameter,
+                        speed,
+                        portal_boundary,
+                        warning_time,
+                        warning_blocks,
+                    } => {
+                        buf.put_f64(x);
+                        buf.put_f64(z);
+                        buf.put_f64(old_diameter);
+                        buf.put_f64(new_diameter);
+                        buf.put_var_long(speed);
+                        buf.put_var_int(portal_boundary);
+                        buf.put_var_int(warning_time);
+                        buf.put_var_int(warning_blocks);
+                    }
+                    WorldBorderAction::SetWarningTime(seconds) => buf.put_var_int(seconds),
+                    WorldBorderAction::SetWarningBlocks(blocks) => buf.put_var_int(blocks),
+                }
+            }
+            Packet::S45Title { action } => {
+                buf.put_var_int(action.id());
+                match action {
+                    TitleAction::SetTitle(json_data) | TitleAction::SetSubtitle(json_data) => {
+                        buf.put_string(&json_data);
+                    }
+                    TitleAction::SetTimes {
+                        fade_in,
+                        stay,
+                        fade_out,
+                    } => {
+                        buf.put_i32(fade_in);
+                        buf.put_i32(stay);
+                        buf.put_i32(fade_out);
+                    }
+                    TitleAction::Hide | TitleAction::Reset => {}
+                }
+            }
+            packet => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("No encoder for packet {:?}", packet),
+                ))
+            }
         }
+        Ok(())
+    }
+}
+
+fn put_item_stack(buf: &mut BytesMut, stack: ItemStack) {
+    buf.put_i16(stack.id);
+    if stack.is_present() {
+        buf.put_u8(stack.count);
+        buf.put_u16(stack.damage);
+        buf.put_u8(0); // No NBT data
     }
 }
 
+fn put_team_info(buf: &mut BytesMut, info: &TeamInfo) {
+    buf.put_string(&info.display_name);
+    buf.put_string(&info.prefix);
+    buf.put_string(&info.suffix);
+    buf.put_u8(info.friendly_fire as u8);
+    buf.put_string(&info.name_tag_visibility);
+    buf.put_i8(info.color);
+}
+
+fn put_team_players(buf: &mut BytesMut, players: &[String]) {
+    buf.put_var_int(players.len() as i32);
+    for player in players {
+        buf.put_string(player);
+    }
+}
+
+/// Velocities are sent in units of 1/8000 blocks per tick
+fn put_velocity(buf: &mut BytesMut, velocity: Vec3d) {
+    buf.put_i16((velocity.x.clamp(-3.9, 3.9) * 8000.0) as i16);
+    buf.put_i16((velocity.y.clamp(-3.9, 3.9) * 8000.0) as i16);
+    buf.put_i16((velocity.z.clamp(-3.9, 3.9) * 8000.0) as i16);
+}
+
+fn put_entity_meta(buf: &mut BytesMut, entries: Vec<EntityMetaEntry>) {
+    for entry in entries {
+        buf.put_u8(entry.data.type_id() << 5 | entry.index & 0x1f);
+        match entry.data {
+            EntityMetaData::Byte(v) => buf.put_u8(v),
+            EntityMetaData::Short(v) => buf.put_i16(v),
+            EntityMetaData::Int(v) => buf.put_i32(v),
+            EntityMetaData::Float(v) => buf.put_f32(v),
+            EntityMetaData::String(v) => buf.put_string(v.as_str()),
+            EntityMetaData::Slot(itm) => put_item_stack(buf, itm),
+            EntityMetaData::Vec3i { x, y, z } => {
+                buf.put_i32(x);
+                buf.put_i32(y);
+                buf.put_i32(z);
+            }
+            EntityMetaData::Vec3f { x, y, z } => {
+                buf.put_f32(x);
+                buf.put_f32(y);
+                buf.put_f32(z);
+            }
+        }
+    }
+    buf.put_u8(0x7f);
+}
+
 impl Decoder for MinecraftCodec {
     type Item = Packet;
 
@@ -485,12 +855,10 @@ impl Decoder for MinecraftCodec {
                 let packet_id = payload.get_var_int();
                 trace!("Decoding packet #{} with length {}", packet_id, packet_len);
 
-                Ok(match self.play_state {
-                    PlayState::Handshake => self.decode_handshake_packet(packet_id, &mut payload),
-                    PlayState::Status => self.decode_status_packet(packet_id, &mut payload),
-                    PlayState::Login => self.decode_login_packet(packet_id, &mut payload),
-                    PlayState::Play => self.decode_play_packet(packet_id, &mut payload),
-                })
+                match PacketKind::lookup(self.play_state, Direction::Serverbound, packet_id) {
+                    Some(kind) => self.decode_packet(kind, &mut payload),
+                    None => Ok(None),
+                }
             }
         }
     }
@@ -500,11 +868,16 @@ impl Encoder<Packet> for MinecraftCodec {
     type Error = io::Error;
 
     fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let packet_id: i32 = item.id();
+        let packet_id = item.id().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No id for packet {:?}", item.kind()),
+            )
+        })?;
 
         let mut packet_buf = BytesMut::new();
         packet_buf.put_var_int(packet_id);
-        self.encode_packet(item, &mut packet_buf);
+        self.encode_packet(item, &mut packet_buf)?;
 
         if packet_buf.len() > PACKET_SIZE_LIMIT {
             return Err(io::Error::new(
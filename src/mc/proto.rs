@@ -1,9 +1,19 @@
+use bytes::BufMut;
+
 use crate::{
-    model::{GameMode, ItemStack},
-    world::{BlockFace, BlockPos, Chunk},
+    block_state,
+    model::{Difficulty, GameMode, ItemStack, Vec3d},
+    world::{is_ore, light::put_section_nibbles, BlockFace, BlockPos, Chunk},
 };
 
-#[derive(Debug, Clone)]
+/// Protocol number of Minecraft 1.8
+pub const PROTOCOL_VERSION: i32 = 47;
+pub const VERSION_NAME: &str = "1.8.0";
+
+/// What anti-xray sends instead of enclosed ores
+const HIDDEN_ORE_STATE: u16 = block_state!(1, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PlayState {
     Handshake,
     Status,
@@ -11,14 +21,14 @@ pub enum PlayState {
     Play,
 }
 
-impl TryFrom<i32> for PlayState {
-    type Error = ();
-
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
+impl PlayState {
+    /// Parses the next state field of the handshake. Only status and login can be
+    /// requested there, the other states are never sent over the wire.
+    pub fn from_next_state(value: i32) -> Option<PlayState> {
         match value {
-            1 => Ok(PlayState::Status),
-            2 => Ok(PlayState::Login),
-            _ => Err(()),
+            1 => Some(PlayState::Status),
+            2 => Some(PlayState::Login),
+            _ => None,
         }
     }
 }
@@ -57,10 +67,37 @@ pub struct EntityMetaEntry {
     pub data: EntityMetaData,
 }
 
+#[allow(dead_code)]
 impl EntityMetaEntry {
+    pub const STATUS_ON_FIRE: u8 = 0x01;
+    pub const STATUS_CROUCHED: u8 = 0x02;
+    pub const STATUS_SPRINTING: u8 = 0x08;
+    pub const STATUS_EATING: u8 = 0x10;
+    pub const STATUS_INVISIBLE: u8 = 0x20;
+
     pub fn new(index: u8, data: EntityMetaData) -> EntityMetaEntry {
         EntityMetaEntry { index, data }
     }
+
+    /// Entity status bit field (index 0), see the `STATUS_*` constants
+    pub fn status(flags: u8) -> EntityMetaEntry {
+        EntityMetaEntry::new(0, EntityMetaData::Byte(flags))
+    }
+
+    /// Custom name shown above the entity (index 2)
+    pub fn name(name: String) -> EntityMetaEntry {
+        EntityMetaEntry::new(2, EntityMetaData::String(name))
+    }
+
+    /// Whether the custom name is always visible (index 3)
+    pub fn name_visible(visible: bool) -> EntityMetaEntry {
+        EntityMetaEntry::new(3, EntityMetaData::Byte(visible as u8))
+    }
+
+    /// Item stack of an item entity (index 10)
+    pub fn item(stack: ItemStack) -> EntityMetaEntry {
+        EntityMetaEntry::new(10, EntityMetaData::Slot(stack))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -95,6 +132,12 @@ impl AbilityFlags {
     }
 }
 
+impl From<u8> for AbilityFlags {
+    fn from(val: u8) -> Self {
+        AbilityFlags::new(val & 4 != 0, val & 8 != 0, val & 2 != 0, val & 1 != 0)
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum PlayerListItemAction {
@@ -128,6 +171,140 @@ impl PlayerListItemAction {
     }
 }
 
+/// Chat color names in the order of their codes
+pub const CHAT_COLORS: [&str; 16] = [
+    "black",
+    "dark_blue",
+    "dark_green",
+    "dark_aqua",
+    "dark_red",
+    "dark_purple",
+    "gold",
+    "gray",
+    "dark_gray",
+    "blue",
+    "green",
+    "aqua",
+    "red",
+    "light_purple",
+    "yellow",
+    "white",
+];
+
+pub fn chat_color(name: &str) -> Option<u8> {
+    CHAT_COLORS
+        .iter()
+        .position(|&n| n.eq_ignore_ascii_case(name))
+        .map(|code| code as u8)
+}
+
+#[derive(Debug, Clone)]
+pub struct TeamInfo {
+    pub display_name: String,
+    /// Shown before the names of members. The 1.8 client colors name tags only through this.
+    pub prefix: String,
+    pub suffix: String,
+    pub friendly_fire: bool,
+    /// `always`, `hideForOtherTeams`, `hideForOwnTeam` or `never`
+    pub name_tag_visibility: String,
+    /// Chat color code, or -1 for none
+    pub color: i8,
+}
+
+#[derive(Debug, Clone)]
+pub enum TeamAction {
+    Create {
+        info: TeamInfo,
+        players: Vec<String>,
+    },
+    Remove,
+    UpdateInfo(TeamInfo),
+    AddPlayers(Vec<String>),
+    RemovePlayers(Vec<String>),
+}
+
+impl TeamAction {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Create { .. } => 0,
+            Self::Remove => 1,
+            Self::UpdateInfo(_) => 2,
+            Self::AddPlayers(_) => 3,
+            Self::RemovePlayers(_) => 4,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum WorldBorderAction {
+    SetSize {
+        diameter: f64,
+    },
+    /// Grows or shrinks the border over `speed` milliseconds
+    LerpSize {
+        old_diameter: f64,
+        new_diameter: f64,
+        speed: i64,
+    },
+    SetCenter {
+        x: f64,
+        z: f64,
+    },
+    Initialize {
+        x: f64,
+        z: f64,
+        old_diameter: f64,
+        new_diameter: f64,
+        speed: i64,
+        portal_boundary: i32,
+        warning_time: i32,
+        warning_blocks: i32,
+    },
+    SetWarningTime(i32),
+    SetWarningBlocks(i32),
+}
+
+impl WorldBorderAction {
+    pub fn id(&self) -> i32 {
+        match self {
+            Self::SetSize { .. } => 0,
+            Self::LerpSize { .. } => 1,
+            Self::SetCenter { .. } => 2,
+            Self::Initialize { .. } => 3,
+            Self::SetWarningTime(_) => 4,
+            Self::SetWarningBlocks(_) => 5,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum TitleAction {
+    SetTitle(String),
+    SetSubtitle(String),
+    /// Fade in, stay and fade out durations in ticks
+    SetTimes {
+        fade_in: i32,
+        stay: i32,
+        fade_out: i32,
+    },
+    Hide,
+    Reset,
+}
+
+impl TitleAction {
+    pub fn id(&self) -> i32 {
+        match self {
+            Self::SetTitle(_) => 0,
+            Self::SetSubtitle(_) => 1,
+            Self::SetTimes { .. } => 2,
+            Self::Hide => 3,
+            Self::Reset => 4,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DiggingStatus {
     StartDigging,
@@ -152,6 +329,234 @@ impl From<u8> for DiggingStatus {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UseEntityAction {
+    Interact,
+    Attack,
+    InteractAt,
+}
+
+impl UseEntityAction {
+    /// Parses the action of a use entity packet, which comes straight from the client
+    pub fn from_id(value: i32) -> Option<UseEntityAction> {
+        match value {
+            0 => Some(UseEntityAction::Interact),
+            1 => Some(UseEntityAction::Attack),
+            2 => Some(UseEntityAction::InteractAt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientStatusAction {
+    PerformRespawn,
+    RequestStats,
+    OpenInventory,
+}
+
+impl ClientStatusAction {
+    /// Parses the action of a client status packet, which comes straight from the client
+    pub fn from_id(value: i32) -> Option<ClientStatusAction> {
+        match value {
+            0 => Some(ClientStatusAction::PerformRespawn),
+            1 => Some(ClientStatusAction::RequestStats),
+            2 => Some(ClientStatusAction::OpenInventory),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityAction {
+    StartSneaking,
+    StopSneaking,
+    LeaveBed,
+    StartSprinting,
+    StopSprinting,
+    HorseJump,
+    OpenInventory,
+}
+
+impl EntityAction {
+    /// Parses the action of an entity action packet, which comes straight from the client
+    pub fn from_id(value: i32) -> Option<EntityAction> {
+        match value {
+            0 => Some(EntityAction::StartSneaking),
+            1 => Some(EntityAction::StopSneaking),
+            2 => Some(EntityAction::LeaveBed),
+            3 => Some(EntityAction::StartSprinting),
+            4 => Some(EntityAction::StopSprinting),
+            5 => Some(EntityAction::HorseJump),
+            6 => Some(EntityAction::OpenInventory),
+            _ => None,
+        }
+    }
+}
+
+/// A single block change within a chunk, with coordinates relative to the chunk
+#[derive(Debug, Clone)]
+pub struct BlockChangeRecord {
+    pub x: u8,
+    pub y: u8,
+    pub z: u8,
+    pub block_state: u16,
+}
+
+/// A chunk serialized in the network format, so that sending it doesn't need
+/// a copy of the whole chunk
+#[derive(Debug, Clone)]
+pub struct ChunkData {
+    pub x: i32,
+    pub z: i32,
+    pub bitmask: u16,
+    pub data: Vec<u8>,
+}
+
+impl ChunkData {
+    /// Serializes the sections selected by `section_mask`.
+    ///
+    /// A ground-up chunk replaces the whole chunk on the client, so sections that only
+    /// contain air are left out and the biome array is appended. Any other chunk only
+    /// replaces the sections it contains: all selected sections are written, even empty
+    /// ones, and the biomes must be omitted or the client misreads the data.
+    ///
+    /// With `hide_ores`, ores that are enclosed by solid blocks are sent as stone. The
+    /// stored chunk is not changed.
+    ///
+    /// Light is computed from the blocks, as the client relights around block changes
+    /// starting from the light it received here.
+    pub fn from_chunk(
+        chunk: &Chunk,
+        ground_up: bool,
+        section_mask: u16,
+        hide_ores: bool,
+    ) -> ChunkData {
+        let sections: Vec<_> = chunk
+            .sections
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| section_mask & (1 << i) != 0)
+            .filter(|(_, s)| !ground_up || s.as_ref().is_some_and(|s| !s.is_empty()))
+            .collect();
+        let num_sections = sections.len();
+        let section_indices: Vec<usize> = sections.iter().map(|(i, _)| *i).collect();
+        let mut data = Vec::with_capacity(256 + num_sections * (2 * 4096 + 4096));
+        let mut bitmask: u16 = 0;
+
+        // Write blocks and bitmask
+        for (i, section) in sections {
+            bitmask |= 1 << i;
+            let uniform_block = match section {
+                Some(section) => section.uniform_block(),
+                None => Some(0),
+            };
+            match uniform_block {
+                Some(block_state) => {
+                    let bytes = block_state.to_le_bytes();
+                    data.extend(bytes.iter().cycle().take(2 * 4096));
+                }
+                None => {
+                    let base_y = i as i32 * 16;
+                    for (idx, &block_state) in section.as_ref().unwrap().data.iter().enumerate() {
+                        let idx = idx as i32;
+                        let hidden = hide_ores
+                            && is_ore(block_state)
+                            && chunk.is_enclosed(
+                                idx & 0x0f,
+                                base_y + (idx >> 8),
+                                (idx >> 4) & 0x0f,
+                            );
+                        data.put_u16_le(if hidden {
+                            HIDDEN_ORE_STATE
+                        } else {
+                            block_state
+                        });
+                    }
+                }
+            }
+        }
+
+        // Write block light, then sky light
+        let light = chunk.light();
+        for &i in &section_indices {
+            put_section_nibbles(&mut data, &light.block, i);
+        }
+        for &i in &section_indices {
+            put_section_nibbles(&mut data, &light.sky, i);
+        }
+
+        // Write biomes
+        if ground_up {
+            data.extend_from_slice(&chunk.biomes[..]);
+        }
+
+        ChunkData {
+            x: chunk.x,
+            z: chunk.z,
+            bitmask,
+            data,
+        }
+    }
+}
+
+/// Particle names in the order of their ids, as used by the vanilla `/particle` command
+pub const PARTICLE_NAMES: [&str; 42] = [
+    "explode",
+    "largeexplode",
+    "hugeexplosion",
+    "fireworksSpark",
+    "bubble",
+    "splash",
+    "wake",
+    "suspended",
+    "depthsuspend",
+    "crit",
+    "magicCrit",
+    "smoke",
+    "largesmoke",
+    "spell",
+    "instantSpell",
+    "mobSpell",
+    "mobSpellAmbient",
+    "witchMagic",
+    "dripWater",
+    "dripLava",
+    "angryVillager",
+    "happyVillager",
+    "townaura",
+    "note",
+    "portal",
+    "enchantmenttable",
+    "flame",
+    "lava",
+    "footstep",
+    "cloud",
+    "reddust",
+    "snowballpoof",
+    "snowshovel",
+    "slime",
+    "heart",
+    "barrier",
+    "iconcrack",
+    "blockcrack",
+    "blockdust",
+    "droplet",
+    "take",
+    "mobappearance",
+];
+
+pub const PARTICLE_ICON_CRACK: i32 = 36;
+pub const PARTICLE_BLOCK_CRACK: i32 = 37;
+pub const PARTICLE_BLOCK_DUST: i32 = 38;
+
+pub fn particle_id(name: &str) -> Option<i32> {
+    PARTICLE_NAMES
+        .iter()
+        .position(|&n| n.eq_ignore_ascii_case(name))
+        .map(|id| id as i32)
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum GameStateReason {
@@ -193,6 +598,9 @@ pub enum Packet {
     C00LoginStart {
         username: String,
     },
+    S00LoginDisconnect {
+        reason: String,
+    },
     S02LoginSuccess {
         uuid: String,
         username: String,
@@ -208,6 +616,11 @@ pub enum Packet {
     C01ChatMessage {
         message: String,
     },
+    C02UseEntity {
+        target: i32,
+        action: UseEntityAction,
+        hit_pos: Option<Vec3d>,
+    },
     C03Player {
         on_ground: bool,
     },
@@ -238,23 +651,37 @@ pub enum Packet {
     C08PlayerBlockPlacement {
         location: BlockPos,
         face: BlockFace,
+        cursor: Vec3d,
     },
     C09HeldItemChange {
         slot: i16,
     },
     C0AAnimation,
+    C0BEntityAction {
+        entity_id: i32,
+        action: EntityAction,
+        jump_boost: i32,
+    },
     C10SetCreativeSlot {
         slot_id: i16,
         item: ItemStack,
     },
+    C13PlayerAbilities {
+        flags: AbilityFlags,
+        flying_speed: f32,
+        walking_speed: f32,
+    },
+    C16ClientStatus {
+        action: ClientStatusAction,
+    },
     S00KeepAlive {
-        timestamp: i32,
+        id: i32,
     },
     S01JoinGame {
         entity_id: i32,
         game_mode: GameMode,
         dimension: u8,
-        difficulty: u8,
+        difficulty: Difficulty,
         player_list_size: u8,
         world_type: String,
         reduced_debug_info: bool,
@@ -263,6 +690,17 @@ pub enum Packet {
         json_data: String,
         position: u8,
     },
+    S06UpdateHealth {
+        health: f32,
+        food: i32,
+        saturation: f32,
+    },
+    S07Respawn {
+        dimension: i32,
+        difficulty: Difficulty,
+        game_mode: GameMode,
+        world_type: String,
+    },
     S08SetPlayerPosition {
         x: f64,
         y: f64,
@@ -271,6 +709,10 @@ pub enum Packet {
         pitch: f32,
         flags: u8,
     },
+    S0BAnimation {
+        entity_id: i32,
+        animation: u8,
+    },
     S0ESpawnObject {
         entity_id: i32,
         kind: u8,
@@ -281,26 +723,115 @@ pub enum Packet {
         yaw: f32,
         data: i32,
     },
+    S0FSpawnMob {
+        entity_id: i32,
+        kind: u8,
+        x: f64,
+        y: f64,
+        z: f64,
+        yaw: f32,
+        pitch: f32,
+        head_pitch: f32,
+        velocity: Vec3d,
+        metadata: Vec<EntityMetaEntry>,
+    },
+    S12EntityVelocity {
+        entity_id: i32,
+        velocity: Vec3d,
+    },
+    S13DestroyEntities {
+        entity_ids: Vec<i32>,
+    },
+    S15EntityRelativeMove {
+        entity_id: i32,
+        dx: f64,
+        dy: f64,
+        dz: f64,
+        on_ground: bool,
+    },
+    S16EntityLook {
+        entity_id: i32,
+        yaw: f32,
+        pitch: f32,
+        on_ground: bool,
+    },
+    S18EntityTeleport {
+        entity_id: i32,
+        x: f64,
+        y: f64,
+        z: f64,
+        yaw: f32,
+        pitch: f32,
+        on_ground: bool,
+    },
+    S19EntityHeadLook {
+        entity_id: i32,
+        head_yaw: f32,
+    },
+    S1AEntityStatus {
+        entity_id: i32,
+        status: u8,
+    },
     S1CEntityMeta {
         entity_id: i32,
         entries: Vec<EntityMetaEntry>,
     },
     S21ChunkData {
+        chunk: ChunkData,
+        ground_up: bool,
+    },
+    /// Chunk data without any sections, which makes the client unload the chunk
+    S21UnloadChunk {
         x: i32,
         z: i32,
     },
+    S22MultiBlockChange {
+        chunk_x: i32,
+        chunk_z: i32,
+        records: Vec<BlockChangeRecord>,
+    },
     S23BlockChange {
         location: BlockPos,
         block_state: u16,
     },
     S26MapChunkBulk {
         skylight: bool,
-        chunks: Vec<Chunk>,
+        chunks: Vec<ChunkData>,
+    },
+    S29NamedSoundEffect {
+        name: String,
+        x: f64,
+        y: f64,
+        z: f64,
+        volume: f32,
+        pitch: f32,
+    },
+    S2AParticle {
+        particle_id: i32,
+        long_distance: bool,
+        x: f32,
+        y: f32,
+        z: f32,
+        offset_x: f32,
+        offset_y: f32,
+        offset_z: f32,
+        speed: f32,
+        count: i32,
+        data: Vec<i32>,
     },
     S2BChangeGameState {
         reason: GameStateReason,
         value: f32,
     },
+    S2FSetSlot {
+        window_id: i8,
+        slot: i16,
+        item: ItemStack,
+    },
+    S30WindowItems {
+        window_id: u8,
+        slots: Vec<ItemStack>,
+    },
     S38PlayerListItem {
         uuid: uuid::Uuid,
         action: PlayerListItemAction,
@@ -310,49 +841,768 @@ pub enum Packet {
         flying_speed: f32,
         walking_speed: f32,
     },
+    S3ETeams {
+        name: String,
+        action: TeamAction,
+    },
+    S40Disconnect {
+        reason: String,
+    },
+    S41ServerDifficulty {
+        difficulty: Difficulty,
+    },
+    S44WorldBorder {
+        action: WorldBorderAction,
+    },
+    S45Title {
+        action: TitleAction,
+    },
 }
 
 impl Packet {
-    pub fn id(&self) -> i32 {
+    pub fn kind(&self) -> PacketKind {
+        match self {
+            Packet::C00Handshake { .. } => PacketKind::C00Handshake,
+            Packet::C00StatusRequest { .. } => PacketKind::C00StatusRequest,
+            Packet::C01StatusPing { .. } => PacketKind::C01StatusPing,
+            Packet::S00StatusResponse { .. } => PacketKind::S00StatusResponse,
+            Packet::S01StatusPong { .. } => PacketKind::S01StatusPong,
+            Packet::C00LoginStart { .. } => PacketKind::C00LoginStart,
+            Packet::S00LoginDisconnect { .. } => PacketKind::S00LoginDisconnect,
+            Packet::S02LoginSuccess { .. } => PacketKind::S02LoginSuccess,
+            Packet::S03LoginCompression { .. } => PacketKind::S03LoginCompression,
+            Packet::C00KeepAlive { .. } => PacketKind::C00KeepAlive,
+            Packet::C01ChatMessage { .. } => PacketKind::C01ChatMessage,
+            Packet::C02UseEntity { .. } => PacketKind::C02UseEntity,
+            Packet::C03Player { .. } => PacketKind::C03Player,
+            Packet::C04PlayerPos { .. } => PacketKind::C04PlayerPos,
+            Packet::C05PlayerRot { .. } => PacketKind::C05PlayerRot,
+            Packet::C06PlayerPosRot { .. } => PacketKind::C06PlayerPosRot,
+            Packet::C07PlayerDigging { .. } => PacketKind::C07PlayerDigging,
+            Packet::C08PlayerBlockPlacement { .. } => PacketKind::C08PlayerBlockPlacement,
+            Packet::C09HeldItemChange { .. } => PacketKind::C09HeldItemChange,
+            Packet::C0AAnimation { .. } => PacketKind::C0AAnimation,
+            Packet::C0BEntityAction { .. } => PacketKind::C0BEntityAction,
+            Packet::C10SetCreativeSlot { .. } => PacketKind::C10SetCreativeSlot,
+            Packet::C13PlayerAbilities { .. } => PacketKind::C13PlayerAbilities,
+            Packet::C16ClientStatus { .. } => PacketKind::C16ClientStatus,
+            Packet::S00KeepAlive { .. } => PacketKind::S00KeepAlive,
+            Packet::S01JoinGame { .. } => PacketKind::S01JoinGame,
+            Packet::S02ChatMessage { .. } => PacketKind::S02ChatMessage,
+            Packet::S06UpdateHealth { .. } => PacketKind::S06UpdateHealth,
+            Packet::S07Respawn { .. } => PacketKind::S07Respawn,
+            Packet::S08SetPlayerPosition { .. } => PacketKind::S08SetPlayerPosition,
+            Packet::S0BAnimation { .. } => PacketKind::S0BAnimation,
+            Packet::S0ESpawnObject { .. } => PacketKind::S0ESpawnObject,
+            Packet::S0FSpawnMob { .. } => PacketKind::S0FSpawnMob,
+            Packet::S12EntityVelocity { .. } => PacketKind::S12EntityVelocity,
+            Packet::S13DestroyEntities { .. } => PacketKind::S13DestroyEntities,
+            Packet::S15EntityRelativeMove { .. } => PacketKind::S15EntityRelativeMove,
+            Packet::S16EntityLook { .. } => PacketKind::S16EntityLook,
+            Packet::S18EntityTeleport { .. } => PacketKind::S18EntityTeleport,
+            Packet::S19EntityHeadLook { .. } => PacketKind::S19EntityHeadLook,
+            Packet::S1AEntityStatus { .. } => PacketKind::S1AEntityStatus,
+            Packet::S1CEntityMeta { .. } => PacketKind::S1CEntityMeta,
+            Packet::S21ChunkData { .. } => PacketKind::S21ChunkData,
+            Packet::S21UnloadChunk { .. } => PacketKind::S21UnloadChunk,
+            Packet::S22MultiBlockChange { .. } => PacketKind::S22MultiBlockChange,
+            Packet::S23BlockChange { .. } => PacketKind::S23BlockChange,
+            Packet::S26MapChunkBulk { .. } => PacketKind::S26MapChunkBulk,
+            Packet::S29NamedSoundEffect { .. } => PacketKind::S29NamedSoundEffect,
+            Packet::S2AParticle { .. } => PacketKind::S2AParticle,
+            Packet::S2BChangeGameState { .. } => PacketKind::S2BChangeGameState,
+            Packet::S2FSetSlot { .. } => PacketKind::S2FSetSlot,
+            Packet::S30WindowItems { .. } => PacketKind::S30WindowItems,
+            Packet::S38PlayerListItem { .. } => PacketKind::S38PlayerListItem,
+            Packet::S39PlayerAbilities { .. } => PacketKind::S39PlayerAbilities,
+            Packet::S3ETeams { .. } => PacketKind::S3ETeams,
+            Packet::S40Disconnect { .. } => PacketKind::S40Disconnect,
+            Packet::S41ServerDifficulty { .. } => PacketKind::S41ServerDifficulty,
+            Packet::S44WorldBorder { .. } => PacketKind::S44WorldBorder,
+            Packet::S45Title { .. } => PacketKind::S45Title,
+        }
+    }
+
+    /// Whether a broadcast should skip ahead of bulk traffic like movement and block updates
+    pub fn is_priority(&self) -> bool {
+        matches!(
+            self,
+            Packet::S02ChatMessage { .. }
+                | Packet::S38PlayerListItem { .. }
+                | Packet::S40Disconnect { .. }
+                | Packet::S45Title { .. }
+        )
+    }
+
+    /// Whether a packet is part of the movement and block update traffic, which has its own
+    /// queue per client. These can be dropped when a client falls behind, as it is resynced
+    /// afterwards.
+    pub fn is_bulk(&self) -> bool {
+        matches!(
+            self,
+            Packet::S12EntityVelocity { .. }
+                | Packet::S15EntityRelativeMove { .. }
+                | Packet::S16EntityLook { .. }
+                | Packet::S18EntityTeleport { .. }
+                | Packet::S19EntityHeadLook { .. }
+                | Packet::S21ChunkData { .. }
+                | Packet::S22MultiBlockChange { .. }
+                | Packet::S23BlockChange { .. }
+        )
+    }
+
+    /// The entity a clientbound packet is about, if it is about a single one
+    pub fn entity_id(&self) -> Option<i32> {
         match self {
-            // Handshake
-            &Packet::C00Handshake { .. } => 0x00,
-
-            // Status
-            &Packet::C00StatusRequest { .. } => 0x00,
-            &Packet::C01StatusPing { .. } => 0x01,
-            &Packet::S00StatusResponse { .. } => 0x00,
-            &Packet::S01StatusPong { .. } => 0x01,
-
-            // Login
-            &Packet::C00LoginStart { .. } => 0x00,
-            &Packet::S02LoginSuccess { .. } => 0x02,
-            &Packet::S03LoginCompression { .. } => 0x03,
-
-            // PLay
-            &Packet::C00KeepAlive { .. } => 0x00,
-            &Packet::C01ChatMessage { .. } => 0x01,
-            &Packet::C03Player { .. } => 0x03,
-            &Packet::C04PlayerPos { .. } => 0x04,
-            &Packet::C05PlayerRot { .. } => 0x05,
-            &Packet::C06PlayerPosRot { .. } => 0x06,
-            &Packet::C07PlayerDigging { .. } => 0x07,
-            &Packet::C08PlayerBlockPlacement { .. } => 0x08,
-            &Packet::C09HeldItemChange { .. } => 0x09,
-            &Packet::C0AAnimation { .. } => 0x09,
-            &Packet::C10SetCreativeSlot { .. } => 0x10,
-            &Packet::S00KeepAlive { .. } => 0x00,
-            &Packet::S01JoinGame { .. } => 0x01,
-            &Packet::S02ChatMessage { .. } => 0x02,
-            &Packet::S08SetPlayerPosition { .. } => 0x08,
-            &Packet::S0ESpawnObject { .. } => 0x0E,
-            &Packet::S1CEntityMeta { .. } => 0x1C,
-            &Packet::S21ChunkData { .. } => 0x21,
-            &Packet::S23BlockChange { .. } => 0x23,
-            &Packet::S26MapChunkBulk { .. } => 0x26,
-            &Packet::S2BChangeGameState { .. } => 0x2B,
-            &Packet::S38PlayerListItem { .. } => 0x38,
-            &Packet::S39PlayerAbilities { .. } => 0x39,
+            Packet::S0BAnimation { entity_id, .. }
+            | Packet::S0ESpawnObject { entity_id, .. }
+            | Packet::S0FSpawnMob { entity_id, .. }
+            | Packet::S12EntityVelocity { entity_id, .. }
+            | Packet::S15EntityRelativeMove { entity_id, .. }
+            | Packet::S16EntityLook { entity_id, .. }
+            | Packet::S18EntityTeleport { entity_id, .. }
+            | Packet::S19EntityHeadLook { entity_id, .. }
+            | Packet::S1AEntityStatus { entity_id, .. }
+            | Packet::S1CEntityMeta { entity_id, .. } => Some(*entity_id),
+            _ => None,
+        }
+    }
+
+    /// The packet id from [`PACKET_IDS`], if the packet is listed there
+    pub fn id(&self) -> Option<i32> {
+        let kind = self.kind();
+        PACKET_IDS
+            .iter()
+            .find(|(k, ..)| *k == kind)
+            .map(|(.., id)| *id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Serverbound,
+    Clientbound,
+}
+
+/// The variants of [`Packet`] without their data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    // State::Handshake
+    C00Handshake,
+
+    // State::Status
+    C00StatusRequest,
+    C01StatusPing,
+    S00StatusResponse,
+    S01StatusPong,
+
+    // State::Login
+    C00LoginStart,
+    S00LoginDisconnect,
+    S02LoginSuccess,
+    S03LoginCompression,
+
+    // State::Play
+    C00KeepAlive,
+    C01ChatMessage,
+    C02UseEntity,
+    C03Player,
+    C04PlayerPos,
+    C05PlayerRot,
+    C06PlayerPosRot,
+    C07PlayerDigging,
+    C08PlayerBlockPlacement,
+    C09HeldItemChange,
+    C0AAnimation,
+    C0BEntityAction,
+    C10SetCreativeSlot,
+    C13PlayerAbilities,
+    C16ClientStatus,
+    S00KeepAlive,
+    S01JoinGame,
+    S02ChatMessage,
+    S06UpdateHealth,
+    S07Respawn,
+    S08SetPlayerPosition,
+    S0BAnimation,
+    S0ESpawnObject,
+    S0FSpawnMob,
+    S12EntityVelocity,
+    S13DestroyEntities,
+    S15EntityRelativeMove,
+    S16EntityLook,
+    S18EntityTeleport,
+    S19EntityHeadLook,
+    S1AEntityStatus,
+    S1CEntityMeta,
+    S21ChunkData,
+    S21UnloadChunk,
+    S22MultiBlockChange,
+    S23BlockChange,
+    S26MapChunkBulk,
+    S29NamedSoundEffect,
+    S2AParticle,
+    S2BChangeGameState,
+    S2FSetSlot,
+    S30WindowItems,
+    S38PlayerListItem,
+    S39PlayerAbilities,
+    S3ETeams,
+    S40Disconnect,
+    S41ServerDifficulty,
+    S44WorldBorder,
+    S45Title,
+}
+
+impl PacketKind {
+    /// Finds the packet with the given id, as received or sent in a state
+    pub fn lookup(state: PlayState, direction: Direction, id: i32) -> Option<PacketKind> {
+        PACKET_IDS
+            .iter()
+            .find(|(_, s, d, i)| *s == state && *d == direction && *i == id)
+            .map(|(kind, ..)| *kind)
+    }
+}
+
+/// State, direction and id of every packet. Both the decoder and [`Packet::id`] take their
+/// ids from here, so this is the only place where packets are numbered.
+pub const PACKET_IDS: &[(PacketKind, PlayState, Direction, i32)] = &[
+    // Handshake
+    (
+        PacketKind::C00Handshake,
+        PlayState::Handshake,
+        Direction::Serverbound,
+        0x00,
+    ),
+    // Status
+    (
+        PacketKind::C00StatusRequest,
+        PlayState::Status,
+        Direction::Serverbound,
+        0x00,
+    ),
+    (
+        PacketKind::C01StatusPing,
+        PlayState::Status,
+        Direction::Serverbound,
+        0x01,
+    ),
+    (
+        PacketKind::S00StatusResponse,
+        PlayState::Status,
+        Direction::Clientbound,
+        0x00,
+    ),
+    (
+        PacketKind::S01StatusPong,
+        PlayState::Status,
+        Direction::Clientbound,
+        0x01,
+    ),
+    // Login
+    (
+        PacketKind::C00LoginStart,
+        PlayState::Login,
+        Direction::Serverbound,
+        0x00,
+    ),
+    (
+        PacketKind::S00LoginDisconnect,
+        PlayState::Login,
+        Direction::Clientbound,
+        0x00,
+    ),
+    (
+        PacketKind::S02LoginSuccess,
+        PlayState::Login,
+        Direction::Clientbound,
+        0x02,
+    ),
+    (
+        PacketKind::S03LoginCompression,
+        PlayState::Login,
+        Direction::Clientbound,
+        0x03,
+    ),
+    // Play
+    (
+        PacketKind::C00KeepAlive,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x00,
+    ),
+    (
+        PacketKind::C01ChatMessage,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x01,
+    ),
+    (
+        PacketKind::C02UseEntity,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x02,
+    ),
+    (
+        PacketKind::C03Player,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x03,
+    ),
+    (
+        PacketKind::C04PlayerPos,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x04,
+    ),
+    (
+        PacketKind::C05PlayerRot,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x05,
+    ),
+    (
+        PacketKind::C06PlayerPosRot,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x06,
+    ),
+    (
+        PacketKind::C07PlayerDigging,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x07,
+    ),
+    (
+        PacketKind::C08PlayerBlockPlacement,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x08,
+    ),
+    (
+        PacketKind::C09HeldItemChange,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x09,
+    ),
+    (
+        PacketKind::C0AAnimation,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x0A,
+    ),
+    (
+        PacketKind::C0BEntityAction,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x0B,
+    ),
+    (
+        PacketKind::C10SetCreativeSlot,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x10,
+    ),
+    (
+        PacketKind::C13PlayerAbilities,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x13,
+    ),
+    (
+        PacketKind::C16ClientStatus,
+        PlayState::Play,
+        Direction::Serverbound,
+        0x16,
+    ),
+    (
+        PacketKind::S00KeepAlive,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x00,
+    ),
+    (
+        PacketKind::S01JoinGame,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x01,
+    ),
+    (
+        PacketKind::S02ChatMessage,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x02,
+    ),
+    (
+        PacketKind::S06UpdateHealth,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x06,
+    ),
+    (
+        PacketKind::S07Respawn,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x07,
+    ),
+    (
+        PacketKind::S08SetPlayerPosition,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x08,
+    ),
+    (
+        PacketKind::S0BAnimation,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x0B,
+    ),
+    (
+        PacketKind::S0ESpawnObject,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x0E,
+    ),
+    (
+        PacketKind::S0FSpawnMob,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x0F,
+    ),
+    (
+        PacketKind::S12EntityVelocity,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x12,
+    ),
+    (
+        PacketKind::S13DestroyEntities,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x13,
+    ),
+    (
+        PacketKind::S15EntityRelativeMove,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x15,
+    ),
+    (
+        PacketKind::S16EntityLook,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x16,
+    ),
+    (
+        PacketKind::S18EntityTeleport,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x18,
+    ),
+    (
+        PacketKind::S19EntityHeadLook,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x19,
+    ),
+    (
+        PacketKind::S1AEntityStatus,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x1A,
+    ),
+    (
+        PacketKind::S1CEntityMeta,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x1C,
+    ),
+    (
+        PacketKind::S21ChunkData,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x21,
+    ),
+    (
+        PacketKind::S21UnloadChunk,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x21,
+    ),
+    (
+        PacketKind::S22MultiBlockChange,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x22,
+    ),
+    (
+        PacketKind::S23BlockChange,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x23,
+    ),
+    (
+        PacketKind::S26MapChunkBulk,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x26,
+    ),
+    (
+        PacketKind::S29NamedSoundEffect,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x29,
+    ),
+    (
+        PacketKind::S2AParticle,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x2A,
+    ),
+    (
+        PacketKind::S2BChangeGameState,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x2B,
+    ),
+    (
+        PacketKind::S2FSetSlot,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x2F,
+    ),
+    (
+        PacketKind::S30WindowItems,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x30,
+    ),
+    (
+        PacketKind::S38PlayerListItem,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x38,
+    ),
+    (
+        PacketKind::S39PlayerAbilities,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x39,
+    ),
+    (
+        PacketKind::S3ETeams,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x3E,
+    ),
+    (
+        PacketKind::S40Disconnect,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x40,
+    ),
+    (
+        PacketKind::S41ServerDifficulty,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x41,
+    ),
+    (
+        PacketKind::S44WorldBorder,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x44,
+    ),
+    (
+        PacketKind::S45Title,
+        PlayState::Play,
+        Direction::Clientbound,
+        0x45,
+    ),
+];
+
+/// Checks [`PACKET_IDS`] for mistakes: no packet may be listed twice and no two
+/// serverbound packets of a state may share an id, as the decoder couldn't tell them
+/// apart. Clientbound packets may share one, e.g. unloading a chunk is a chunk data packet.
+pub fn check_packet_ids() -> Result<(), String> {
+    for (i, (kind, state, direction, id)) in PACKET_IDS.iter().enumerate() {
+        for (other, other_state, other_direction, other_id) in &PACKET_IDS[i + 1..] {
+            if kind == other {
+                return Err(format!("Packet {:?} is listed twice", kind));
+            }
+            if *direction == Direction::Serverbound
+                && direction == other_direction
+                && state == other_state
+                && id == other_id
+            {
+                return Err(format!(
+                    "Packets {:?} and {:?} share the id {:#04x}",
+                    kind, other, id
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_KINDS: [PacketKind; 58] = [
+        PacketKind::C00Handshake,
+        PacketKind::C00StatusRequest,
+        PacketKind::C01StatusPing,
+        PacketKind::S00StatusResponse,
+        PacketKind::S01StatusPong,
+        PacketKind::C00LoginStart,
+        PacketKind::S00LoginDisconnect,
+        PacketKind::S02LoginSuccess,
+        PacketKind::S03LoginCompression,
+        PacketKind::C00KeepAlive,
+        PacketKind::C01ChatMessage,
+        PacketKind::C02UseEntity,
+        PacketKind::C03Player,
+        PacketKind::C04PlayerPos,
+        PacketKind::C05PlayerRot,
+        PacketKind::C06PlayerPosRot,
+        PacketKind::C07PlayerDigging,
+        PacketKind::C08PlayerBlockPlacement,
+        PacketKind::C09HeldItemChange,
+        PacketKind::C0AAnimation,
+        PacketKind::C0BEntityAction,
+        PacketKind::C10SetCreativeSlot,
+        PacketKind::C13PlayerAbilities,
+        PacketKind::C16ClientStatus,
+        PacketKind::S00KeepAlive,
+        PacketKind::S01JoinGame,
+        PacketKind::S02ChatMessage,
+        PacketKind::S06UpdateHealth,
+        PacketKind::S07Respawn,
+        PacketKind::S08SetPlayerPosition,
+        PacketKind::S0BAnimation,
+        PacketKind::S0ESpawnObject,
+        PacketKind::S0FSpawnMob,
+        PacketKind::S12EntityVelocity,
+        PacketKind::S13DestroyEntities,
+        PacketKind::S15EntityRelativeMove,
+        PacketKind::S16EntityLook,
+        PacketKind::S18EntityTeleport,
+        PacketKind::S19EntityHeadLook,
+        PacketKind::S1AEntityStatus,
+        PacketKind::S1CEntityMeta,
+        PacketKind::S21ChunkData,
+        PacketKind::S21UnloadChunk,
+        PacketKind::S22MultiBlockChange,
+        PacketKind::S23BlockChange,
+        PacketKind::S26MapChunkBulk,
+        PacketKind::S29NamedSoundEffect,
+        PacketKind::S2AParticle,
+        PacketKind::S2BChangeGameState,
+        PacketKind::S2FSetSlot,
+        PacketKind::S30WindowItems,
+        PacketKind::S38PlayerListItem,
+        PacketKind::S39PlayerAbilities,
+        PacketKind::S3ETeams,
+        PacketKind::S40Disconnect,
+        PacketKind::S41ServerDifficulty,
+        PacketKind::S44WorldBorder,
+        PacketKind::S45Title,
+    ];
+
+    /// Packets are named after their direction. The match is exhaustive, so a new packet
+    /// kind doesn't compile until it is added here and to `ALL_KINDS`.
+    fn named_direction(kind: PacketKind) -> Direction {
+        match kind {
+            PacketKind::C00Handshake
+            | PacketKind::C00StatusRequest
+            | PacketKind::C01StatusPing
+            | PacketKind::C00LoginStart
+            | PacketKind::C00KeepAlive
+            | PacketKind::C01ChatMessage
+            | PacketKind::C02UseEntity
+            | PacketKind::C03Player
+            | PacketKind::C04PlayerPos
+            | PacketKind::C05PlayerRot
+            | PacketKind::C06PlayerPosRot
+            | PacketKind::C07PlayerDigging
+            | PacketKind::C08PlayerBlockPlacement
+            | PacketKind::C09HeldItemChange
+            | PacketKind::C0AAnimation
+            | PacketKind::C0BEntityAction
+            | PacketKind::C10SetCreativeSlot
+            | PacketKind::C13PlayerAbilities
+            | PacketKind::C16ClientStatus => Direction::Serverbound,
+            PacketKind::S00StatusResponse
+            | PacketKind::S01StatusPong
+            | PacketKind::S00LoginDisconnect
+            | PacketKind::S02LoginSuccess
+            | PacketKind::S03LoginCompression
+            | PacketKind::S00KeepAlive
+            | PacketKind::S01JoinGame
+            | PacketKind::S02ChatMessage
+            | PacketKind::S06UpdateHealth
+            | PacketKind::S07Respawn
+            | PacketKind::S08SetPlayerPosition
+            | PacketKind::S0BAnimation
+            | PacketKind::S0ESpawnObject
+            | PacketKind::S0FSpawnMob
+            | PacketKind::S12EntityVelocity
+            | PacketKind::S13DestroyEntities
+            | PacketKind::S15EntityRelativeMove
+            | PacketKind::S16EntityLook
+            | PacketKind::S18EntityTeleport
+            | PacketKind::S19EntityHeadLook
+            | PacketKind::S1AEntityStatus
+            | PacketKind::S1CEntityMeta
+            | PacketKind::S21ChunkData
+            | PacketKind::S21UnloadChunk
+            | PacketKind::S22MultiBlockChange
+            | PacketKind::S23BlockChange
+            | PacketKind::S26MapChunkBulk
+            | PacketKind::S29NamedSoundEffect
+            | PacketKind::S2AParticle
+            | PacketKind::S2BChangeGameState
+            | PacketKind::S2FSetSlot
+            | PacketKind::S30WindowItems
+            | PacketKind::S38PlayerListItem
+            | PacketKind::S39PlayerAbilities
+            | PacketKind::S3ETeams
+            | PacketKind::S40Disconnect
+            | PacketKind::S41ServerDifficulty
+            | PacketKind::S44WorldBorder
+            | PacketKind::S45Title => Direction::Clientbound,
+        }
+    }
+
+    #[test]
+    fn packet_ids_are_valid() {
+        assert_eq!(check_packet_ids(), Ok(()));
+    }
+
+    #[test]
+    fn every_packet_kind_has_an_id() {
+        assert_eq!(PACKET_IDS.len(), ALL_KINDS.len());
+        for kind in ALL_KINDS {
+            let (_, _, direction, _) = PACKET_IDS
+                .iter()
+                .find(|(k, ..)| *k == kind)
+                .unwrap_or_else(|| panic!("{:?} has no id", kind));
+            assert_eq!(*direction, named_direction(kind), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn ids_map_back_to_their_packets() {
+        for &(kind, state, direction, id) in PACKET_IDS {
+            let found = PacketKind::lookup(state, direction, id).unwrap();
+            match direction {
+                Direction::Serverbound => assert_eq!(found, kind),
+                // Clientbound packets may share an id, see `check_packet_ids`
+                Direction::Clientbound => {
+                    assert!(PACKET_IDS.contains(&(found, state, direction, id)))
+                }
+            }
         }
     }
 }
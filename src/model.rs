@@ -1,11 +1,21 @@
-use bytes::{Buf, BytesMut};
+use std::{collections::VecDeque, str::FromStr};
+
+use bytes::{Buf, BufMut, BytesMut};
 use rand::Rng;
+use serde::de::{self, Deserialize, Deserializer};
 use serde_derive::Deserialize;
 use uuid::Uuid;
 
-use crate::world::{BlockPos, ChunkPos};
+use crate::{
+    block_state,
+    mc::proto::{EntityMetaEntry, Packet, TeamInfo, WorldBorderAction},
+    world::{is_liquid, is_solid, registry::registry, BlockPos, ChunkPos, World},
+};
+
+const PLAYER_DATA_MAGIC: &[u8; 4] = b"MCRP";
+const PLAYER_DATA_VERSION: u8 = 2;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GameMode {
     Survival,
     Creative,
@@ -13,6 +23,76 @@ pub enum GameMode {
     Spectator,
 }
 
+/// Accepts the id or the (case insensitive) name of a game mode
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "0" | "survival" => Ok(GameMode::Survival),
+            "1" | "creative" => Ok(GameMode::Creative),
+            "2" | "adventure" => Ok(GameMode::Adventure),
+            "3" | "spectator" => Ok(GameMode::Spectator),
+            _ => Err(format!("{} is not a valid game mode", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for GameMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_id_or_name(deserializer)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    Peaceful,
+    Easy,
+    Normal,
+    Hard,
+}
+
+/// Accepts the id or the (case insensitive) name of a difficulty
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "0" | "peaceful" => Ok(Difficulty::Peaceful),
+            "1" | "easy" => Ok(Difficulty::Easy),
+            "2" | "normal" => Ok(Difficulty::Normal),
+            "3" | "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("{} is not a valid difficulty", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Difficulty {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_id_or_name(deserializer)
+    }
+}
+
+/// Deserializes an enum given either as its numeric id or as its name
+fn deserialize_id_or_name<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Id(u8),
+        Name(String),
+    }
+
+    let text = match Repr::deserialize(deserializer)? {
+        Repr::Id(id) => id.to_string(),
+        Repr::Name(name) => name,
+    };
+    text.parse().map_err(de::Error::custom)
+}
+
 impl From<u8> for GameMode {
     fn from(val: u8) -> Self {
         match val {
@@ -60,8 +140,83 @@ impl ItemStack {
         self.id != -1
     }
 
-    pub fn is_block(&self) -> bool {
-        self.id <= 255
+    /// The block state the item places, with the damage as the block meta. Damage that
+    /// doesn't fit into the meta places nothing.
+    pub fn block_state(&self) -> Option<u16> {
+        if self.damage > 15 {
+            return None;
+        }
+        registry()
+            .block_for_item(self.id)
+            .map(|id| block_state!(id, self.damage))
+    }
+
+    /// How many of this item fit into one slot, see `config/items.toml`
+    pub fn max_stack_size(&self) -> u8 {
+        registry().max_stack(self.id)
+    }
+
+    /// Food points and saturation modifier restored when eating this item
+    pub fn food_value(&self) -> Option<(i32, f32)> {
+        match self.id {
+            260 => Some((4, 0.3)),
+            282 => Some((6, 0.6)),
+            297 => Some((5, 0.6)),
+            319 => Some((3, 0.3)),
+            320 => Some((8, 0.8)),
+            322 => Some((4, 1.2)),
+            349 => Some((2, 0.1)),
+            350 => Some((5, 0.6)),
+            357 => Some((2, 0.1)),
+            360 => Some((2, 0.3)),
+            363 => Some((3, 0.3)),
+            364 => Some((8, 0.8)),
+            365 => Some((2, 0.3)),
+            366 => Some((6, 0.6)),
+            367 => Some((4, 0.1)),
+            391 => Some((3, 0.6)),
+            392 => Some((1, 0.3)),
+            393 => Some((5, 0.6)),
+            394 => Some((2, 0.3)),
+            396 => Some((6, 1.2)),
+            400 => Some((8, 0.3)),
+            411 => Some((3, 0.3)),
+            412 => Some((5, 0.6)),
+            413 => Some((10, 0.6)),
+            423 => Some((2, 0.3)),
+            424 => Some((6, 0.8)),
+            _ => None,
+        }
+    }
+
+    /// Damage dealt when hitting an entity with this item, or with the bare hand
+    pub fn attack_damage(&self) -> f32 {
+        match self.id {
+            276 => 8.0,
+            267 | 279 => 7.0,
+            272 | 258 => 6.0,
+            268 | 283 | 275 => 5.0,
+            271 | 286 => 4.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// What hurt a player, used for the death message
+#[derive(Debug, Clone)]
+pub enum DamageCause {
+    Fall,
+    Starvation,
+    Player(String),
+}
+
+impl DamageCause {
+    pub fn death_message(&self, username: &str) -> String {
+        match self {
+            DamageCause::Fall => format!("{} fell from a high place", username),
+            DamageCause::Starvation => format!("{} starved to death", username),
+            DamageCause::Player(attacker) => format!("{} was slain by {}", username, attacker),
+        }
     }
 }
 
@@ -72,6 +227,15 @@ pub struct Vec3d {
     pub z: f64,
 }
 
+impl Vec3d {
+    pub fn distance_to(&self, other: Vec3d) -> f64 {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        let dz = other.z - self.z;
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Vec2f {
     pub x: f32,
@@ -100,10 +264,57 @@ pub trait Entity {
     fn set_rot(&mut self, rot: Vec2f);
 }
 
+/// Bounded history of bulk block edits, storing the previous block states of each edit
+#[derive(Default)]
+pub struct EditHistory {
+    edits: VecDeque<Vec<(BlockPos, u16)>>,
+    num_blocks: usize,
+}
+
+impl EditHistory {
+    const MAX_EDITS: usize = 16;
+    const MAX_BLOCKS: usize = 262144;
+
+    pub fn new() -> EditHistory {
+        Self::default()
+    }
+
+    /// Adds an edit. Returns false if it has more blocks than the whole history may keep,
+    /// in which case it can't be undone.
+    pub fn push(&mut self, previous: Vec<(BlockPos, u16)>) -> bool {
+        if previous.len() > Self::MAX_BLOCKS {
+            return false;
+        }
+        if previous.is_empty() {
+            return true;
+        }
+
+        self.num_blocks += previous.len();
+        self.edits.push_back(previous);
+
+        // Drop the oldest edits until we are within limits again
+        while self.edits.len() > Self::MAX_EDITS || self.num_blocks > Self::MAX_BLOCKS {
+            match self.edits.pop_front() {
+                Some(edit) => self.num_blocks -= edit.len(),
+                None => break,
+            }
+        }
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<(BlockPos, u16)>> {
+        let edit = self.edits.pop_back()?;
+        self.num_blocks -= edit.len();
+        Some(edit)
+    }
+}
+
 pub struct Player {
     pub eid: i32,
     pub uuid: Uuid,
     pub username: String,
+    /// Nickname shown instead of the username in chat and the player list
+    pub display_name: Option<String>,
     pub position: Vec3d,
     pub rotation: Vec2f,
     pub game_mode: GameMode,
@@ -111,14 +322,141 @@ pub struct Player {
     pub walk_speed: f32,
     pub inventory: Vec<ItemStack>,
     pub selected_slot: i16,
+    pub edit_history: EditHistory,
+    pub permission_level: u8,
+    pub health: f32,
+    pub food: i32,
+    pub saturation: f32,
+    pub exhaustion: f32,
+    pub food_timer: u32,
+    pub eating_ticks: u32,
+    pub flying: bool,
+    pub sprinting: bool,
+    pub sneaking: bool,
+    pub on_ground: bool,
+    pub fall_start: Option<f64>,
+}
+
+/// State of a player that is kept when they log out
+#[derive(Debug, Clone)]
+pub struct PlayerData {
+    pub game_mode: GameMode,
+    pub position: Vec3d,
+    pub rotation: Vec2f,
+    pub health: f32,
+    pub food: i32,
+    pub saturation: f32,
+    pub inventory: Vec<ItemStack>,
+    pub display_name: Option<String>,
+}
+
+impl PlayerData {
+    /// Binary layout: magic, version, game mode, position as f64, rotation, health, food and
+    /// saturation, followed by the slot count as u16 and id, count and damage of each slot.
+    /// Since version 2 the nickname follows, as its length in bytes as u16 and its UTF-8,
+    /// with a length of 0 for none.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(52 + self.inventory.len() * 5);
+        buf.put_slice(PLAYER_DATA_MAGIC);
+        buf.put_u8(PLAYER_DATA_VERSION);
+        buf.put_u8(self.game_mode as u8);
+        buf.put_f64(self.position.x);
+        buf.put_f64(self.position.y);
+        buf.put_f64(self.position.z);
+        buf.put_f32(self.rotation.x);
+        buf.put_f32(self.rotation.y);
+        buf.put_f32(self.health);
+        buf.put_i32(self.food);
+        buf.put_f32(self.saturation);
+        buf.put_u16(self.inventory.len() as u16);
+        for stack in &self.inventory {
+            buf.put_i16(stack.id);
+            buf.put_u8(stack.count);
+            buf.put_u16(stack.damage);
+        }
+        let display_name = self.display_name.as_deref().unwrap_or_default();
+        buf.put_u16(display_name.len() as u16);
+        buf.put_slice(display_name.as_bytes());
+        buf.to_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<PlayerData, String> {
+        let mut buf = data;
+        if buf.remaining() < 52 || &buf[0..4] != PLAYER_DATA_MAGIC {
+            return Err("Not a player data file".to_string());
+        }
+        buf.advance(4);
+
+        let version = buf.get_u8();
+        if version == 0 || version > PLAYER_DATA_VERSION {
+            return Err(format!("Unsupported player data version {}", version));
+        }
+        let game_mode = buf.get_u8();
+        if game_mode > 3 {
+            return Err(format!("Invalid game mode {}", game_mode));
+        }
+
+        let mut data = PlayerData {
+            game_mode: GameMode::from(game_mode),
+            position: Vec3d {
+                x: buf.get_f64(),
+                y: buf.get_f64(),
+                z: buf.get_f64(),
+            },
+            rotation: Vec2f {
+                x: buf.get_f32(),
+                y: buf.get_f32(),
+            },
+            health: buf.get_f32(),
+            food: buf.get_i32(),
+            saturation: buf.get_f32(),
+            inventory: Vec::new(),
+            display_name: None,
+        };
+
+        let num_slots = buf.get_u16() as usize;
+        if num_slots != 45 || buf.remaining() < num_slots * 5 {
+            return Err("Player data file is truncated".to_string());
+        }
+        for _ in 0..num_slots {
+            data.inventory.push(ItemStack {
+                id: buf.get_i16(),
+                count: buf.get_u8(),
+                damage: buf.get_u16(),
+            });
+        }
+
+        if version >= 2 {
+            if buf.remaining() < 2 {
+                return Err("Player data file is truncated".to_string());
+            }
+            let len = buf.get_u16() as usize;
+            if buf.remaining() != len {
+                return Err("Player data file is truncated".to_string());
+            }
+            let display_name = String::from_utf8(buf.to_vec())
+                .map_err(|_| "Player data has an invalid nickname".to_string())?;
+            data.display_name = Some(display_name).filter(|name| !name.is_empty());
+        } else if buf.has_remaining() {
+            return Err("Player data file is truncated".to_string());
+        }
+
+        Ok(data)
+    }
 }
 
 impl Player {
+    const FOOD_TICK_INTERVAL: u32 = 80;
+    const PEACEFUL_REGEN_INTERVAL: u32 = 20;
+    const WIDTH: f64 = 0.6;
+    const HEIGHT: f64 = 1.8;
+
     pub fn new(eid: i32, game_mode: GameMode) -> Player {
         Player {
             eid,
             uuid: Uuid::from_u128(rand::thread_rng().gen()),
             username: String::new(),
+            display_name: None,
             position: Default::default(),
             rotation: Default::default(),
             game_mode,
@@ -126,11 +464,204 @@ impl Player {
             walk_speed: 0.1,
             inventory: vec![ItemStack::default(); 45],
             selected_slot: 0,
+            edit_history: EditHistory::new(),
+            permission_level: 0,
+            health: 20.0,
+            food: 20,
+            saturation: 5.0,
+            exhaustion: 0.0,
+            food_timer: 0,
+            eating_ticks: 0,
+            flying: false,
+            sprinting: false,
+            sneaking: false,
+            on_ground: false,
+            fall_start: None,
         }
     }
 
-    pub fn is_logged_in(&self) -> bool {
-        !self.username.is_empty()
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.username)
+    }
+
+    pub fn eat(&mut self, food: i32, saturation_modifier: f32) {
+        self.food = (self.food + food).min(20);
+        self.saturation =
+            (self.saturation + food as f32 * saturation_modifier * 2.0).min(self.food as f32);
+    }
+
+    pub fn add_exhaustion(&mut self, amount: f32) {
+        self.exhaustion = (self.exhaustion + amount).min(40.0);
+    }
+
+    /// Adds exhaustion for the distance walked since the previous position and for jumping
+    pub fn track_movement(&mut self, previous: Vec3d, on_ground: bool) {
+        let dx = self.position.x - previous.x;
+        let dz = self.position.z - previous.z;
+        let dist = (dx * dx + dz * dz).sqrt() as f32;
+
+        // Ignore teleports
+        if dist < 5.0 {
+            let cost = if self.sprinting { 0.1 } else { 0.01 };
+            self.add_exhaustion(cost * dist);
+        }
+
+        if self.on_ground && !on_ground && self.position.y > previous.y {
+            let cost = if self.sprinting { 0.8 } else { 0.2 };
+            self.add_exhaustion(cost);
+        }
+        self.on_ground = on_ground;
+    }
+
+    /// Advances hunger by one tick and returns the health to add, which is negative when starving
+    pub fn tick_hunger(&mut self, difficulty: Difficulty) -> f32 {
+        if !self.is_vulnerable() || self.is_dead() {
+            return 0.0;
+        }
+
+        // Peaceful refills food and heals over time
+        if difficulty == Difficulty::Peaceful {
+            self.exhaustion = 0.0;
+            self.food_timer += 1;
+            if self.food_timer >= Self::PEACEFUL_REGEN_INTERVAL {
+                self.food_timer = 0;
+                self.food = (self.food + 1).min(20);
+                if self.health < 20.0 {
+                    return 1.0;
+                }
+            }
+            return 0.0;
+        }
+
+        if self.exhaustion >= 4.0 {
+            self.exhaustion -= 4.0;
+            if self.saturation > 0.0 {
+                self.saturation = (self.saturation - 1.0).max(0.0);
+            } else {
+                self.food = (self.food - 1).max(0);
+            }
+        }
+
+        if self.food >= 18 && self.health < 20.0 {
+            self.food_timer += 1;
+            if self.food_timer >= Self::FOOD_TICK_INTERVAL {
+                self.food_timer = 0;
+                self.add_exhaustion(3.0);
+                return 1.0;
+            }
+        } else if self.food == 0 {
+            self.food_timer += 1;
+            if self.food_timer >= Self::FOOD_TICK_INTERVAL {
+                self.food_timer = 0;
+
+                // Starvation gets more deadly with higher difficulty
+                let starves = match difficulty {
+                    Difficulty::Peaceful => false,
+                    Difficulty::Easy => self.health > 10.0,
+                    Difficulty::Normal => self.health > 1.0,
+                    Difficulty::Hard => true,
+                };
+                if starves {
+                    return -1.0;
+                }
+            }
+        } else {
+            self.food_timer = 0;
+        }
+
+        0.0
+    }
+
+    /// Flags for the entity status metadata that other players see
+    pub fn status_flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.sneaking {
+            flags |= EntityMetaEntry::STATUS_CROUCHED;
+        }
+        if self.sprinting {
+            flags |= EntityMetaEntry::STATUS_SPRINTING;
+        }
+        if self.eating_ticks > 0 {
+            flags |= EntityMetaEntry::STATUS_EATING;
+        }
+        flags
+    }
+
+    /// The UUID that vanilla servers in offline mode give a player, so it stays the
+    /// same across logins
+    pub fn offline_uuid(username: &str) -> Uuid {
+        let mut hash = md5::compute(format!("OfflinePlayer:{}", username)).0;
+        hash[6] = hash[6] & 0x0f | 0x30; // Version 3
+        hash[8] = hash[8] & 0x3f | 0x80; // IETF variant
+        Uuid::from_bytes(hash)
+    }
+
+    pub fn to_data(&self) -> PlayerData {
+        PlayerData {
+            game_mode: self.game_mode,
+            position: self.position,
+            rotation: self.rotation,
+            health: self.health,
+            food: self.food,
+            saturation: self.saturation,
+            inventory: self.inventory.clone(),
+            display_name: self.display_name.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, data: &PlayerData) {
+        self.game_mode = data.game_mode;
+        self.display_name = data.display_name.clone();
+        self.inventory = data.inventory.clone();
+        // Saves from before stack sizes were enforced may hold too many
+        for stack in &mut self.inventory {
+            stack.count = stack.count.min(stack.max_stack_size());
+        }
+
+        // Players that logged out while dead start over at the spawn
+        if data.health > 0.0 {
+            self.position = data.position;
+            self.rotation = data.rotation;
+            self.health = data.health;
+            self.food = data.food;
+            self.saturation = data.saturation;
+        }
+    }
+
+    /// Whether the player can be hurt in their current game mode
+    pub fn is_vulnerable(&self) -> bool {
+        matches!(self.game_mode, GameMode::Survival | GameMode::Adventure)
+    }
+
+    /// Tracks the highest point reached while airborne and returns the distance fallen on landing
+    pub fn update_fall(&mut self, on_ground: bool, in_liquid: bool) -> f64 {
+        if self.flying || in_liquid || !self.is_vulnerable() {
+            self.fall_start = None;
+            return 0.0;
+        }
+
+        let y = self.position.y;
+        if on_ground {
+            return self.fall_start.take().map_or(0.0, |start| start - y);
+        }
+
+        self.fall_start = Some(self.fall_start.map_or(y, |start| start.max(y)));
+        0.0
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+    /// Whether the bounding box of a player standing at the position overlaps the block
+    pub fn hitbox_intersects(position: Vec3d, block: BlockPos) -> bool {
+        let half_width = Self::WIDTH / 2.0;
+        position.x + half_width > block.x as f64
+            && position.x - half_width < (block.x + 1) as f64
+            && position.y + Self::HEIGHT > block.y as f64
+            && position.y < (block.y + 1) as f64
+            && position.z + half_width > block.z as f64
+            && position.z - half_width < (block.z + 1) as f64
     }
 
     pub fn item_stack_at(&mut self, id: i16) -> &mut ItemStack {
@@ -163,3 +694,684 @@ impl Entity for Player {
         self.rotation = rot;
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MobKind {
+    Pig = 90,
+    Sheep = 91,
+    Cow = 92,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mob {
+    pub eid: i32,
+    pub kind: MobKind,
+    pub position: Vec3d,
+    pub rotation: Vec2f,
+    pub target: Option<Vec3d>,
+}
+
+impl Mob {
+    const WANDER_SPEED: f64 = 0.2;
+    const WANDER_RANGE: f64 = 8.0;
+    const WANDER_CHANCE: u32 = 8;
+
+    pub fn new(eid: i32, kind: MobKind, position: Vec3d) -> Mob {
+        Mob {
+            eid,
+            kind,
+            position,
+            rotation: Vec2f {
+                x: rand::thread_rng().gen_range(0.0..360.0),
+                y: 0.0,
+            },
+            target: None,
+        }
+    }
+
+    /// Advances the wandering AI by one step and returns the packets describing the movement
+    pub fn wander(&mut self, world: &World) -> Vec<Packet> {
+        let target = match self.target {
+            Some(target) => target,
+            None => {
+                let mut rng = rand::thread_rng();
+                if rng.gen_range(0..Self::WANDER_CHANCE) != 0 {
+                    return Vec::new();
+                }
+
+                let target = Vec3d {
+                    x: self.position.x + rng.gen_range(-Self::WANDER_RANGE..Self::WANDER_RANGE),
+                    y: self.position.y,
+                    z: self.position.z + rng.gen_range(-Self::WANDER_RANGE..Self::WANDER_RANGE),
+                };
+                self.target = Some(target);
+                return self.look_towards(target);
+            }
+        };
+
+        let dx = target.x - self.position.x;
+        let dz = target.z - self.position.z;
+        let dist = (dx * dx + dz * dz).sqrt();
+        if dist < Self::WANDER_SPEED {
+            self.target = None;
+            return Vec::new();
+        }
+
+        let next_x = self.position.x + dx / dist * Self::WANDER_SPEED;
+        let next_z = self.position.z + dz / dist * Self::WANDER_SPEED;
+        let next_y = match Self::step_height(world, next_x, self.position.y, next_z) {
+            Some(y) => y,
+            None => {
+                // Blocked by a wall, a cliff or water
+                self.target = None;
+                return Vec::new();
+            }
+        };
+
+        let next = Vec3d {
+            x: next_x,
+            y: next_y,
+            z: next_z,
+        };
+        let packet = self.move_packet(next);
+        self.position = next;
+        vec![packet]
+    }
+
+    fn look_towards(&mut self, target: Vec3d) -> Vec<Packet> {
+        let dx = target.x - self.position.x;
+        let dz = target.z - self.position.z;
+        self.rotation.x = (-dx).atan2(dz).to_degrees() as f32;
+        vec![
+            Packet::S16EntityLook {
+                entity_id: self.eid,
+                yaw: self.rotation.x,
+                pitch: self.rotation.y,
+                on_ground: true,
+            },
+            Packet::S19EntityHeadLook {
+                entity_id: self.eid,
+                head_yaw: self.rotation.x,
+            },
+        ]
+    }
+
+    /// The height the mob would stand at after walking to x/z, allowing a step of one block
+    fn step_height(world: &World, x: f64, y: f64, z: f64) -> Option<f64> {
+        let bx = x.floor() as i32;
+        let by = y.floor() as i32;
+        let bz = z.floor() as i32;
+        let solid = |y: i32| is_solid(world.get_block(bx, y, bz));
+        let free = |y: i32| {
+            let block_state = world.get_block(bx, y, bz);
+            !is_solid(block_state) && !is_liquid(block_state)
+        };
+
+        if solid(by) {
+            if free(by + 1) && free(by + 2) {
+                return Some((by + 1) as f64);
+            }
+            return None;
+        }
+
+        if !free(by) || !free(by + 1) {
+            return None;
+        }
+
+        if solid(by - 1) {
+            Some(by as f64)
+        } else if free(by - 1) && solid(by - 2) {
+            Some((by - 1) as f64)
+        } else {
+            None
+        }
+    }
+
+    /// Relative moves are sent in fixed point, so we compute the delta from the positions as
+    /// the client sees them to avoid drift. Larger distances need a teleport.
+    fn move_packet(&self, next: Vec3d) -> Packet {
+        let fixed = |v: f64| (v * 32.0).floor();
+        let dx = fixed(next.x) - fixed(self.position.x);
+        let dy = fixed(next.y) - fixed(self.position.y);
+        let dz = fixed(next.z) - fixed(self.position.z);
+        if dx.abs() < 128.0 && dy.abs() < 128.0 && dz.abs() < 128.0 {
+            Packet::S15EntityRelativeMove {
+                entity_id: self.eid,
+                dx: dx / 32.0,
+                dy: dy / 32.0,
+                dz: dz / 32.0,
+                on_ground: true,
+            }
+        } else {
+            Packet::S18EntityTeleport {
+                entity_id: self.eid,
+                x: next.x,
+                y: next.y,
+                z: next.z,
+                yaw: self.rotation.x,
+                pitch: self.rotation.y,
+                on_ground: true,
+            }
+        }
+    }
+
+    pub fn spawn_packet(&self) -> Packet {
+        Packet::S0FSpawnMob {
+            entity_id: self.eid,
+            kind: self.kind as u8,
+            x: self.position.x,
+            y: self.position.y,
+            z: self.position.z,
+            yaw: self.rotation.x,
+            pitch: self.rotation.y,
+            head_pitch: self.rotation.y,
+            velocity: Vec3d::default(),
+            metadata: Vec::new(),
+        }
+    }
+}
+
+impl Entity for Mob {
+    fn id(&self) -> i32 {
+        self.eid
+    }
+
+    fn pos(&self) -> Vec3d {
+        self.position
+    }
+
+    fn set_pos(&mut self, pos: Vec3d) {
+        self.position = pos
+    }
+
+    fn rot(&self) -> Vec2f {
+        self.rotation
+    }
+
+    fn set_rot(&mut self, rot: Vec2f) {
+        self.rotation = rot;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectileKind {
+    Snowball = 61,
+    Egg = 62,
+    EnderPearl = 65,
+}
+
+impl ProjectileKind {
+    /// The projectile thrown by using an item, if it is a throwable
+    pub fn from_item(id: i16) -> Option<ProjectileKind> {
+        match id {
+            332 => Some(ProjectileKind::Snowball),
+            344 => Some(ProjectileKind::Egg),
+            368 => Some(ProjectileKind::EnderPearl),
+            _ => None,
+        }
+    }
+}
+
+/// A thrown item flying through the world. The client simulates the flight itself from the
+/// initial velocity, the server does the same to find out where it lands.
+#[derive(Debug, Clone)]
+pub struct Projectile {
+    pub eid: i32,
+    pub kind: ProjectileKind,
+    pub thrower: i32,
+    pub position: Vec3d,
+    pub velocity: Vec3d,
+    pub age: u32,
+}
+
+impl Projectile {
+    const SPEED: f64 = 1.5;
+    const GRAVITY: f64 = 0.03;
+    const DRAG: f64 = 0.99;
+    /// Projectiles that never hit anything, e.g. because they left the loaded area, are
+    /// removed after a minute
+    const MAX_AGE: u32 = 1200;
+    /// Longest distance moved between two collision checks
+    const COLLISION_STEP: f64 = 0.25;
+
+    /// Creates a projectile thrown from the eye position in the direction the thrower looks
+    pub fn thrown(
+        eid: i32,
+        kind: ProjectileKind,
+        thrower: i32,
+        eye: Vec3d,
+        rotation: Vec2f,
+    ) -> Projectile {
+        let yaw = (rotation.x as f64).to_radians();
+        let pitch = (rotation.y as f64).to_radians();
+        Projectile {
+            eid,
+            kind,
+            thrower,
+            position: Vec3d {
+                x: eye.x - yaw.cos() * 0.16,
+                y: eye.y - 0.1,
+                z: eye.z - yaw.sin() * 0.16,
+            },
+            velocity: Vec3d {
+                x: -yaw.sin() * pitch.cos() * Self::SPEED,
+                y: -pitch.sin() * Self::SPEED,
+                z: yaw.cos() * pitch.cos() * Self::SPEED,
+            },
+            age: 0,
+        }
+    }
+
+    pub fn spawn_packets(&self) -> Vec<Packet> {
+        vec![
+            Packet::S0ESpawnObject {
+                entity_id: self.eid,
+                kind: self.kind as u8,
+                x: self.position.x as f32,
+                y: self.position.y as f32,
+                z: self.position.z as f32,
+                pitch: 0.0,
+                yaw: 0.0,
+                data: 0,
+            },
+            Packet::S12EntityVelocity {
+                entity_id: self.eid,
+                velocity: self.velocity,
+            },
+        ]
+    }
+
+    /// Moves the projectile by one tick. Returns the last free position before the block it
+    /// hit, if it hit one.
+    pub fn tick(&mut self, world: &World) -> Option<Vec3d> {
+        self.age += 1;
+
+        let distance = Vec3d::default().distance_to(self.velocity);
+        let steps = (distance / Self::COLLISION_STEP).ceil().max(1.0) as u32;
+        for _ in 0..steps {
+            let next = Vec3d {
+                x: self.position.x + self.velocity.x / steps as f64,
+                y: self.position.y + self.velocity.y / steps as f64,
+                z: self.position.z + self.velocity.z / steps as f64,
+            };
+            let block_pos = BlockPos::from_pos(next.x, next.y, next.z);
+            if is_solid(world.get_block(block_pos.x, block_pos.y, block_pos.z)) {
+                return Some(self.position);
+            }
+            self.position = next;
+        }
+
+        self.velocity.x *= Self::DRAG;
+        self.velocity.y = self.velocity.y * Self::DRAG - Self::GRAVITY;
+        self.velocity.z *= Self::DRAG;
+        None
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.age >= Self::MAX_AGE || self.position.y < 0.0
+    }
+}
+
+impl Entity for Projectile {
+    fn id(&self) -> i32 {
+        self.eid
+    }
+
+    fn pos(&self) -> Vec3d {
+        self.position
+    }
+
+    fn set_pos(&mut self, pos: Vec3d) {
+        self.position = pos
+    }
+
+    fn rot(&self) -> Vec2f {
+        Vec2f::default()
+    }
+
+    fn set_rot(&mut self, _rot: Vec2f) {}
+}
+
+/// A dropped item stack lying in the world
+#[derive(Debug, Clone)]
+pub struct ItemEntity {
+    pub eid: i32,
+    pub stack: ItemStack,
+    pub position: Vec3d,
+    pub fall_speed: f64,
+    pub age: u32,
+}
+
+impl ItemEntity {
+    const GRAVITY: f64 = 0.04;
+    const DRAG: f64 = 0.98;
+    /// Items disappear after five minutes, like in vanilla
+    const MAX_AGE: u32 = 6000;
+
+    pub fn new(eid: i32, stack: ItemStack, position: Vec3d) -> ItemEntity {
+        ItemEntity {
+            eid,
+            stack,
+            position,
+            fall_speed: 0.0,
+            age: 0,
+        }
+    }
+
+    pub fn spawn_packets(&self) -> Vec<Packet> {
+        vec![
+            Packet::S0ESpawnObject {
+                entity_id: self.eid,
+                kind: 2,
+                x: self.position.x as f32,
+                y: self.position.y as f32,
+                z: self.position.z as f32,
+                pitch: 0.0,
+                yaw: 0.0,
+                data: 0,
+            },
+            Packet::S1CEntityMeta {
+                entity_id: self.eid,
+                entries: vec![EntityMetaEntry::item(self.stack)],
+            },
+        ]
+    }
+
+    /// Lets the item fall until it rests on a solid block. The client does the same on its
+    /// own, so no packets are needed.
+    pub fn tick(&mut self, world: &World) {
+        self.age += 1;
+
+        let below = BlockPos::from_pos(
+            self.position.x,
+            self.position.y - self.fall_speed - Self::GRAVITY,
+            self.position.z,
+        );
+        if is_solid(world.get_block(below.x, below.y, below.z)) {
+            self.position.y = self.position.y.min((below.y + 1) as f64);
+            self.fall_speed = 0.0;
+            return;
+        }
+
+        self.fall_speed = (self.fall_speed + Self::GRAVITY) * Self::DRAG;
+        self.position.y -= self.fall_speed;
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.age >= Self::MAX_AGE || self.position.y < 0.0
+    }
+}
+
+impl Entity for ItemEntity {
+    fn id(&self) -> i32 {
+        self.eid
+    }
+
+    fn pos(&self) -> Vec3d {
+        self.position
+    }
+
+    fn set_pos(&mut self, pos: Vec3d) {
+        self.position = pos
+    }
+
+    fn rot(&self) -> Vec2f {
+        Vec2f::default()
+    }
+
+    fn set_rot(&mut self, _rot: Vec2f) {}
+}
+
+/// What happened to an entity during a tick
+#[derive(Debug, Default)]
+pub struct EntityUpdate {
+    /// Packets that show the change to the players
+    pub packets: Vec<Packet>,
+    /// The entity is gone and has to be removed
+    pub removed: bool,
+    /// A player to teleport, with the destination
+    pub teleport: Option<(i32, Vec3d)>,
+}
+
+/// Any entity the server ticks, apart from players
+#[derive(Debug, Clone)]
+pub enum WorldEntity {
+    Item(ItemEntity),
+    Mob(Mob),
+    Projectile(Projectile),
+}
+
+impl WorldEntity {
+    fn inner(&self) -> &dyn Entity {
+        match self {
+            WorldEntity::Item(item) => item,
+            WorldEntity::Mob(mob) => mob,
+            WorldEntity::Projectile(projectile) => projectile,
+        }
+    }
+
+    pub fn spawn_packets(&self) -> Vec<Packet> {
+        match self {
+            WorldEntity::Item(item) => item.spawn_packets(),
+            WorldEntity::Mob(mob) => vec![mob.spawn_packet()],
+            WorldEntity::Projectile(projectile) => projectile.spawn_packets(),
+        }
+    }
+
+    pub fn tick(&mut self, world: &World) -> EntityUpdate {
+        match self {
+            WorldEntity::Item(item) => {
+                item.tick(world);
+                EntityUpdate {
+                    removed: item.is_expired(),
+                    ..Default::default()
+                }
+            }
+            WorldEntity::Mob(mob) => EntityUpdate {
+                packets: mob.wander(world),
+                ..Default::default()
+            },
+            WorldEntity::Projectile(projectile) => match projectile.tick(world) {
+                Some(position) => EntityUpdate {
+                    removed: true,
+                    teleport: (projectile.kind == ProjectileKind::EnderPearl)
+                        .then_some((projectile.thrower, position)),
+                    ..Default::default()
+                },
+                None => EntityUpdate {
+                    removed: projectile.is_expired(),
+                    ..Default::default()
+                },
+            },
+        }
+    }
+
+    /// Whether the entity is removed once no player is close enough to see it. Dropped
+    /// items stay until they expire.
+    pub fn despawns_out_of_view(&self) -> bool {
+        !matches!(self, WorldEntity::Item(_))
+    }
+}
+
+impl Entity for WorldEntity {
+    fn id(&self) -> i32 {
+        self.inner().id()
+    }
+
+    fn pos(&self) -> Vec3d {
+        self.inner().pos()
+    }
+
+    fn set_pos(&mut self, pos: Vec3d) {
+        match self {
+            WorldEntity::Item(item) => item.set_pos(pos),
+            WorldEntity::Mob(mob) => mob.set_pos(pos),
+            WorldEntity::Projectile(projectile) => projectile.set_pos(pos),
+        }
+    }
+
+    fn rot(&self) -> Vec2f {
+        self.inner().rot()
+    }
+
+    fn set_rot(&mut self, rot: Vec2f) {
+        match self {
+            WorldEntity::Item(item) => item.set_rot(rot),
+            WorldEntity::Mob(mob) => mob.set_rot(rot),
+            WorldEntity::Projectile(projectile) => projectile.set_rot(rot),
+        }
+    }
+}
+
+/// A team of players whose names are shown in the team color
+#[derive(Debug, Clone)]
+pub struct Team {
+    pub color: u8,
+    pub members: Vec<String>,
+}
+
+impl Team {
+    pub fn new(color: u8) -> Team {
+        Team {
+            color,
+            members: Vec::new(),
+        }
+    }
+
+    pub fn info(&self, name: &str) -> TeamInfo {
+        TeamInfo {
+            display_name: name.to_string(),
+            prefix: format!("§{:x}", self.color),
+            suffix: "§r".to_string(),
+            friendly_fire: true,
+            name_tag_visibility: "always".to_string(),
+            color: self.color as i8,
+        }
+    }
+}
+
+/// Square border around the playable area, the only shape the 1.8 client can show
+#[derive(Debug, Clone, Copy)]
+pub struct WorldBorder {
+    pub center_x: f64,
+    pub center_z: f64,
+    /// Distance from the center to each side
+    pub radius: f64,
+}
+
+impl WorldBorder {
+    /// Distance in blocks and time in seconds at which the client starts showing the warning
+    const WARNING_BLOCKS: i32 = 5;
+    const WARNING_TIME: i32 = 15;
+
+    pub fn contains(&self, x: f64, z: f64) -> bool {
+        (x - self.center_x).abs() <= self.radius && (z - self.center_z).abs() <= self.radius
+    }
+
+    /// Whether the whole block is within the border
+    pub fn contains_block(&self, pos: BlockPos) -> bool {
+        self.contains(pos.x as f64, pos.z as f64)
+            && self.contains(pos.x as f64 + 1.0, pos.z as f64 + 1.0)
+    }
+
+    /// Moves a position to the nearest point within the border
+    pub fn clamp(&self, pos: Vec3d) -> Vec3d {
+        Vec3d {
+            x: pos
+                .x
+                .clamp(self.center_x - self.radius, self.center_x + self.radius),
+            y: pos.y,
+            z: pos
+                .z
+                .clamp(self.center_z - self.radius, self.center_z + self.radius),
+        }
+    }
+
+    pub fn init_packet(&self) -> Packet {
+        Packet::S44WorldBorder {
+            action: WorldBorderAction::Initialize {
+                x: self.center_x,
+                z: self.center_z,
+                old_diameter: self.radius * 2.0,
+                new_diameter: self.radius * 2.0,
+                speed: 0,
+                portal_boundary: 29_999_984,
+                warning_time: Self::WARNING_TIME,
+                warning_blocks: Self::WARNING_BLOCKS,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_data(display_name: Option<&str>) -> PlayerData {
+        PlayerData {
+            game_mode: GameMode::Survival,
+            position: Vec3d {
+                x: 1.5,
+                y: 64.0,
+                z: -3.5,
+            },
+            rotation: Default::default(),
+            health: 20.0,
+            food: 20,
+            saturation: 5.0,
+            inventory: vec![ItemStack::default(); 45],
+            display_name: display_name.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn nickname_survives_saving() {
+        for display_name in [Some("§aSteve"), None] {
+            let bytes = player_data(display_name).to_bytes();
+            let loaded = PlayerData::from_bytes(&bytes).unwrap();
+            assert_eq!(loaded.display_name.as_deref(), display_name);
+            assert_eq!(loaded.position, player_data(None).position);
+        }
+    }
+
+    #[test]
+    fn version_1_has_no_nickname() {
+        // Version 1 ends after the inventory
+        let mut bytes = player_data(None).to_bytes();
+        bytes[4] = 1;
+        bytes.truncate(bytes.len() - 2);
+        assert_eq!(PlayerData::from_bytes(&bytes).unwrap().display_name, None);
+
+        bytes[4] = 2;
+        assert!(PlayerData::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn oversized_edit_is_rejected() {
+        let edit = |n| vec![(BlockPos::new(0, 0, 0), 0); n];
+        let mut history = EditHistory::new();
+        assert!(history.push(edit(10)));
+        assert!(!history.push(edit(EditHistory::MAX_BLOCKS + 1)));
+
+        // The history from before is kept
+        assert_eq!(history.pop().map(|e| e.len()), Some(10));
+        assert!(history.push(edit(EditHistory::MAX_BLOCKS)));
+        assert_eq!(
+            history.pop().map(|e| e.len()),
+            Some(EditHistory::MAX_BLOCKS)
+        );
+    }
+
+    #[test]
+    fn status_flags_follow_the_pose() {
+        let mut player = Player::new(1, GameMode::Survival);
+        assert_eq!(player.status_flags(), 0);
+
+        player.sneaking = true;
+        player.sprinting = true;
+        assert_eq!(
+            player.status_flags(),
+            EntityMetaEntry::STATUS_CROUCHED | EntityMetaEntry::STATUS_SPRINTING
+        );
+    }
+}
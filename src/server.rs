@@ -1,26 +1,171 @@
-use std::sync::{
-    atomic::{AtomicI32, Ordering},
-    Arc,
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use dashmap::DashMap;
-use tokio::{io, sync::mpsc};
+use dashmap::{DashMap, DashSet};
+use log::{debug, error, info, log_enabled, warn, Level};
+use rand::Rng;
+use serde_json::json;
+use tokio::{
+    fs, io, select,
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
+    time,
+};
+use uuid::Uuid;
 
 use crate::{
-    config::ServerConfig,
-    mc::proto::Packet,
-    world::{sched::GenerationScheduler, World},
+    access::{AccessList, Ban, BanList, RegionList},
+    action_bar,
+    audit::format_age,
+    audit::BlockLog,
+    block_id, block_meta, chat_packet,
+    client::SPAWN_POS,
+    command::{self, Command},
+    config::{ServerConfig, WorldGenConfig, MAX_BORDER_RADIUS, WORLD_CONFIG_PATH},
+    listener::{GameListener, SpawnProtection},
+    mc::proto::{
+        chat_color, particle_id, Packet, TeamAction, TitleAction, CHAT_COLORS,
+        PARTICLE_BLOCK_CRACK, PARTICLE_BLOCK_DUST, PARTICLE_ICON_CRACK,
+    },
+    model::{
+        Entity, ItemEntity, ItemStack, Mob, MobKind, Player, PlayerData, Team, Vec3d, WorldBorder,
+        WorldEntity,
+    },
+    utils::translate_color_codes,
+    world::{
+        is_ore, is_solid, lock_chunk, sched::GenerationScheduler, BlockFace, BlockPos, ChunkPos,
+        World,
+    },
 };
 
+const TICK_DURATION: Duration = Duration::from_millis(50);
+pub const PARTICLE_RANGE: f64 = 32.0;
+const SOUND_RANGE: f64 = 16.0;
+/// Longest team name the 1.8 client accepts
+const MAX_TEAM_NAME_LENGTH: usize = 16;
+const DEFAULT_BAN_REASON: &str = "Banned by an operator";
+const LAGGING_CLIENT_REASON: &str = "Your connection is too slow to keep up";
+/// Packets that may wait for a client, see `Packet::is_bulk` for the larger queue
+const CLIENT_QUEUE_SIZE: usize = 128;
+const BULK_QUEUE_SIZE: usize = 2048;
+/// Events a subscriber may fall behind by before it starts missing them
+const EVENT_BUS_CAPACITY: usize = 1024;
+/// Changes shown by `/lookup`
+const LOOKUP_LIMIT: usize = 10;
+/// White, for teams created without a color
+const DEFAULT_TEAM_COLOR: u8 = 15;
+const MOB_SPAWN_INTERVAL: u64 = 20;
+const MOB_SPAWN_RADIUS: i32 = 6;
+const MOB_AI_SPREAD: u64 = 4;
+const MAX_MOB_UPDATES_PER_TICK: usize = 64;
+const PASSIVE_MOB_KINDS: [MobKind; 3] = [MobKind::Pig, MobKind::Sheep, MobKind::Cow];
+
+/// Grassy biomes where animals may spawn (plains, hills, forests, taiga, swamp, jungle)
+const PASSIVE_MOB_BIOMES: &[u8] = &[1, 3, 4, 5, 6, 18, 21, 22, 27, 29, 34];
+
+/// Something that happened in the game, for systems that want to react to it without being
+/// called directly. Subscribe with [`ServerHandler::subscribe_events`].
+#[derive(Debug, Clone)]
+pub enum GameEvent {
+    /// One event per action, so a large edit doesn't flood the bus
+    BlocksChanged {
+        /// Username of the player who changed them
+        player: String,
+        changes: Arc<[BlockChange]>,
+    },
+    PlayerJoined {
+        id: i32,
+        username: String,
+    },
+    PlayerLeft {
+        id: i32,
+        username: String,
+    },
+    ChunkGenerated {
+        pos: ChunkPos,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockChange {
+    pub location: BlockPos,
+    pub old_state: u16,
+    pub new_state: u16,
+}
+
+/// Actions that other clients ask a client handler to apply to its player
 #[derive(Debug)]
-pub enum GameEvent {}
+pub enum PlayerEvent {
+    Attacked {
+        attacker: i32,
+        damage: f32,
+        knockback: Vec3d,
+    },
+    Kick {
+        reason: String,
+    },
+    /// An ender pearl thrown by the player landed
+    Teleport {
+        position: Vec3d,
+    },
+}
+
+/// Shared view of a logged in player for server-wide logic
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct PlayerInfo {
+    pub username: String,
+    pub uuid: Uuid,
+    pub position: Vec3d,
+    pub address: IpAddr,
+}
 
 pub struct ServerHandler {
     pub config: Arc<ServerConfig>,
     pub world: Arc<World>,
     pub gen: Arc<GenerationScheduler>,
+    pub banned_ips: AccessList,
+    pub banned_players: BanList,
+    pub whitelist: AccessList,
+    pub regions: Arc<RegionList>,
+    block_log: Option<Arc<BlockLog>>,
+    whitelist_enabled: AtomicBool,
     broadcast_tx: mpsc::Sender<Packet>,
+    /// Broadcasts that skip ahead of the bulk traffic, see `Packet::is_priority`
+    priority_tx: mpsc::Sender<Packet>,
+    events_tx: broadcast::Sender<GameEvent>,
+    listeners: RwLock<Vec<Box<dyn GameListener>>>,
+    shutdown_tx: watch::Sender<bool>,
     clients: DashMap<i32, mpsc::Sender<Packet>>,
+    bulk_queues: DashMap<i32, mpsc::Sender<Packet>>,
+    /// Clients that missed bulk packets and need their chunks and entities sent again
+    out_of_sync: DashSet<i32>,
+    player_events: DashMap<i32, mpsc::Sender<PlayerEvent>>,
+    pub players: DashMap<i32, PlayerInfo>,
+    player_data: DashMap<Uuid, PlayerData>,
+    /// Who last sent a private message to each player, for `/r`
+    reply_targets: DashMap<i32, i32>,
+    teams: DashMap<String, Team>,
+    world_border: RwLock<WorldBorder>,
+    /// Every entity apart from the players
+    entities: DashMap<i32, WorldEntity>,
+    /// The entities in each chunk, so finding the ones near a player doesn't scan them all
+    entity_chunks: DashMap<ChunkPos, HashSet<i32>>,
+    /// The clients each entity was spawned for, which are the only ones sent its updates
+    entity_viewers: DashMap<i32, HashSet<i32>>,
+    /// Clients that were sent each chunk, so updates of a chunk only go to them
+    chunk_viewers: DashMap<ChunkPos, HashSet<i32>>,
     id_counter: AtomicI32,
     player_counter: AtomicI32,
 }
@@ -30,39 +175,526 @@ impl ServerHandler {
         config: Arc<ServerConfig>,
         world: Arc<World>,
         gen: Arc<GenerationScheduler>,
+        banned_ips: AccessList,
+        banned_players: BanList,
+        whitelist: AccessList,
+        regions: RegionList,
     ) -> Arc<ServerHandler> {
         let (broadcast_tx, broadcast_rx) = mpsc::channel::<Packet>(128);
+        let (priority_tx, priority_rx) = mpsc::channel::<Packet>(128);
 
         let handler = Arc::new(ServerHandler {
+            whitelist_enabled: AtomicBool::new(config.whitelist),
+            world_border: RwLock::new(config.world_border()),
+            block_log: config.block_log.as_deref().map(BlockLog::start),
             config,
             world,
             gen,
+            banned_ips,
+            banned_players,
+            whitelist,
+            regions: Arc::new(regions),
             broadcast_tx,
+            priority_tx,
+            events_tx: broadcast::channel(EVENT_BUS_CAPACITY).0,
+            listeners: RwLock::new(Vec::new()),
+            shutdown_tx: watch::channel(false).0,
             clients: DashMap::new(),
+            bulk_queues: DashMap::new(),
+            out_of_sync: DashSet::new(),
+            player_events: DashMap::new(),
+            players: DashMap::new(),
+            player_data: DashMap::new(),
+            reply_targets: DashMap::new(),
+            teams: DashMap::new(),
+            entities: DashMap::new(),
+            entity_chunks: DashMap::new(),
+            entity_viewers: DashMap::new(),
+            chunk_viewers: DashMap::new(),
             id_counter: AtomicI32::new(1),
             player_counter: AtomicI32::new(0),
         });
 
         let h = handler.clone();
         tokio::spawn(async move {
-            h.run_broker_loop(broadcast_rx).await;
+            h.run_broker_loop(broadcast_rx, priority_rx).await;
+        });
+
+        let h = handler.clone();
+        tokio::spawn(async move {
+            h.run_tick_loop().await;
+        });
+
+        let h = handler.clone();
+        let mut completions = handler.gen.subscribe_completions();
+        tokio::spawn(async move {
+            loop {
+                match completions.recv().await {
+                    Ok(pos) => h.emit(GameEvent::ChunkGenerated { pos }),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
         });
 
+        if handler.config.spawn_protection > 0 {
+            handler.register_listener(Box::new(SpawnProtection {
+                center: BlockPos::from_pos(SPAWN_POS.x, SPAWN_POS.y, SPAWN_POS.z),
+                radius: handler.config.spawn_protection,
+            }));
+        }
+
+        handler.register_listener(Box::new(handler.regions.clone()));
+
+        if log_enabled!(Level::Debug) {
+            let events = handler.subscribe_events();
+            tokio::spawn(async move {
+                log_events(events).await;
+            });
+        }
+
+        if handler.config.anti_xray {
+            let h = handler.clone();
+            let events = handler.subscribe_events();
+            tokio::spawn(async move {
+                h.run_anti_xray(events).await;
+            });
+        }
+
         handler
     }
 
+    pub fn subscribe_events(&self) -> broadcast::Receiver<GameEvent> {
+        self.events_tx.subscribe()
+    }
+
+    /// Publishes an event to all subscribers. Nobody listening is fine. Block changes also
+    /// go to the block log, which must not miss any.
+    pub fn emit(&self, event: GameEvent) {
+        if let (Some(block_log), GameEvent::BlocksChanged { player, changes }) =
+            (&self.block_log, &event)
+        {
+            block_log.record(player, changes);
+        }
+        let _ = self.events_tx.send(event);
+    }
+
+    pub fn register_listener(&self, listener: Box<dyn GameListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Asks the listeners whether a player may change a block. Setting air is a break,
+    /// anything else a place.
+    pub fn check_block_change(
+        &self,
+        player: &Player,
+        location: BlockPos,
+        old_state: u16,
+        new_state: u16,
+    ) -> Result<(), String> {
+        let listeners = self.listeners.read().unwrap();
+        if new_state == 0 {
+            listeners
+                .iter()
+                .try_for_each(|l| l.on_block_break(player, location, old_state))
+        } else {
+            listeners
+                .iter()
+                .try_for_each(|l| l.on_block_place(player, location, new_state))
+        }
+    }
+
+    pub fn check_chat(&self, player: &Player, message: &str) -> Result<(), String> {
+        let listeners = self.listeners.read().unwrap();
+        listeners
+            .iter()
+            .try_for_each(|l| l.on_chat(player, message))
+    }
+
+    pub fn check_join(&self, player: &Player) -> Result<(), String> {
+        let listeners = self.listeners.read().unwrap();
+        listeners.iter().try_for_each(|l| l.on_join(player))
+    }
+
+    pub fn notify_leave(&self, player: &Player) {
+        for listener in self.listeners.read().unwrap().iter() {
+            listener.on_leave(player);
+        }
+    }
+
+    /// Anti-xray sends enclosed ores as stone, so the real ores next to a block have to be
+    /// shown once it no longer covers them
+    async fn run_anti_xray(&self, mut events: broadcast::Receiver<GameEvent>) {
+        loop {
+            let changes = match events.recv().await {
+                Ok(GameEvent::BlocksChanged { changes, .. }) => changes,
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("Anti-xray missed {} events, some ores may stay hidden", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+
+            // Ores next to several opened blocks are only sent once
+            let mut exposed = HashSet::new();
+            for change in changes.iter().filter(|c| !is_solid(c.new_state)) {
+                for face in BlockFace::ALL {
+                    let pos = change.location.offset(face);
+                    if exposed.contains(&pos) {
+                        continue;
+                    }
+                    let neighbor = self.world.get_block(pos.x, pos.y, pos.z);
+                    if is_ore(neighbor) {
+                        exposed.insert(pos);
+                        let packet = Packet::S23BlockChange {
+                            location: pos,
+                            block_state: neighbor,
+                        };
+                        if self.send_broadcast(packet).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     pub fn new_id(&self) -> i32 {
         self.id_counter.fetch_add(1, Ordering::SeqCst)
     }
 
     pub fn add_client(&self, id: i32) -> mpsc::Receiver<Packet> {
-        let (tx, rx) = mpsc::channel::<Packet>(128);
+        let (tx, rx) = mpsc::channel::<Packet>(CLIENT_QUEUE_SIZE);
         self.clients.insert(id, tx);
         rx
     }
 
     pub fn remove_client(&self, id: i32) {
         self.clients.remove(&id);
+        self.bulk_queues.remove(&id);
+        self.out_of_sync.remove(&id);
+        self.player_events.remove(&id);
+        for mut viewers in self.entity_viewers.iter_mut() {
+            viewers.remove(&id);
+        }
+        self.chunk_viewers.retain(|_, viewers| {
+            viewers.remove(&id);
+            !viewers.is_empty()
+        });
+    }
+
+    pub fn add_bulk_queue(&self, id: i32) -> mpsc::Receiver<Packet> {
+        let (tx, rx) = mpsc::channel::<Packet>(BULK_QUEUE_SIZE);
+        self.bulk_queues.insert(id, tx);
+        rx
+    }
+
+    /// Whether bulk packets were dropped for a client since it was last asked
+    pub fn take_out_of_sync(&self, id: i32) -> bool {
+        self.out_of_sync.remove(&id).is_some()
+    }
+
+    pub fn add_player_events(&self, id: i32) -> mpsc::Receiver<PlayerEvent> {
+        let (tx, rx) = mpsc::channel::<PlayerEvent>(32);
+        self.player_events.insert(id, tx);
+        rx
+    }
+
+    /// Sends an event to the handler of another player. Never waits, as two handlers waiting
+    /// for each other's full queues would hang both. Returns false if that player is gone
+    /// or the event was dropped because the queue is full.
+    pub fn send_player_event(&self, id: i32, event: PlayerEvent) -> bool {
+        let result = match self.player_events.get(&id) {
+            Some(tx) => tx.try_send(event),
+            None => return false,
+        };
+        match result {
+            Ok(()) => true,
+            Err(TrySendError::Closed(_)) => false,
+            Err(TrySendError::Full(event)) => {
+                warn!("Dropping {:?} for player {} as it can't keep up", event, id);
+                false
+            }
+        }
+    }
+
+    pub fn add_player(&self, id: i32, info: PlayerInfo) {
+        let username = info.username.clone();
+        self.players.insert(id, info);
+        self.emit(GameEvent::PlayerJoined { id, username });
+    }
+
+    pub fn remove_player(&self, id: i32) {
+        self.reply_targets.remove(&id);
+        if let Some((_, info)) = self.players.remove(&id) {
+            self.emit(GameEvent::PlayerLeft {
+                id,
+                username: info.username,
+            });
+        }
+    }
+
+    pub fn set_reply_target(&self, id: i32, sender_id: i32) {
+        self.reply_targets.insert(id, sender_id);
+    }
+
+    pub fn reply_target(&self, id: i32) -> Option<i32> {
+        self.reply_targets.get(&id).map(|sender_id| *sender_id)
+    }
+
+    pub async fn create_team(&self, name: &str, color: u8) -> io::Result<()> {
+        let team = Team::new(color);
+        let info = team.info(name);
+        self.teams.insert(name.to_string(), team);
+        self.send_team_action(
+            name,
+            TeamAction::Create {
+                info,
+                players: Vec::new(),
+            },
+        )
+        .await
+    }
+
+    pub async fn remove_team(&self, name: &str) -> io::Result<()> {
+        if self.teams.remove(name).is_some() {
+            self.send_team_action(name, TeamAction::Remove).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_team_color(&self, name: &str, color: u8) -> io::Result<()> {
+        let info = match self.teams.get_mut(name) {
+            Some(mut team) => {
+                team.color = color;
+                team.info(name)
+            }
+            None => return Ok(()),
+        };
+        self.send_team_action(name, TeamAction::UpdateInfo(info))
+            .await
+    }
+
+    /// Adds a player to a team. Players can only be on one team, the client moves them
+    /// out of the previous one by itself.
+    pub async fn join_team(&self, name: &str, username: &str) -> io::Result<()> {
+        for mut team in self.teams.iter_mut() {
+            team.members.retain(|member| member != username);
+        }
+        match self.teams.get_mut(name) {
+            Some(mut team) => team.members.push(username.to_string()),
+            None => return Ok(()),
+        }
+        self.send_team_action(name, TeamAction::AddPlayers(vec![username.to_string()]))
+            .await
+    }
+
+    /// Removes a player from their team and returns the team's name
+    pub async fn leave_team(&self, username: &str) -> io::Result<Option<String>> {
+        let name = self.teams.iter_mut().find_map(|mut team| {
+            let count = team.members.len();
+            team.members.retain(|member| member != username);
+            (team.members.len() != count).then(|| team.key().clone())
+        });
+
+        if let Some(name) = &name {
+            self.send_team_action(name, TeamAction::RemovePlayers(vec![username.to_string()]))
+                .await?;
+        }
+        Ok(name)
+    }
+
+    /// Packets that tell a joining player about all teams
+    pub fn team_packets(&self) -> Vec<Packet> {
+        self.teams
+            .iter()
+            .map(|team| Packet::S3ETeams {
+                name: team.key().clone(),
+                action: TeamAction::Create {
+                    info: team.info(team.key()),
+                    players: team.members.clone(),
+                },
+            })
+            .collect()
+    }
+
+    async fn send_team_action(&self, name: &str, action: TeamAction) -> io::Result<()> {
+        self.send_broadcast(Packet::S3ETeams {
+            name: name.to_string(),
+            action,
+        })
+        .await
+    }
+
+    /// Loads the saved state of a player. Missing or corrupt files mean the player
+    /// starts fresh.
+    pub async fn load_player_data(&self, uuid: Uuid) -> Option<PlayerData> {
+        if let Some(data) = self.player_data.get(&uuid) {
+            return Some(data.clone());
+        }
+
+        let bytes = fs::read(self.player_data_path(uuid)).await.ok()?;
+        match PlayerData::from_bytes(&bytes) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                warn!("Ignoring player data of {}: {}", uuid, e);
+                None
+            }
+        }
+    }
+
+    /// Keeps the state of a player for when they return, also across restarts
+    pub async fn save_player_data(&self, uuid: Uuid, data: PlayerData) {
+        let bytes = data.to_bytes();
+        self.player_data.insert(uuid, data);
+
+        let result = async {
+            fs::create_dir_all(&self.config.player_data_dir).await?;
+            fs::write(self.player_data_path(uuid), bytes).await
+        };
+        if let Err(e) = result.await {
+            error!("Failed to save player data of {}: {}", uuid, e);
+        }
+    }
+
+    fn player_data_path(&self, uuid: Uuid) -> PathBuf {
+        Path::new(&self.config.player_data_dir).join(format!("{}.dat", uuid))
+    }
+
+    pub fn update_player_pos(&self, id: i32, position: Vec3d) {
+        if let Some(mut info) = self.players.get_mut(&id) {
+            info.position = position;
+        }
+    }
+
+    /// Entities within `range` blocks horizontally, with the chunk each one is in
+    pub fn entities_near(&self, position: Vec3d, range: f64) -> Vec<(i32, ChunkPos)> {
+        let min = ChunkPos::from_block_pos(
+            (position.x - range).floor() as i32,
+            (position.z - range).floor() as i32,
+        );
+        let max = ChunkPos::from_block_pos(
+            (position.x + range).floor() as i32,
+            (position.z + range).floor() as i32,
+        );
+
+        // Collect the ids first, so the index isn't locked while the entities are
+        let mut candidates = Vec::new();
+        for z in min.z..=max.z {
+            for x in min.x..=max.x {
+                if let Some(ids) = self.entity_chunks.get(&ChunkPos::new(x, z)) {
+                    candidates.extend(ids.iter().copied());
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .filter_map(|eid| self.entities.get(&eid))
+            .filter(|e| {
+                let pos = e.pos();
+                let dx = pos.x - position.x;
+                let dz = pos.z - position.z;
+                dx * dx + dz * dz <= range * range
+            })
+            .map(|e| (*e.key(), e.chunk_pos()))
+            .collect()
+    }
+
+    /// Records that an entity was spawned for a client, so its updates are sent there
+    pub fn track_entity(&self, id: i32, eid: i32) {
+        if let Some(mut viewers) = self.entity_viewers.get_mut(&eid) {
+            viewers.insert(id);
+        }
+    }
+
+    /// Records that an entity was destroyed for a client
+    pub fn untrack_entity(&self, id: i32, eid: i32) {
+        if let Some(mut viewers) = self.entity_viewers.get_mut(&eid) {
+            viewers.remove(&id);
+        }
+    }
+
+    /// Records that a chunk was sent to a client, so its updates are sent there
+    pub fn track_chunk(&self, id: i32, pos: ChunkPos) {
+        self.chunk_viewers.entry(pos).or_default().insert(id);
+    }
+
+    /// Records that a chunk was unloaded for a client
+    pub fn untrack_chunk(&self, id: i32, pos: ChunkPos) {
+        self.chunk_viewers.remove_if_mut(&pos, |_, viewers| {
+            viewers.remove(&id);
+            viewers.is_empty()
+        });
+    }
+
+    /// Queues a packet for the clients that have a chunk
+    pub fn send_to_chunk_viewers(&self, pos: ChunkPos, packet: Packet) {
+        let targets: Vec<i32> = self
+            .chunk_viewers
+            .get(&pos)
+            .map(|viewers| viewers.iter().copied().collect())
+            .unwrap_or_default();
+        for id in targets {
+            self.deliver(id, packet.clone());
+        }
+    }
+
+    /// The clients a broadcast goes to. Packets about a world entity only go to the clients
+    /// that were sent that entity, everything else goes to every client.
+    fn broadcast_targets(&self, packet: &Packet) -> Vec<i32> {
+        let viewers = packet
+            .entity_id()
+            .and_then(|eid| self.entity_viewers.get(&eid));
+        match viewers {
+            Some(viewers) => viewers.iter().copied().collect(),
+            None => self.clients.iter().map(|c| *c.key()).collect(),
+        }
+    }
+
+    /// Packets that show an entity in its current state, nothing if it is gone
+    pub fn entity_spawn_packets(&self, eid: i32) -> Vec<Packet> {
+        self.entities
+            .get(&eid)
+            .map(|e| e.spawn_packets())
+            .unwrap_or_default()
+    }
+
+    /// Adds an entity. The client handlers show it to the players that are close enough.
+    pub fn spawn_entity(&self, entity: WorldEntity) {
+        let eid = entity.id();
+        self.entity_chunks
+            .entry(entity.chunk_pos())
+            .or_default()
+            .insert(eid);
+        self.entity_viewers.insert(eid, HashSet::new());
+        self.entities.insert(eid, entity);
+    }
+
+    /// Removes entities. The client handlers destroy them for the players that saw them.
+    fn remove_entities(&self, entity_ids: &[i32]) {
+        for eid in entity_ids {
+            if let Some((_, entity)) = self.entities.remove(eid) {
+                self.unindex_entity(*eid, entity.chunk_pos());
+            }
+            self.entity_viewers.remove(eid);
+        }
+    }
+
+    fn unindex_entity(&self, eid: i32, chunk_pos: ChunkPos) {
+        if let Some(mut ids) = self.entity_chunks.get_mut(&chunk_pos) {
+            ids.remove(&eid);
+        }
+        self.entity_chunks
+            .remove_if(&chunk_pos, |_, ids| ids.is_empty());
+    }
+
+    /// Spawns a dropped item entity
+    pub fn spawn_item(&self, position: Vec3d, stack: ItemStack) -> i32 {
+        let eid = self.new_id();
+        self.spawn_entity(WorldEntity::Item(ItemEntity::new(eid, stack, position)));
+        eid
     }
 
     pub fn change_num_players(&self, chg: i32) {
@@ -73,20 +705,844 @@ impl ServerHandler {
         self.player_counter.load(Ordering::SeqCst)
     }
 
+    /// Usernames of all online players, sorted alphabetically
+    pub fn player_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.players.iter().map(|p| p.username.clone()).collect();
+        names.sort_unstable_by_key(|name| name.to_lowercase());
+        names
+    }
+
+    pub fn find_player(&self, username: &str) -> Option<i32> {
+        self.players
+            .iter()
+            .find(|p| p.username.eq_ignore_ascii_case(username))
+            .map(|p| *p.key())
+    }
+
+    /// Sends a packet to a single client. Returns false if that client is gone.
+    pub async fn send_to(&self, id: i32, packet: Packet) -> bool {
+        self.deliver(id, packet)
+    }
+
+    /// Queues a packet for a client without waiting, so one slow client can't hold up
+    /// everyone else. Bulk packets that don't fit are dropped and the client is resynced
+    /// once it caught up. A client whose other queue is full is disconnected, as those
+    /// packets can't be restored. Returns false if the packet wasn't queued.
+    fn deliver(&self, id: i32, packet: Packet) -> bool {
+        let is_bulk = packet.is_bulk();
+        let queues = if is_bulk {
+            &self.bulk_queues
+        } else {
+            &self.clients
+        };
+        let result = match queues.get(&id) {
+            Some(tx) => tx.try_send(packet),
+            None => return false,
+        };
+        match result {
+            Ok(()) => true,
+            // The client is disconnecting and doesn't need the packet anymore
+            Err(TrySendError::Closed(_)) => false,
+            Err(TrySendError::Full(_)) if is_bulk => {
+                if self.out_of_sync.insert(id) {
+                    debug!("Dropping bulk packets for client {} until it caught up", id);
+                }
+                false
+            }
+            Err(TrySendError::Full(_)) => {
+                warn!(
+                    "Dropping packets for client {} as it can't keep up, disconnecting it",
+                    id
+                );
+                self.clients.remove(&id);
+                if let Some(tx) = self.player_events.get(&id) {
+                    let _ = tx.try_send(PlayerEvent::Kick {
+                        reason: LAGGING_CLIENT_REASON.to_string(),
+                    });
+                }
+                false
+            }
+        }
+    }
+
+    /// Sends a packet to all players within `radius` blocks of `position`
+    pub async fn send_nearby(
+        &self,
+        position: Vec3d,
+        radius: f64,
+        packet: Packet,
+    ) -> io::Result<()> {
+        let targets: Vec<i32> = self
+            .players
+            .iter()
+            .filter(|p| p.position.distance_to(position) <= radius)
+            .map(|p| *p.key())
+            .collect();
+
+        for id in targets {
+            self.deliver(id, packet.clone());
+        }
+        Ok(())
+    }
+
+    /// Runs a command that doesn't need a player, for players and the console alike
+    pub async fn exec_command(
+        &self,
+        command: &Command<'_>,
+        permission_level: u8,
+    ) -> Result<Option<String>, String> {
+        match command.name() {
+            "help" => Ok(Some(command::help_text(permission_level))),
+            "reloadgen" => {
+                let config = WorldGenConfig::load(WORLD_CONFIG_PATH)?;
+                self.gen.generator().update_config(config);
+                Ok(Some(
+                    "World generator config reloaded, new chunks will use it".to_string(),
+                ))
+            }
+            "particle" => {
+                let name = command.arg::<String>(0)?;
+                let particle_id = particle_id(&name)
+                    .ok_or_else(|| format!("{} is not a valid particle", name))?;
+                if matches!(
+                    particle_id,
+                    PARTICLE_ICON_CRACK | PARTICLE_BLOCK_CRACK | PARTICLE_BLOCK_DUST
+                ) {
+                    return Err(format!("Particle {} is not supported", name));
+                }
+
+                let (x, y, z) = (
+                    command.arg::<f32>(1)?,
+                    command.arg::<f32>(2)?,
+                    command.arg::<f32>(3)?,
+                );
+                let count = match command.arg::<i32>(4) {
+                    Ok(count) if count > 0 => count,
+                    Ok(_) => return Err("Count must be positive".to_string()),
+                    Err(_) => 1,
+                };
+
+                let position = Vec3d {
+                    x: x as f64,
+                    y: y as f64,
+                    z: z as f64,
+                };
+                self.send_nearby(
+                    position,
+                    PARTICLE_RANGE,
+                    Packet::S2AParticle {
+                        particle_id,
+                        long_distance: false,
+                        x,
+                        y,
+                        z,
+                        offset_x: 0.0,
+                        offset_y: 0.0,
+                        offset_z: 0.0,
+                        speed: 0.0,
+                        count,
+                        data: Vec::new(),
+                    },
+                )
+                .await
+                .expect("Failed to send particles");
+                Ok(Some(format!("Spawned {} {} particles", count, name)))
+            }
+            "playsound" => {
+                let name = command.arg::<String>(0)?;
+                let position = Vec3d {
+                    x: command.arg::<f64>(1)?,
+                    y: command.arg::<f64>(2)?,
+                    z: command.arg::<f64>(3)?,
+                };
+                let volume = command.arg::<f32>(4).unwrap_or(1.0);
+                let pitch = command.arg::<f32>(5).unwrap_or(1.0);
+                if !volume.is_finite() || volume < 0.0 {
+                    return Err("Volume must not be negative".to_string());
+                }
+                if !(0.0..=2.0).contains(&pitch) {
+                    return Err("Pitch must be between 0 and 2".to_string());
+                }
+
+                // Louder sounds are heard from further away
+                let range = SOUND_RANGE * volume.max(1.0) as f64;
+                self.send_nearby(
+                    position,
+                    range,
+                    Packet::S29NamedSoundEffect {
+                        name: name.clone(),
+                        x: position.x,
+                        y: position.y,
+                        z: position.z,
+                        volume,
+                        pitch,
+                    },
+                )
+                .await
+                .expect("Failed to send sound");
+                Ok(Some(format!("Played sound {}", name)))
+            }
+            "title" => {
+                let target = command.arg::<String>(0)?;
+                let target_id = self
+                    .find_player(&target)
+                    .ok_or_else(|| format!("Player {} not found", target))?;
+
+                let action = command.arg::<String>(1)?;
+                let action = match action.as_str() {
+                    "title" | "subtitle" => {
+                        let json_data = command.rest(2)?;
+                        if serde_json::from_str::<serde_json::Value>(&json_data).is_err() {
+                            return Err(format!("{} is not valid JSON", json_data));
+                        }
+                        if action == "title" {
+                            TitleAction::SetTitle(json_data)
+                        } else {
+                            TitleAction::SetSubtitle(json_data)
+                        }
+                    }
+                    "times" => TitleAction::SetTimes {
+                        fade_in: command.arg::<i32>(2)?,
+                        stay: command.arg::<i32>(3)?,
+                        fade_out: command.arg::<i32>(4)?,
+                    },
+                    "clear" => TitleAction::Hide,
+                    "reset" => TitleAction::Reset,
+                    _ => return Err(format!("Unknown action {}", action)),
+                };
+
+                self.send_to(target_id, Packet::S45Title { action }).await;
+                Ok(Some(format!("Title updated for {}", target)))
+            }
+            "actionbar" => {
+                let target = command.arg::<String>(0)?;
+                let target_id = self
+                    .find_player(&target)
+                    .ok_or_else(|| format!("Player {} not found", target))?;
+                let text = command.rest(1)?;
+
+                self.send_to(target_id, action_bar!(text)).await;
+                Ok(Some(format!("Action bar updated for {}", target)))
+            }
+            "team" => {
+                let action = command.arg::<String>(0)?;
+                match action.as_str() {
+                    "create" => {
+                        let name = command.arg::<String>(1)?;
+                        if name.chars().count() > MAX_TEAM_NAME_LENGTH {
+                            return Err(format!(
+                                "Team names can have at most {} characters",
+                                MAX_TEAM_NAME_LENGTH
+                            ));
+                        }
+                        if self.teams.contains_key(&name) {
+                            return Err(format!("Team {} already exists", name));
+                        }
+                        let color = match command.arg::<String>(2) {
+                            Ok(color) => Self::parse_chat_color(&color)?,
+                            Err(_) => DEFAULT_TEAM_COLOR,
+                        };
+
+                        self.create_team(&name, color)
+                            .await
+                            .map_err(|e| format!("Failed to create team: {}", e))?;
+                        Ok(Some(format!("Team {} created", name)))
+                    }
+                    "remove" => {
+                        let name = self.team_arg(command, 1)?;
+                        self.remove_team(&name)
+                            .await
+                            .map_err(|e| format!("Failed to remove team: {}", e))?;
+                        Ok(Some(format!("Team {} removed", name)))
+                    }
+                    "color" => {
+                        let name = self.team_arg(command, 1)?;
+                        let color = Self::parse_chat_color(&command.arg::<String>(2)?)?;
+                        self.set_team_color(&name, color)
+                            .await
+                            .map_err(|e| format!("Failed to update team: {}", e))?;
+                        Ok(Some(format!("Team {} updated", name)))
+                    }
+                    "join" => {
+                        let name = self.team_arg(command, 1)?;
+                        let target = command.arg::<String>(2)?;
+                        let username = self
+                            .find_player(&target)
+                            .and_then(|id| self.players.get(&id).map(|p| p.username.clone()))
+                            .ok_or_else(|| format!("Player {} not found", target))?;
+                        self.join_team(&name, &username)
+                            .await
+                            .map_err(|e| format!("Failed to join team: {}", e))?;
+                        Ok(Some(format!("{} joined team {}", username, name)))
+                    }
+                    "leave" => {
+                        let target = command.arg::<String>(1)?;
+                        match self
+                            .leave_team(&target)
+                            .await
+                            .map_err(|e| format!("Failed to leave team: {}", e))?
+                        {
+                            Some(name) => Ok(Some(format!("{} left team {}", target, name))),
+                            None => Err(format!("{} is not on a team", target)),
+                        }
+                    }
+                    _ => Err(format!("Unknown action {}", action)),
+                }
+            }
+            "ban-ip" => {
+                let target = command.arg::<String>(0)?;
+                let address = match target.parse::<IpAddr>() {
+                    Ok(address) => address.to_canonical(),
+                    Err(_) => self
+                        .find_player(&target)
+                        .and_then(|id| self.players.get(&id).map(|p| p.address))
+                        .ok_or_else(|| format!("{} is neither an address nor a player", target))?,
+                };
+                if address.is_loopback() {
+                    return Err("Local connections can't be banned".to_string());
+                }
+
+                self.banned_ips
+                    .add(&address.to_string())
+                    .await
+                    .map_err(|e| format!("Failed to save the ban list: {}", e))?;
+                let kicked = self
+                    .kick_address(address, "You are banned from this server")
+                    .await;
+                Ok(Some(format!(
+                    "Banned {}, {} players kicked",
+                    address, kicked
+                )))
+            }
+            "pardon-ip" => {
+                let address = command
+                    .arg::<IpAddr>(0)
+                    .map_err(|_| "Argument 1 is not a valid address".to_string())?
+                    .to_canonical();
+                let removed = self
+                    .banned_ips
+                    .remove(&address.to_string())
+                    .await
+                    .map_err(|e| format!("Failed to save the ban list: {}", e))?;
+                if !removed {
+                    return Err(format!("{} is not banned", address));
+                }
+                Ok(Some(format!("Unbanned {}", address)))
+            }
+            "ban" => {
+                let target = command.arg::<String>(0)?;
+                let reason = command
+                    .rest(1)
+                    .unwrap_or_else(|_| DEFAULT_BAN_REASON.to_string());
+                let online = self.find_player(&target);
+                let (name, uuid) = match online.and_then(|id| self.players.get(&id)) {
+                    Some(info) => (Some(info.username.clone()), info.uuid),
+                    None => match target.parse::<Uuid>() {
+                        Ok(uuid) => (None, uuid),
+                        Err(_) => (Some(target.clone()), Player::offline_uuid(&target)),
+                    },
+                };
+
+                self.banned_players
+                    .add(Ban {
+                        uuid: uuid.to_string(),
+                        name,
+                        reason: reason.clone(),
+                        created: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs()),
+                    })
+                    .await
+                    .map_err(|e| format!("Failed to save the ban list: {}", e))?;
+                if let Some(id) = online {
+                    let reason = format!("You are banned from this server: {}", reason);
+                    self.send_player_event(id, PlayerEvent::Kick { reason });
+                }
+                Ok(Some(format!("Banned {}: {}", target, reason)))
+            }
+            "pardon" => {
+                let target = command.arg::<String>(0)?;
+                let removed = self
+                    .banned_players
+                    .remove(&target)
+                    .await
+                    .map_err(|e| format!("Failed to save the ban list: {}", e))?;
+                match removed {
+                    Some(_) => Ok(Some(format!("Unbanned {}", target))),
+                    None => Err(format!("{} is not banned", target)),
+                }
+            }
+            "region" => {
+                let action = command.arg::<String>(0)?;
+                match action.as_str() {
+                    "define" => {
+                        let name = command.arg::<String>(1)?;
+                        let (a, b) = (command.pos_arg(2)?, command.pos_arg(5)?);
+                        let defined = self
+                            .regions
+                            .define(&name, a, b)
+                            .await
+                            .map_err(|e| format!("Failed to save the regions: {}", e))?;
+                        if !defined {
+                            return Err(format!("Region {} already exists", name));
+                        }
+                        Ok(Some(format!(
+                            "Region {} defined, add owners to let players build in it",
+                            name
+                        )))
+                    }
+                    "addowner" => {
+                        let name = command.arg::<String>(1)?;
+                        let owner = command.arg::<String>(2)?;
+                        let added = self
+                            .regions
+                            .add_owner(&name, &owner)
+                            .await
+                            .map_err(|e| format!("Failed to save the regions: {}", e))?;
+                        if !added {
+                            return Err(format!("There is no region {}", name));
+                        }
+                        Ok(Some(format!("{} now owns region {}", owner, name)))
+                    }
+                    _ => Err(format!("Unknown action {}", action)),
+                }
+            }
+            "lookup" => {
+                let location = command.pos_arg(0)?;
+                let block_log = self
+                    .block_log
+                    .as_ref()
+                    .ok_or_else(|| "The block log is off".to_string())?;
+                let entries = block_log
+                    .lookup(location, LOOKUP_LIMIT)
+                    .await
+                    .map_err(|e| format!("Failed to read the block log: {}", e))?;
+                if entries.is_empty() {
+                    return Ok(Some("No changes logged at that position".to_string()));
+                }
+
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                let mut text = format!(
+                    "Latest changes at {} {} {}:",
+                    location.x, location.y, location.z
+                );
+                for entry in entries {
+                    text += &format!(
+                        "\n§7 {} ago§r: {} {} {}:{} -> {}:{}",
+                        format_age(now.saturating_sub(entry.time)),
+                        entry.player,
+                        entry.action(),
+                        block_id!(entry.old_state),
+                        block_meta!(entry.old_state),
+                        block_id!(entry.new_state),
+                        block_meta!(entry.new_state),
+                    );
+                }
+                Ok(Some(text))
+            }
+            "banlist" => {
+                let bans = self.banned_players.bans();
+                let mut text = format!("There are {} banned players:", bans.len());
+                for ban in bans {
+                    let name = ban.name.as_deref().unwrap_or(&ban.uuid);
+                    text += &format!("\n§7 {}§r: {}", name, ban.reason);
+                }
+                Ok(Some(text))
+            }
+            "worldborder" => {
+                let mut border = self.world_border();
+                let action = command.arg::<String>(0)?;
+                match action.as_str() {
+                    "get" => {
+                        return Ok(Some(format!(
+                            "The world border is {} blocks wide around {} {}",
+                            border.radius * 2.0,
+                            border.center_x,
+                            border.center_z
+                        )))
+                    }
+                    "set" => {
+                        let radius = command.arg::<f64>(1)?;
+                        if !(1.0..=MAX_BORDER_RADIUS).contains(&radius) {
+                            return Err(format!(
+                                "The radius must be between 1 and {}",
+                                MAX_BORDER_RADIUS
+                            ));
+                        }
+                        border.radius = radius;
+                    }
+                    "center" => {
+                        border.center_x = command.arg::<f64>(1)?;
+                        border.center_z = command.arg::<f64>(2)?;
+                    }
+                    _ => return Err(format!("Unknown action {}", action)),
+                }
+
+                self.set_world_border(border)
+                    .await
+                    .map_err(|e| format!("Failed to send the world border: {}", e))?;
+                Ok(Some(format!(
+                    "The world border is now {} blocks wide around {} {}",
+                    border.radius * 2.0,
+                    border.center_x,
+                    border.center_z
+                )))
+            }
+            "whitelist" => {
+                let action = command.arg::<String>(0)?;
+                match action.as_str() {
+                    "add" | "remove" => {
+                        // Names are not case sensitive, UUIDs are kept in their usual form
+                        let target = command.arg::<String>(1)?;
+                        let entry = match target.parse::<Uuid>() {
+                            Ok(uuid) => uuid.to_string(),
+                            Err(_) => target.to_lowercase(),
+                        };
+
+                        let changed = if action == "add" {
+                            self.whitelist.add(&entry).await
+                        } else {
+                            self.whitelist.remove(&entry).await
+                        }
+                        .map_err(|e| format!("Failed to save the whitelist: {}", e))?;
+
+                        match (action.as_str(), changed) {
+                            ("add", true) => Ok(Some(format!("Added {} to the whitelist", target))),
+                            ("add", false) => Err(format!("{} is already whitelisted", target)),
+                            (_, true) => Ok(Some(format!("Removed {} from the whitelist", target))),
+                            (_, false) => Err(format!("{} is not whitelisted", target)),
+                        }
+                    }
+                    "list" => {
+                        let entries = self.whitelist.entries();
+                        Ok(Some(format!(
+                            "There are {} whitelisted players: {}",
+                            entries.len(),
+                            entries.join(", ")
+                        )))
+                    }
+                    "on" => {
+                        self.whitelist_enabled.store(true, Ordering::SeqCst);
+                        let mut message = "Whitelist turned on".to_string();
+                        if self.whitelist.is_empty() {
+                            message += ", §cbut it is empty so only ops can join";
+                        }
+                        Ok(Some(message))
+                    }
+                    "off" => {
+                        self.whitelist_enabled.store(false, Ordering::SeqCst);
+                        Ok(Some("Whitelist turned off".to_string()))
+                    }
+                    _ => Err(format!("Unknown action {}", action)),
+                }
+            }
+            "list" => {
+                let names = self.player_names();
+                Ok(Some(format!(
+                    "There are {} of {} players online: {}",
+                    names.len(),
+                    self.config.slots,
+                    names.join(", ")
+                )))
+            }
+            "say" => {
+                let message = translate_color_codes(&command.rest(0)?);
+                info!("[Server] {}", message);
+                let packet = chat_packet!(1, format!("§d[Server] {}", message));
+                if let Err(e) = self.send_broadcast(packet).await {
+                    error!("Failed to send announcement: {}", e);
+                }
+                Ok(None)
+            }
+            "stop" => {
+                self.shutdown();
+                Ok(Some("Stopping the server".to_string()))
+            }
+            _ => Err(format!("{}: Unknown command.", command.name())),
+        }
+    }
+
+    /// Whether the player may join. Everyone may if the whitelist is off.
+    pub fn is_whitelisted(&self, username: &str, uuid: Uuid) -> bool {
+        !self.whitelist_enabled.load(Ordering::SeqCst)
+            || self.whitelist.contains(&username.to_lowercase())
+            || self.whitelist.contains(&uuid.to_string())
+    }
+
+    pub fn world_border(&self) -> WorldBorder {
+        *self.world_border.read().unwrap()
+    }
+
+    /// Changes the world border. Players outside are moved in with their next movement.
+    pub async fn set_world_border(&self, border: WorldBorder) -> io::Result<()> {
+        *self.world_border.write().unwrap() = border;
+        self.send_broadcast(border.init_packet()).await
+    }
+
+    pub fn is_ip_banned(&self, address: IpAddr) -> bool {
+        self.banned_ips
+            .contains(&address.to_canonical().to_string())
+    }
+
+    /// Kicks all players connected from the address and returns how many there were
+    async fn kick_address(&self, address: IpAddr, reason: &str) -> usize {
+        let targets: Vec<i32> = self
+            .players
+            .iter()
+            .filter(|p| p.address.to_canonical() == address)
+            .map(|p| *p.key())
+            .collect();
+
+        for &id in &targets {
+            let reason = reason.to_string();
+            self.send_player_event(id, PlayerEvent::Kick { reason });
+        }
+        targets.len()
+    }
+
+    /// Reads the name of an existing team
+    fn team_arg(&self, command: &Command<'_>, idx: usize) -> Result<String, String> {
+        let name = command.arg::<String>(idx)?;
+        if !self.teams.contains_key(&name) {
+            return Err(format!("Team {} not found", name));
+        }
+        Ok(name)
+    }
+
+    fn parse_chat_color(name: &str) -> Result<u8, String> {
+        chat_color(name).ok_or_else(|| {
+            format!(
+                "Unknown color {}, known colors are: {}",
+                name,
+                CHAT_COLORS.join(", ")
+            )
+        })
+    }
+
+    /// Asks all clients to disconnect and the server to stop accepting connections
+    pub fn shutdown(&self) {
+        let _ = self.shutdown_tx.send(true);
+    }
+
+    pub fn subscribe_shutdown(&self) -> watch::Receiver<bool> {
+        self.shutdown_tx.subscribe()
+    }
+
     pub async fn send_broadcast(&self, packet: Packet) -> io::Result<()> {
-        match self.broadcast_tx.send(packet).await {
+        let tx = if packet.is_priority() {
+            &self.priority_tx
+        } else {
+            &self.broadcast_tx
+        };
+        match tx.send(packet).await {
             Ok(_) => Ok(()),
             Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
         }
     }
 
-    async fn run_broker_loop(&self, mut rx: mpsc::Receiver<Packet>) {
-        while let Some(packet) = rx.recv().await {
-            for c in &self.clients {
-                c.send(packet.clone())
-                    .await
-                    .expect("Failed to send packet to client");
+    async fn run_tick_loop(&self) {
+        let mut interval = time::interval(TICK_DURATION);
+        let mut tick: u64 = 0;
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.tick(tick).await {
+                error!("Server tick failed: {}", e);
+            }
+            tick += 1;
+        }
+    }
+
+    async fn tick(&self, tick: u64) -> io::Result<()> {
+        if tick.is_multiple_of(MOB_SPAWN_INTERVAL) {
+            self.despawn_entities();
+            self.spawn_mobs();
+        }
+        self.update_entities(tick).await
+    }
+
+    /// Ticks every entity. Mob AI only runs once per spread ticks and is bounded per tick to
+    /// limit the cost, everything else is ticked every tick.
+    async fn update_entities(&self, tick: u64) -> io::Result<()> {
+        let mut packets = Vec::new();
+        let mut removed = Vec::new();
+        let mut teleports = Vec::new();
+        let mut moved = Vec::new();
+        let mut num_mob_updates = 0;
+        for mut entity in self.entities.iter_mut() {
+            if let WorldEntity::Mob(mob) = entity.value() {
+                if mob.eid as u64 % MOB_AI_SPREAD != tick % MOB_AI_SPREAD
+                    || num_mob_updates >= MAX_MOB_UPDATES_PER_TICK
+                {
+                    continue;
+                }
+                num_mob_updates += 1;
+            }
+
+            let old_chunk = entity.chunk_pos();
+            let update = entity.tick(&self.world);
+            let new_chunk = entity.chunk_pos();
+            if new_chunk != old_chunk {
+                moved.push((*entity.key(), old_chunk, new_chunk));
+            }
+            packets.extend(update.packets);
+            if update.removed {
+                removed.push(*entity.key());
+            }
+            teleports.extend(update.teleport);
+        }
+
+        // Only now that the entities aren't locked anymore, see `entities_near`
+        for (eid, old_chunk, new_chunk) in moved {
+            self.unindex_entity(eid, old_chunk);
+            self.entity_chunks.entry(new_chunk).or_default().insert(eid);
+        }
+        for packet in packets {
+            self.send_broadcast(packet).await?;
+        }
+        self.remove_entities(&removed);
+        for (player, position) in teleports {
+            self.send_player_event(player, PlayerEvent::Teleport { position });
+        }
+        Ok(())
+    }
+
+    fn spawn_mobs(&self) {
+        if self.config.mob_cap_per_chunk == 0 {
+            return;
+        }
+
+        let positions: Vec<Vec3d> = self.players.iter().map(|p| p.position).collect();
+        for position in positions {
+            if let Some(mob) = self.try_create_mob(position) {
+                self.spawn_entity(WorldEntity::Mob(mob));
+            }
+        }
+    }
+
+    /// Tries to find a spot for a passive mob in a random chunk near the position
+    fn try_create_mob(&self, near: Vec3d) -> Option<Mob> {
+        let mut rng = rand::thread_rng();
+        let r = MOB_SPAWN_RADIUS.min(self.config.view_dist);
+        let center = ChunkPos::from_block_pos(near.x.floor() as i32, near.z.floor() as i32);
+        let chunk_pos = ChunkPos::new(
+            center.x + rng.gen_range(-r..=r),
+            center.z + rng.gen_range(-r..=r),
+        );
+
+        let ids: Vec<i32> = self
+            .entity_chunks
+            .get(&chunk_pos)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default();
+        let num_mobs = ids
+            .iter()
+            .filter(|eid| {
+                self.entities
+                    .get(eid)
+                    .is_some_and(|e| matches!(e.value(), WorldEntity::Mob(_)))
+            })
+            .count();
+        if num_mobs >= self.config.mob_cap_per_chunk {
+            return None;
+        }
+
+        let x = rng.gen_range(0..16);
+        let z = rng.gen_range(0..16);
+        let chunk_ref = self.world.get_chunk(chunk_pos)?;
+        let chunk = lock_chunk(&chunk_ref);
+        if !PASSIVE_MOB_BIOMES.contains(&chunk.get_biome(x, z)) {
+            return None;
+        }
+
+        // Animals stand on grass, possibly with a plant on top
+        let mut y = chunk.get_highest_block(x, z)?;
+        if matches!(block_id!(chunk.get_block(x, y, z)), 31 | 37 | 38) {
+            y -= 1;
+        }
+        if block_id!(chunk.get_block(x, y, z)) != 2 {
+            return None;
+        }
+
+        let kind = PASSIVE_MOB_KINDS[rng.gen_range(0..PASSIVE_MOB_KINDS.len())];
+        let position = Vec3d {
+            x: (chunk_pos.x * 16 + x) as f64 + 0.5,
+            y: (y + 1) as f64,
+            z: (chunk_pos.z * 16 + z) as f64 + 0.5,
+        };
+        Some(Mob::new(self.new_id(), kind, position))
+    }
+
+    /// Removes mobs and projectiles that are out of view distance of every player, as their
+    /// chunks are not sent to anyone anymore
+    fn despawn_entities(&self) {
+        let r = self.config.view_dist;
+        let player_chunks: Vec<ChunkPos> = self
+            .players
+            .iter()
+            .map(|p| {
+                ChunkPos::from_block_pos(p.position.x.floor() as i32, p.position.z.floor() as i32)
+            })
+            .collect();
+
+        let despawned: Vec<i32> = self
+            .entities
+            .iter()
+            .filter(|e| {
+                let pos = e.chunk_pos();
+                e.despawns_out_of_view()
+                    && !player_chunks
+                        .iter()
+                        .any(|p| (p.x - pos.x).abs() <= r && (p.z - pos.z).abs() <= r)
+            })
+            .map(|e| *e.key())
+            .collect();
+        self.remove_entities(&despawned);
+    }
+
+    /// Hands broadcasts to the clients. Priority packets go first, so chat isn't stuck
+    /// behind a burst of movement or block updates. Delivering never waits for a client.
+    async fn run_broker_loop(
+        &self,
+        mut bulk_rx: mpsc::Receiver<Packet>,
+        mut priority_rx: mpsc::Receiver<Packet>,
+    ) {
+        loop {
+            let packet = select! {
+                biased;
+                Some(packet) = priority_rx.recv() => packet,
+                Some(packet) = bulk_rx.recv() => packet,
+                else => break,
+            };
+
+            for id in self.broadcast_targets(&packet) {
+                self.deliver(id, packet.clone());
+            }
+        }
+    }
+}
+
+/// Traces the event bus, which helps when writing a subscriber
+async fn log_events(mut events: broadcast::Receiver<GameEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(GameEvent::BlocksChanged { player, changes }) => {
+                debug!("Event: {} changed {} blocks", player, changes.len());
+            }
+            Ok(GameEvent::PlayerJoined { id, username }) => {
+                debug!("Event: {} joined as entity {}", username, id);
+            }
+            Ok(GameEvent::PlayerLeft { id, username }) => {
+                debug!("Event: {} (entity {}) left", username, id);
+            }
+            Ok(GameEvent::ChunkGenerated { pos }) => {
+                debug!("Event: chunk {} {} generated", pos.x, pos.z);
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                debug!("Event log skipped {} events", n);
             }
+            Err(broadcast::error::RecvError::Closed) => break,
         }
     }
 }
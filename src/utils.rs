@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 #[macro_export]
 macro_rules! chat_packet {
     ($pos: expr, $msg: expr) => {
@@ -7,3 +9,72 @@ macro_rules! chat_packet {
         }
     };
 }
+
+/// Message shown above the hotbar instead of in the chat box
+#[macro_export]
+macro_rules! action_bar {
+    ($msg: expr) => {
+        chat_packet!(2, $msg)
+    };
+}
+
+/// Turns `&` color codes, which players can type, into `§` codes
+pub fn translate_color_codes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(code) if c == '&' && "0123456789abcdefklmnor".contains(*code) => result.push('§'),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Removes `§` color codes, for the console and from player input
+pub fn strip_colors(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Token bucket that allows bursts of `capacity` actions and `rate` actions per second
+/// on average
+pub struct RateLimiter {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: f64, rate: f64) -> RateLimiter {
+        RateLimiter {
+            capacity,
+            rate,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is left, returns false if the action is over the limit
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
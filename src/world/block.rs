@@ -0,0 +1,489 @@
+use rand::Rng;
+
+use crate::{
+    block_id, block_meta, block_state,
+    model::{ItemStack, Vec3d},
+    world::{registry::registry, BlockFace},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolKind {
+    Pickaxe,
+    Shovel,
+    Axe,
+    Shears,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tool {
+    pub kind: ToolKind,
+    pub harvest_level: u8,
+    pub speed: f32,
+}
+
+impl Tool {
+    fn new(kind: ToolKind, harvest_level: u8, speed: f32) -> Tool {
+        Tool {
+            kind,
+            harvest_level,
+            speed,
+        }
+    }
+
+    pub fn from_item(item: ItemStack) -> Option<Tool> {
+        use ToolKind::*;
+        match item.id {
+            270 => Some(Tool::new(Pickaxe, 0, 2.0)),
+            274 => Some(Tool::new(Pickaxe, 1, 4.0)),
+            257 => Some(Tool::new(Pickaxe, 2, 6.0)),
+            278 => Some(Tool::new(Pickaxe, 3, 8.0)),
+            285 => Some(Tool::new(Pickaxe, 0, 12.0)),
+            269 => Some(Tool::new(Shovel, 0, 2.0)),
+            273 => Some(Tool::new(Shovel, 1, 4.0)),
+            256 => Some(Tool::new(Shovel, 2, 6.0)),
+            277 => Some(Tool::new(Shovel, 3, 8.0)),
+            284 => Some(Tool::new(Shovel, 0, 12.0)),
+            271 => Some(Tool::new(Axe, 0, 2.0)),
+            275 => Some(Tool::new(Axe, 1, 4.0)),
+            258 => Some(Tool::new(Axe, 2, 6.0)),
+            279 => Some(Tool::new(Axe, 3, 8.0)),
+            286 => Some(Tool::new(Axe, 0, 12.0)),
+            359 => Some(Tool::new(Shears, 0, 5.0)),
+            _ => None,
+        }
+    }
+}
+
+/// Hardness of a block as in vanilla, or None if it cannot be broken
+pub fn hardness(block_state: u16) -> Option<f32> {
+    registry().info(block_state).and_then(|b| b.hardness)
+}
+
+/// The tool kind that mines a block faster
+fn effective_tool(block_state: u16) -> Option<ToolKind> {
+    match block_id!(block_state) {
+        1
+        | 4
+        | 14..=16
+        | 21..=24
+        | 41..=45
+        | 48
+        | 49
+        | 52
+        | 56
+        | 57
+        | 61
+        | 62
+        | 67
+        | 71
+        | 73
+        | 74
+        | 79
+        | 87
+        | 98
+        | 101
+        | 108
+        | 109
+        | 112..=114
+        | 116
+        | 121
+        | 128
+        | 129
+        | 133
+        | 139
+        | 145
+        | 152..=156
+        | 158
+        | 159
+        | 167
+        | 168
+        | 172..=174
+        | 179..=182 => Some(ToolKind::Pickaxe),
+        2 | 3 | 12 | 13 | 60 | 78 | 80 | 82 | 88 | 110 => Some(ToolKind::Shovel),
+        5
+        | 17
+        | 25
+        | 47
+        | 53
+        | 54
+        | 58
+        | 63
+        | 64
+        | 65
+        | 68
+        | 84..=86
+        | 91
+        | 96
+        | 99
+        | 100
+        | 103
+        | 107
+        | 125
+        | 126
+        | 134..=136
+        | 146
+        | 162..=164
+        | 183..=197 => Some(ToolKind::Axe),
+        18 | 30 | 35 | 161 => Some(ToolKind::Shears),
+        _ => None,
+    }
+}
+
+/// The tool and level needed to get anything out of a block, if it needs a tool at all
+fn required_harvest_level(block_state: u16) -> Option<(ToolKind, u8)> {
+    match block_id!(block_state) {
+        49 => Some((ToolKind::Pickaxe, 3)),
+        14 | 41 | 56 | 57 | 73 | 74 | 129 | 133 => Some((ToolKind::Pickaxe, 2)),
+        15 | 21 | 22 | 42 | 101 | 167 => Some((ToolKind::Pickaxe, 1)),
+        1
+        | 4
+        | 16
+        | 23
+        | 24
+        | 43..=45
+        | 48
+        | 52
+        | 61
+        | 62
+        | 67
+        | 71
+        | 87
+        | 98
+        | 108
+        | 109
+        | 112..=114
+        | 116
+        | 121
+        | 128
+        | 139
+        | 145
+        | 152..=156
+        | 158
+        | 159
+        | 168
+        | 172
+        | 173
+        | 179..=182 => Some((ToolKind::Pickaxe, 0)),
+        78 | 80 => Some((ToolKind::Shovel, 0)),
+        30 => Some((ToolKind::Shears, 0)),
+        _ => None,
+    }
+}
+
+/// Whether breaking the block with the given item yields a drop
+pub fn can_harvest(block_state: u16, item: ItemStack) -> bool {
+    match required_harvest_level(block_state) {
+        Some((kind, level)) => match Tool::from_item(item) {
+            Some(tool) => tool.kind == kind && tool.harvest_level >= level,
+            None => false,
+        },
+        None => true,
+    }
+}
+
+/// Number of ticks it takes to break a block with the given item, or None if it is unbreakable
+pub fn dig_ticks(block_state: u16, item: ItemStack) -> Option<u32> {
+    let hardness = hardness(block_state)?;
+    if hardness == 0.0 {
+        return Some(0);
+    }
+
+    let speed = match Tool::from_item(item) {
+        Some(tool) if Some(tool.kind) == effective_tool(block_state) => tool.speed,
+        _ => 1.0,
+    };
+    let divisor = if can_harvest(block_state, item) {
+        30.0
+    } else {
+        100.0
+    };
+
+    let progress_per_tick = speed / hardness / divisor;
+    Some((1.0 / progress_per_tick).ceil() as u32)
+}
+
+/// Items dropped when breaking a block in survival with the given item
+pub fn drops(block_state: u16, item: ItemStack) -> Vec<ItemStack> {
+    if !can_harvest(block_state, item) {
+        return Vec::new();
+    }
+
+    let id = block_id!(block_state);
+    let meta = block_meta!(block_state);
+    let mut rng = rand::thread_rng();
+    let uses_shears = item.id == 359;
+    let stack = |id: i16, count: u8, damage: u16| vec![ItemStack { id, count, damage }];
+
+    match id {
+        // Blocks that never drop anything
+        0
+        | 7..=11
+        | 20
+        | 34
+        | 36
+        | 51
+        | 52
+        | 79
+        | 90
+        | 92
+        | 95
+        | 97
+        | 99
+        | 100
+        | 102
+        | 104
+        | 105
+        | 119
+        | 120
+        | 137
+        | 160
+        | 166 => Vec::new(),
+
+        // Plants that need shears
+        31 | 32 | 106 => {
+            if uses_shears {
+                stack(id as i16, 1, meta)
+            } else if id == 31 && rng.gen_ratio(1, 8) {
+                stack(295, 1, 0)
+            } else {
+                Vec::new()
+            }
+        }
+        18 | 161 => {
+            if uses_shears {
+                stack(id as i16, 1, meta & 3)
+            } else if rng.gen_ratio(1, 20) {
+                let sapling_meta = if id == 18 { meta & 3 } else { 4 + (meta & 1) };
+                stack(6, 1, sapling_meta)
+            } else if id == 18 && meta & 3 == 0 && rng.gen_ratio(1, 200) {
+                stack(260, 1, 0)
+            } else {
+                Vec::new()
+            }
+        }
+        175 => {
+            // Only the lower half drops, grass and ferns need shears
+            if meta & 8 != 0 || (!uses_shears && matches!(meta, 2 | 3)) {
+                Vec::new()
+            } else {
+                stack(175, 1, meta)
+            }
+        }
+
+        // Blocks that turn into something else
+        1 if meta == 0 => stack(4, 1, 0),
+        2 | 60 | 110 => stack(3, 1, 0),
+        3 => stack(3, 1, if meta == 1 { 1 } else { 0 }),
+        13 if rng.gen_ratio(1, 10) => stack(318, 1, 0),
+        16 => stack(263, 1, 0),
+        21 => stack(351, rng.gen_range(4..=8), 4),
+        30 => stack(287, 1, 0),
+        47 => stack(340, 3, 0),
+        55 => stack(331, 1, 0),
+        56 => stack(264, 1, 0),
+        59 if meta == 7 => {
+            let mut items = stack(296, 1, 0);
+            items.extend(stack(295, rng.gen_range(1..=3), 0));
+            items
+        }
+        59 => stack(295, 1, 0),
+        73 | 74 => stack(331, rng.gen_range(4..=5), 0),
+        78 => stack(332, meta as u8 + 1, 0),
+        80 => stack(332, 4, 0),
+        82 => stack(337, 4, 0),
+        83 => stack(338, 1, 0),
+        89 => stack(348, rng.gen_range(2..=4), 0),
+        103 => stack(360, rng.gen_range(3..=7), 0),
+        115 => stack(372, 1, 0),
+        117 => stack(379, 1, 0),
+        118 => stack(380, 1, 0),
+        127 => stack(351, 3, 3),
+        129 => stack(388, 1, 0),
+        130 => stack(49, 8, 0),
+        140 => stack(390, 1, 0),
+        141 => stack(391, 1, 0),
+        142 => stack(392, 1, 0),
+        144 => stack(397, 1, 0),
+        153 => stack(406, 1, 0),
+        169 => stack(410, rng.gen_range(2..=3), 0),
+
+        // Double slabs drop two slabs
+        43 => stack(44, 2, meta & 7),
+        125 => stack(126, 2, meta & 7),
+        181 => stack(182, 2, meta & 7),
+
+        _ => vec![pick_item(block_state)],
+    }
+}
+
+/// The item that places a block state, as picked with the middle mouse button
+pub fn pick_item(block_state: u16) -> ItemStack {
+    let id = block_id!(block_state);
+    let meta = block_meta!(block_state);
+    let (item_id, damage) = match id {
+        // Placed as a block, but held as an item
+        26 => (355, 0),
+        55 => (331, 0),
+        59 => (295, 0),
+        63 | 68 => (323, 0),
+        64 => (324, 0),
+        71 => (330, 0),
+        83 => (338, 0),
+        93 | 94 => (356, 0),
+        104 => (361, 0),
+        105 => (362, 0),
+        115 => (372, 0),
+        117 => (379, 0),
+        118 => (380, 0),
+        127 => (351, 3),
+        132 => (287, 0),
+        140 => (390, 0),
+        141 => (391, 0),
+        142 => (392, 0),
+        144 => (397, 0),
+        149 | 150 => (404, 0),
+        193..=197 => (427 + (id - 193) as i16, 0),
+
+        // Lit variants become their unlit block
+        62 => (61, 0),
+        74 => (73, 0),
+        75 => (76, 0),
+        124 => (123, 0),
+
+        // Double slabs become single slabs
+        43 => (44, meta & 7),
+        125 => (126, meta & 7),
+        181 => (182, meta & 7),
+
+        // Only part of the meta is the item variant
+        6 | 44 | 126 | 182 => (id as i16, meta & 7),
+        17 | 18 => (id as i16, meta & 3),
+        161 | 162 => (id as i16, meta & 1),
+
+        // Blocks whose meta only stores orientation or state
+        23
+        | 27
+        | 28
+        | 29
+        | 33
+        | 50
+        | 53
+        | 54
+        | 61
+        | 65..=67
+        | 69
+        | 70
+        | 72
+        | 76
+        | 77
+        | 84
+        | 86
+        | 91
+        | 96
+        | 101
+        | 107..=109
+        | 114
+        | 116
+        | 123
+        | 128
+        | 131
+        | 134..=136
+        | 143
+        | 145..=148
+        | 151
+        | 154
+        | 156
+        | 157
+        | 158
+        | 163
+        | 164
+        | 167
+        | 170
+        | 178
+        | 180
+        | 183..=187 => (id as i16, 0),
+
+        _ => (id as i16, meta),
+    };
+
+    ItemStack {
+        id: item_id,
+        count: 1,
+        damage,
+    }
+}
+
+pub fn is_door(block_state: u16) -> bool {
+    matches!(block_id!(block_state), 64 | 71 | 193..=197)
+}
+
+/// Whether a block consists of a lower half and an upper half with meta 8
+pub fn is_two_high(block_state: u16) -> bool {
+    is_door(block_state) || block_id!(block_state) == 175
+}
+
+/// Adjusts the meta of a block about to be placed for directional blocks, based on the
+/// clicked face, the cursor position on that face and where the player is looking
+pub fn oriented_state(block_state: u16, face: BlockFace, cursor: Vec3d, yaw: f32) -> u16 {
+    let id = block_id!(block_state);
+    let meta = block_meta!(block_state);
+
+    // Direction the player is looking at: 0 south, 1 west, 2 north, 3 east
+    let facing = ((yaw * 4.0 / 360.0) + 0.5).floor() as i32 & 3;
+    let is_side = !matches!(face, BlockFace::PosY | BlockFace::NegY);
+    let upper_half = face == BlockFace::NegY || (is_side && cursor.y > 0.5);
+
+    let meta = match id {
+        // Logs by axis
+        17 | 162 => {
+            let axis = match face {
+                BlockFace::PosX | BlockFace::NegX => 4,
+                BlockFace::PosZ | BlockFace::NegZ => 8,
+                _ => 0,
+            };
+            (meta & 3) | axis
+        }
+
+        // Stairs ascend away from the player and can be placed upside down
+        53 | 67 | 108 | 109 | 114 | 128 | 134..=136 | 156 | 163 | 164 | 180 => {
+            let direction = match facing {
+                0 => 2,
+                1 => 1,
+                2 => 3,
+                _ => 0,
+            };
+            direction | if upper_half { 4 } else { 0 }
+        }
+
+        // Slabs go into the half that was clicked
+        44 | 126 | 182 => (meta & 7) | if upper_half { 8 } else { 0 },
+
+        // Torches attach to the clicked face
+        50 | 75 | 76 => match face {
+            BlockFace::PosX => 1,
+            BlockFace::NegX => 2,
+            BlockFace::PosZ => 3,
+            BlockFace::NegZ => 4,
+            _ => 5,
+        },
+
+        // Doors open away from the player, the hinge side is kept simple
+        64 | 71 | 193..=197 => ((facing + 3) & 3) as u16,
+
+        // Ladders hang on the side that was clicked
+        65 if is_side => face as u16,
+
+        // Containers face the player
+        23 | 54 | 61 | 62 | 130 | 146 | 158 => match facing {
+            0 => 2,
+            1 => 5,
+            2 => 3,
+            _ => 4,
+        },
+        86 | 91 => ((facing + 2) & 3) as u16,
+
+        _ => meta,
+    };
+
+    block_state!(id, meta)
+}
@@ -0,0 +1,49 @@
+/// Chunk (or section) coordinate of a world coordinate. Rounds towards negative
+/// infinity, so -1 is in chunk -1 and not in chunk 0.
+pub fn world_to_chunk(coord: i32) -> i32 {
+    coord >> 4
+}
+
+/// Coordinate inside the chunk (or section), always in `0..16`. For negative
+/// coordinates this is the same as `rem_euclid(16)`, e.g. -1 becomes 15.
+pub fn world_to_local(coord: i32) -> i32 {
+    coord & 0x0f
+}
+
+/// Index of a block in section data, from coordinates inside the section
+pub fn local_to_index(x: i32, y: i32, z: i32) -> usize {
+    (x + 16 * (z + 16 * y)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_of_negative_coords() {
+        assert_eq!(world_to_chunk(-1), -1);
+        assert_eq!(world_to_chunk(-16), -1);
+        assert_eq!(world_to_chunk(-17), -2);
+    }
+
+    #[test]
+    fn local_of_negative_coords() {
+        assert_eq!(world_to_local(-1), 15);
+        assert_eq!(world_to_local(-16), 0);
+        assert_eq!(world_to_local(-17), 15);
+    }
+
+    #[test]
+    fn chunk_border_at_16() {
+        assert_eq!((world_to_chunk(15), world_to_local(15)), (0, 15));
+        assert_eq!((world_to_chunk(16), world_to_local(16)), (1, 0));
+    }
+
+    #[test]
+    fn local_matches_rem_euclid() {
+        for coord in -100..100 {
+            assert_eq!(world_to_local(coord), coord.rem_euclid(16));
+            assert_eq!(world_to_chunk(coord) * 16 + world_to_local(coord), coord);
+        }
+    }
+}
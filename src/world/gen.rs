@@ -1,91 +1,672 @@
-use std::{panic, sync::Arc};
+use std::sync::{Arc, OnceLock, RwLock};
 
-use log::debug;
+use log::{debug, warn};
 use noise::{NoiseFn, Seedable, SuperSimplex};
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use crate::{
-    block_state,
-    config::{BiomeConfig, BiomeLayer, WorldGenConfig},
+    block_id, block_state,
+    config::{BiomeConfig, BiomeLayer, TerrainMode, WorldGenConfig},
     world::Chunk,
     world::World,
 };
 
-use super::{math::diff_opt, ChunkPos};
+use super::{
+    coords::{world_to_chunk, world_to_local},
+    is_liquid, is_solid, lock_chunk,
+    math::diff_opt,
+    registry,
+    structure::Structure,
+    BlockPos, CanReplace, ChunkPos,
+};
+
+/// Names of all features that can be referenced by a biome
+pub const FEATURES: &[&str] = &[
+    "grass",
+    "fern",
+    "bushes",
+    "dead_bushes",
+    "flowers",
+    "mushrooms",
+    "puddles",
+    "lilypads",
+    "boulders",
+    "cacti",
+    "icicles",
+    "warm_tree",
+    "cold_tree",
+    "jungle_tree",
+];
+
+/// Features that grow on the ground. They only go into air on top of a solid block, so
+/// they never replace a surface layer like snow, or stand on one.
+const PLANT_FEATURES: &[&str] = &[
+    "grass",
+    "fern",
+    "bushes",
+    "dead_bushes",
+    "flowers",
+    "mushrooms",
+];
+
+/// Height of the water surface in seas and rivers
+const SEA_LEVEL: i32 = 64;
+
+/// Water depth at which the seabed gets its full variation, shallower seas get less
+const SEABED_FADE_DEPTH: f64 = 8.0;
+
+/// Noise values around zero that form a trench in the seabed
+const TRENCH_WIDTH: f64 = 0.05;
+
+/// Highest layer that can contain bedrock
+const BEDROCK_HEIGHT: i32 = 3;
+
+/// Lowest height of the seabed, so trenches don't cut into the bedrock
+const MIN_SEABED_HEIGHT: i32 = 8;
+
+/// Scale of the noise that covers the seabed with patches of sand and clay
+const SEABED_PATCH_SCALE: f64 = 0.06;
+
+/// Blocks between the points that noise is sampled at before interpolating
+const NOISE_GRID_SPACING: i32 = 4;
+
+/// Blocks that the members of a feature cluster may be away from its first one
+const FEATURE_CLUSTER_RADIUS: i32 = 3;
+
+/// Structures are placed at most once per region of this many chunks squared
+const STRUCTURE_REGION_SIZE: i32 = 8;
+
+/// Biomes of a chunk and of the `biome_smoothing` blocks around it, sampled once so that
+/// smoothing doesn't sample the neighborhood of every column again
+struct BiomeGrid<'a> {
+    radius: i32,
+    /// Side length of the grid
+    size: i32,
+    samples: Vec<(Climate, &'a BiomeConfig)>,
+    /// Summed-area table of the biome scales, with a leading row and column of zeros
+    scale_sums: Vec<f64>,
+}
+
+impl<'a> BiomeGrid<'a> {
+    fn sample(
+        gen: &WorldGenerator,
+        config: &'a WorldGenConfig,
+        base_x: i32,
+        base_z: i32,
+    ) -> BiomeGrid<'a> {
+        let radius = config.biome_smoothing;
+        let size = 16 + 2 * radius;
+        let (min_x, min_z) = (base_x - radius, base_z - radius);
+
+        // The climate only changes slowly, so it is sampled on a coarse lattice and
+        // interpolated. The lattice is aligned to world coordinates, so neighboring chunks
+        // see the same values.
+        let (lattice_x, lattice_z) = (
+            min_x.div_euclid(NOISE_GRID_SPACING),
+            min_z.div_euclid(NOISE_GRID_SPACING),
+        );
+        let lattice_size = (min_x + size - 1).div_euclid(NOISE_GRID_SPACING) - lattice_x + 2;
+        let lattice_size_z = (min_z + size - 1).div_euclid(NOISE_GRID_SPACING) - lattice_z + 2;
+        let mut lattice = Vec::with_capacity((lattice_size * lattice_size_z) as usize);
+        for lz in 0..lattice_size_z {
+            for lx in 0..lattice_size {
+                lattice.push(gen.sample_climate(
+                    config,
+                    (lattice_x + lx) * NOISE_GRID_SPACING,
+                    (lattice_z + lz) * NOISE_GRID_SPACING,
+                ));
+            }
+        }
+
+        let mut samples = Vec::with_capacity((size * size) as usize);
+        for z in min_z..min_z + size {
+            for x in min_x..min_x + size {
+                let lx = x.div_euclid(NOISE_GRID_SPACING) - lattice_x;
+                let lz = z.div_euclid(NOISE_GRID_SPACING) - lattice_z;
+                let at = |dx: i32, dz: i32| lattice[((lz + dz) * lattice_size + lx + dx) as usize];
+                let tx = x.rem_euclid(NOISE_GRID_SPACING) as f64 / NOISE_GRID_SPACING as f64;
+                let tz = z.rem_euclid(NOISE_GRID_SPACING) as f64 / NOISE_GRID_SPACING as f64;
+                let climate = Climate::lerp(
+                    Climate::lerp(at(0, 0), at(1, 0), tx),
+                    Climate::lerp(at(0, 1), at(1, 1), tx),
+                    tz,
+                );
+                samples.push((climate, climate.biome(config)));
+            }
+        }
+
+        let sums_size = (size + 1) as usize;
+        let mut scale_sums = vec![0.0; sums_size * sums_size];
+        for z in 0..size as usize {
+            for x in 0..size as usize {
+                let scale = samples[z * size as usize + x].1.scale;
+                scale_sums[(z + 1) * sums_size + x + 1] =
+                    scale + scale_sums[z * sums_size + x + 1] + scale_sums[(z + 1) * sums_size + x]
+                        - scale_sums[z * sums_size + x];
+            }
+        }
+
+        BiomeGrid {
+            radius,
+            size,
+            samples,
+            scale_sums,
+        }
+    }
+
+    /// Climate and biome of a column, in chunk coordinates
+    fn get(&self, x: i32, z: i32) -> (Climate, &'a BiomeConfig) {
+        self.samples[((z + self.radius) * self.size + x + self.radius) as usize]
+    }
+
+    /// Average biome scale of the square with the smoothing radius around a column
+    fn smoothed_scale(&self, x: i32, z: i32) -> f64 {
+        let sums_size = (self.size + 1) as usize;
+        let (x0, z0) = (x as usize, z as usize);
+        let (x1, z1) = (
+            x0 + 2 * self.radius as usize + 1,
+            z0 + 2 * self.radius as usize + 1,
+        );
+        let total = self.scale_sums[z1 * sums_size + x1]
+            - self.scale_sums[z0 * sums_size + x1]
+            - self.scale_sums[z1 * sums_size + x0]
+            + self.scale_sums[z0 * sums_size + x0];
+        let side = (2 * self.radius + 1) as f64;
+        total / (side * side)
+    }
+}
+
+/// Noise values that decide the biome of a column
+#[derive(Clone, Copy)]
+struct Climate {
+    elevation: f64,
+    temperature: f64,
+    moisture: f64,
+    river: f64,
+}
+
+impl Climate {
+    fn lerp(a: Climate, b: Climate, t: f64) -> Climate {
+        Climate {
+            elevation: lerp(a.elevation, b.elevation, t),
+            temperature: lerp(a.temperature, b.temperature, t),
+            moisture: lerp(a.moisture, b.moisture, t),
+            river: lerp(a.river, b.river, t),
+        }
+    }
+
+    fn biome(self, config: &WorldGenConfig) -> &BiomeConfig {
+        WorldGenerator::determine_biome(
+            config,
+            self.temperature,
+            self.moisture,
+            self.elevation,
+            self.river,
+        )
+    }
+
+    /// River biome that fits the climate, for land that a river channel cuts into
+    fn river_biome(self, config: &WorldGenConfig) -> &BiomeConfig {
+        WorldGenerator::determine_biome(
+            config,
+            self.temperature,
+            self.moisture,
+            self.elevation.max(config.ocean_level),
+            0.0,
+        )
+    }
+
+    /// How much a river lowers the terrain, from 0 outside of rivers to 1 in their middle
+    fn river_carve(self, config: &WorldGenConfig) -> f64 {
+        let t = (1.0 - self.river / config.river_width).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+/// 3D noise of a chunk, sampled on a lattice and interpolated like the climate. Holds
+/// `N` noise values per point.
+struct NoiseGrid<const N: usize> {
+    /// Indexed by (y * 5 + z) * 5 + x
+    values: Vec<[f64; N]>,
+}
+
+impl<const N: usize> NoiseGrid<N> {
+    const SIZE: i32 = 16 / NOISE_GRID_SPACING + 1;
+    const HEIGHT: i32 = 256 / NOISE_GRID_SPACING + 1;
+
+    /// Calls `noise` with the world coordinates of every lattice point
+    fn sample(base_x: i32, base_z: i32, noise: impl Fn(i32, i32, i32) -> [f64; N]) -> Self {
+        let mut values = Vec::with_capacity((Self::SIZE * Self::SIZE * Self::HEIGHT) as usize);
+        for ly in 0..Self::HEIGHT {
+            for lz in 0..Self::SIZE {
+                for lx in 0..Self::SIZE {
+                    values.push(noise(
+                        base_x + lx * NOISE_GRID_SPACING,
+                        ly * NOISE_GRID_SPACING,
+                        base_z + lz * NOISE_GRID_SPACING,
+                    ));
+                }
+            }
+        }
+        NoiseGrid { values }
+    }
+
+    /// Noise values at a block, in chunk coordinates
+    fn get(&self, x: i32, y: i32, z: i32) -> [f64; N] {
+        let (lx, ly, lz) = (
+            x / NOISE_GRID_SPACING,
+            y / NOISE_GRID_SPACING,
+            z / NOISE_GRID_SPACING,
+        );
+        let spacing = NOISE_GRID_SPACING as f64;
+        let (tx, ty, tz) = (
+            (x % NOISE_GRID_SPACING) as f64 / spacing,
+            (y % NOISE_GRID_SPACING) as f64 / spacing,
+            (z % NOISE_GRID_SPACING) as f64 / spacing,
+        );
+        let at = |dx: i32, dy: i32, dz: i32| {
+            self.values[(((ly + dy) * Self::SIZE + lz + dz) * Self::SIZE + lx + dx) as usize]
+        };
+        let lerp_n =
+            |a: [f64; N], b: [f64; N], t: f64| std::array::from_fn(|i| lerp(a[i], b[i], t));
+        let plane = |dy: i32| {
+            lerp_n(
+                lerp_n(at(0, dy, 0), at(1, dy, 0), tx),
+                lerp_n(at(0, dy, 1), at(1, dy, 1), tx),
+                tz,
+            )
+        };
+        lerp_n(plane(0), plane(1), ty)
+    }
+}
+
+/// Leaves only grow into air and plants, so they don't carve into terrain or other trees
+fn leaves_can_replace(block_state: u16) -> bool {
+    !is_solid(block_state) && !is_liquid(block_state)
+}
+
+/// Trunks may grow through leaves of other trees, but not through terrain or logs
+fn trunk_can_replace(block_state: u16) -> bool {
+    // A plain function can't hold the id, and this runs for every trunk block
+    static LEAVES: OnceLock<u16> = OnceLock::new();
+    let leaves = *LEAVES.get_or_init(|| block_id!(registry::state("leaves", 0)));
+    leaves_can_replace(block_state) || block_id!(block_state) == leaves
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// State that is local to the generation of a single chunk
+struct ChunkContext<'a> {
+    config: &'a WorldGenConfig,
+    rng: StdRng,
+    /// Receives blocks that fall outside of the chunk, if any
+    world: Option<&'a World>,
+    /// Both cave noises
+    caves: NoiseGrid<2>,
+    /// Terrain density, only sampled in the density terrain mode
+    density: Option<NoiseGrid<1>>,
+    /// The configured sea block
+    sea_block: u16,
+}
+
+/// Blocks that the generator places, looked up once instead of by name for every block
+struct GenBlocks {
+    stone: u16,
+    bedrock: u16,
+    lava: u16,
+    water: u16,
+    dirt: u16,
+    grass: u16,
+    sand: u16,
+    clay: u16,
+    cobblestone: u16,
+    tall_grass: u16,
+    fern: u16,
+    bush: u16,
+    dead_bush: u16,
+    flower: u16,
+    mushroom: u16,
+    lily_pad: u16,
+    boulder: u16,
+    cactus: u16,
+    packed_ice: u16,
+    /// Log and leaves of warm, cold and jungle trees
+    trees: [(u16, u16); 3],
+}
+
+impl GenBlocks {
+    fn lookup() -> GenBlocks {
+        GenBlocks {
+            stone: registry::state("stone", 0),
+            bedrock: registry::state("bedrock", 0),
+            lava: registry::state("lava", 0),
+            water: registry::state("water", 0),
+            dirt: registry::state("dirt", 0),
+            grass: registry::state("grass", 0),
+            sand: registry::state("sand", 0),
+            clay: registry::state("clay", 0),
+            cobblestone: registry::state("cobblestone", 0),
+            tall_grass: registry::state("tallgrass", 1),
+            fern: registry::state("tallgrass", 2),
+            bush: registry::state("leaves", 3),
+            dead_bush: registry::state("deadbush", 0),
+            flower: registry::state("red_flower", 0),
+            mushroom: registry::state("brown_mushroom", 0),
+            lily_pad: registry::state("waterlily", 0),
+            boulder: registry::state("stone", 5),
+            cactus: registry::state("cactus", 0),
+            packed_ice: registry::state("packed_ice", 0),
+            trees: [0, 1, 3].map(|meta| {
+                (
+                    registry::state("log", meta),
+                    registry::state("leaves", meta),
+                )
+            }),
+        }
+    }
+}
 
 pub struct WorldGenerator {
-    config: WorldGenConfig,
+    seed: u64,
+    config: RwLock<Arc<WorldGenConfig>>,
     world: Arc<World>,
     noise: SuperSimplex,
+    house: Structure,
+    blocks: GenBlocks,
 }
 
 impl WorldGenerator {
-    pub fn new(seed: u32, config: WorldGenConfig, world: Arc<World>) -> WorldGenerator {
+    pub fn new(seed: u64, config: WorldGenConfig, world: Arc<World>) -> WorldGenerator {
         debug!("Using seed {} for world generation", seed);
 
+        // The noise generator only supports 32-bit seeds, so fold the upper half in
+        let noise_seed = (seed ^ (seed >> 32)) as u32;
+
         WorldGenerator {
-            config,
+            seed,
+            config: RwLock::new(Arc::new(config)),
             world,
-            noise: SuperSimplex::new().set_seed(seed),
+            noise: SuperSimplex::new().set_seed(noise_seed),
+            house: Self::build_house(),
+            blocks: GenBlocks::lookup(),
         }
     }
 
+    /// Replaces the generator config. Only chunks generated afterwards are affected,
+    /// chunks that are currently being generated keep using the previous config.
+    pub fn update_config(&self, config: WorldGenConfig) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+
     pub fn generate_chunk(&self, chunk_x: i32, chunk_z: i32) {
         let pos = ChunkPos::new(chunk_x, chunk_z);
+        let config = self.config.read().unwrap().clone();
+
+        // Generate without holding the chunk lock, so that async tasks reading
+        // the chunk never wait for the generator
+        let mut generated = Chunk::new(chunk_x, chunk_z);
+        self.generate_into_chunk(&config, &mut generated, true);
 
         match self.world.get_chunk(pos) {
             Some(chunk) => {
-                let mut chunk = chunk.lock().unwrap();
-                self.generate_into_chunk(&mut *chunk);
-            }
-            None => {
-                let mut chunk = Chunk::new(chunk_x, chunk_z);
-                self.generate_into_chunk(&mut chunk);
-                self.world.insert_chunk(chunk);
+                *lock_chunk(&chunk) = generated;
+                self.world.apply_pending_edits(pos);
             }
+            None => self.world.insert_chunk(generated),
         }
     }
 
-    fn generate_into_chunk(&self, chunk: &mut Chunk) {
+    /// Generates a chunk without touching the shared world, e.g. for tooling
+    pub fn generate_chunk_standalone(&self, chunk_x: i32, chunk_z: i32) -> Chunk {
+        let config = self.config.read().unwrap().clone();
+        let mut chunk = Chunk::new(chunk_x, chunk_z);
+        self.generate_into_chunk(&config, &mut chunk, false);
+        chunk
+    }
+
+    fn generate_into_chunk(&self, config: &WorldGenConfig, chunk: &mut Chunk, in_world: bool) {
         let base_x = chunk.x << 4;
         let base_z = chunk.z << 4;
+        let mut ctx = ChunkContext {
+            config,
+            rng: StdRng::seed_from_u64(self.chunk_seed(chunk.x, chunk.z)),
+            world: if in_world { Some(&self.world) } else { None },
+            caves: NoiseGrid::sample(base_x, base_z, |x, y, z| {
+                [
+                    self.sample_cave_noise_fractal(config, x, y, z),
+                    self.sample_cave_noise_fractal(config, x, y - 16384, z),
+                ]
+            }),
+            density: (config.terrain_mode == TerrainMode::Density).then(|| {
+                NoiseGrid::sample(base_x, base_z, |x, y, z| {
+                    [self.sample_density_noise_fractal(config, x, y, z)]
+                })
+            }),
+            sea_block: registry::state(&config.sea_block, 0),
+        };
+
+        let biomes = BiomeGrid::sample(self, config, base_x, base_z);
 
+        // Biome and surface height of every column, indexed by z * 16 + x
+        let mut columns = Vec::with_capacity(256);
         for z in 0..16 {
             for x in 0..16 {
-                let world_x = base_x + x;
-                let world_z = base_z + z;
+                columns.push(self.generate_column(&mut ctx, &biomes, chunk, x, z));
+            }
+        }
 
-                self.generate_column(chunk, x, z, world_x, world_z)
+        // Features come after the terrain, so clusters can reach into any column
+        for z in 0..16 {
+            for x in 0..16 {
+                self.generate_features(&mut ctx, chunk, &columns, x, z);
             }
         }
+
+        self.generate_structures(&ctx, chunk);
     }
 
-    fn generate_column(&self, chunk: &mut Chunk, x: i32, z: i32, world_x: i32, world_z: i32) {
-        let (elevation, biome) = self.sample_biome(world_x, world_z);
-        let interp_scale =
-            self.multi_sample_biome_scale(world_x, world_z, self.config.biome_smoothing);
+    /// Sets a block given in world coordinates, if `can_replace` accepts the block that
+    /// is there. Blocks outside of the chunk are handed to the world, which applies them
+    /// once their chunk is generated.
+    fn place_block(
+        ctx: &ChunkContext,
+        chunk: &mut Chunk,
+        x: i32,
+        y: i32,
+        z: i32,
+        block_state: u16,
+        can_replace: CanReplace,
+    ) {
+        if !(0..256).contains(&y) {
+            return;
+        }
 
-        let noise_val = elevation * interp_scale;
-        let terrain_height = (noise_val * 16.0) as i32 + 64;
-        let generate_height = if biome.sea_level { 64 } else { terrain_height };
-        let mut top_layer_height = 0;
-        let mut top_layer_state = 0;
+        if world_to_chunk(x) == chunk.x && world_to_chunk(z) == chunk.z {
+            let (lx, lz) = (world_to_local(x), world_to_local(z));
+            if can_replace(chunk.get_block(lx, y, lz)) {
+                chunk.set_block(lx, y, lz, block_state);
+            }
+        } else if let Some(world) = ctx.world {
+            world.set_block_deferred_if(BlockPos::new(x, y, z), block_state, can_replace);
+        }
+    }
 
-        // Convert heightmap to blocks
-        for y in 0..=generate_height {
-            let block_state =
-                self.determine_block(world_x, y, world_z, terrain_height, generate_height, biome);
+    fn generate_structures(&self, ctx: &ChunkContext, chunk: &mut Chunk) {
+        if ctx.config.structure_chance <= 0.0 {
+            return;
+        }
 
-            if block_state != 0 {
-                top_layer_height = y + 1;
-                top_layer_state = block_state;
-                chunk.set_block(x, y, z, block_state);
+        // Every region decides on its own structure, so the decision is the same
+        // no matter which of its chunks is generated first
+        let region_x = chunk.x.div_euclid(STRUCTURE_REGION_SIZE);
+        let region_z = chunk.z.div_euclid(STRUCTURE_REGION_SIZE);
+        let mut rng =
+            StdRng::seed_from_u64(self.chunk_seed(region_x, region_z) ^ 0x5851_F42D_4C95_7F2D);
+        if !rng.gen_bool(ctx.config.structure_chance) {
+            return;
+        }
+
+        let origin_chunk_x =
+            region_x * STRUCTURE_REGION_SIZE + rng.gen_range(0..STRUCTURE_REGION_SIZE);
+        let origin_chunk_z =
+            region_z * STRUCTURE_REGION_SIZE + rng.gen_range(0..STRUCTURE_REGION_SIZE);
+        if origin_chunk_x != chunk.x || origin_chunk_z != chunk.z {
+            return;
+        }
+
+        let x = rng.gen_range(0..16);
+        let z = rng.gen_range(0..16);
+        let ground_y = match Self::find_ground(chunk, x, z) {
+            Some(y) => y,
+            None => return,
+        };
+
+        let structure = &self.house;
+        let origin = BlockPos::new((chunk.x << 4) + x, ground_y + 1, (chunk.z << 4) + z);
+
+        // Build a foundation so that the structure does not float on slopes
+        for sz in 0..structure.size_z {
+            for sx in 0..structure.size_x {
+                for y in (origin.y - 4)..origin.y {
+                    let state = self.blocks.cobblestone;
+                    Self::place_block(ctx, chunk, origin.x + sx, y, origin.z + sz, state, |_| true);
+                }
+            }
+        }
+
+        for (pos, block_state) in structure.blocks_at(origin) {
+            Self::place_block(ctx, chunk, pos.x, pos.y, pos.z, block_state, |_| true);
+        }
+    }
+
+    /// Finds the height of the solid ground in a column, ignoring plants and snow.
+    /// Returns `None` if the column is not suitable for building, e.g. water or trees.
+    fn find_ground(chunk: &Chunk, x: i32, z: i32) -> Option<i32> {
+        for y in (0..256).rev() {
+            match block_id!(chunk.get_block(x, y, z)) {
+                0 | 31 | 32 | 37 | 38 | 39 | 78 => continue,
+                8 | 9 | 10 | 11 | 17 | 18 | 79 | 81 => return None,
+                _ if y < 64 => return None,
+                _ => return Some(y),
+            }
+        }
+        None
+    }
+
+    /// A small house made of planks, with log pillars, windows and an open door
+    fn build_house() -> Structure {
+        let mut house = Structure::new(7, 6, 7);
+        for y in 0..house.size_y {
+            for z in 0..house.size_z {
+                for x in 0..house.size_x {
+                    let edge_x = x == 0 || x == house.size_x - 1;
+                    let edge_z = z == 0 || z == house.size_z - 1;
+                    let center_x = x == house.size_x / 2;
+                    let center_z = z == house.size_z / 2;
+
+                    let block_state = match y {
+                        0 => registry::state("cobblestone", 0),
+                        1..=3 if edge_x && edge_z => registry::state("log", 0),
+                        1 | 2 if center_x && z == 0 => 0,
+                        2 if (edge_x && center_z) || (center_x && edge_z) => {
+                            registry::state("glass", 0)
+                        }
+                        1..=3 if edge_x || edge_z => registry::state("planks", 0),
+                        4 => registry::state("planks", 0),
+                        5 if !edge_x && !edge_z => registry::state("wooden_slab", 0),
+                        _ => 0,
+                    };
+                    house.set_block(x, y, z, block_state);
+                }
+            }
+        }
+        house.set_block(1, 1, 1, registry::state("torch", 5));
+        house
+    }
+
+    /// Derives a seed for the random features of a chunk, so that the same world
+    /// seed always yields the same terrain regardless of generation order
+    fn chunk_seed(&self, chunk_x: i32, chunk_z: i32) -> u64 {
+        self.seed
+            ^ (chunk_x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (chunk_z as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+    }
+
+    /// Generates the terrain of a column and returns its biome and surface height
+    fn generate_column<'a>(
+        &self,
+        ctx: &mut ChunkContext<'a>,
+        biomes: &BiomeGrid<'a>,
+        chunk: &mut Chunk,
+        x: i32,
+        z: i32,
+    ) -> (&'a BiomeConfig, i32) {
+        let (climate, mut biome) = biomes.get(x, z);
+        let interp_scale = biomes.smoothed_scale(x, z);
+
+        let noise_val = climate.elevation * interp_scale;
+        let mut terrain_height = (noise_val * 16.0) as i32 + SEA_LEVEL;
+
+        // Rivers pull the terrain down towards their bed, which cuts a channel through
+        // the land. Land that ends up below the water becomes part of the river.
+        let carve = climate.river_carve(ctx.config);
+        if carve > 0.0 && biome.layer != BiomeLayer::Sea {
+            let river_bed = (SEA_LEVEL - ctx.config.river_depth) as f64;
+            terrain_height = lerp(terrain_height as f64, river_bed, carve).round() as i32;
+            if biome.layer == BiomeLayer::Land && terrain_height < SEA_LEVEL {
+                biome = climate.river_biome(ctx.config);
+            }
+        }
+
+        // Beaches follow the water line, so gentle coasts get wide ones. The height limit
+        // varies a little between columns, so the sand doesn't end in a straight line.
+        let shore_height = SEA_LEVEL + ctx.config.beach_height + ctx.rng.gen_range(0..=1);
+        if biome.layer == BiomeLayer::Land
+            && carve == 0.0
+            && climate.elevation < ctx.config.ocean_level + ctx.config.beach_width
+            && terrain_height <= shore_height
+        {
+            biome = &ctx.config.biomes["beach"];
+        }
+
+        let world_x = (chunk.x << 4) + x;
+        let world_z = (chunk.z << 4) + z;
+        let under_sea = biome.layer == BiomeLayer::Sea && terrain_height < SEA_LEVEL;
+        if under_sea {
+            terrain_height = self.seabed_height(ctx.config, world_x, world_z, terrain_height);
+        }
+        let (top_layer_height, top_layer_state) = if ctx.density.is_some() && !biome.sea_level {
+            self.generate_density_column(ctx, chunk, x, z, terrain_height, biome)
+        } else {
+            self.generate_heightmap_column(ctx, chunk, x, z, terrain_height, biome)
+        };
+
+        // Fill the sea from the seabed up, below any cover that isn't water, like ice
+        if biome.sea_level && terrain_height < SEA_LEVEL {
+            for y in terrain_height..=SEA_LEVEL {
+                let block = chunk.get_block(x, y, z);
+                if block == 0 || block == self.blocks.water {
+                    chunk.set_block(x, y, z, ctx.sea_block);
+                }
+            }
+        }
+
+        // Cover the seabed with patches of sand and clay
+        if under_sea {
+            if let Some(patch) = self.seabed_patch(world_x, world_z) {
+                for y in terrain_height - 2..terrain_height {
+                    if chunk.get_block(x, y, z) != 0 {
+                        chunk.set_block(x, y, z, patch);
+                    }
+                }
             }
         }
 
         // Let grass grow on top level dirt
-        if top_layer_state == block_state!(3, 0) {
-            chunk.set_block(x, top_layer_height, z, block_state!(2, 0));
+        if top_layer_state == self.blocks.dirt {
+            chunk.set_block(x, top_layer_height, z, self.blocks.grass);
         }
 
         // Apply surface layer
@@ -98,96 +679,279 @@ impl WorldGenerator {
             );
         }
 
-        // Generate features
-        for (feature, prob) in &biome.features {
-            if self.should_generate_feature(*prob) {
-                self.generate_feature(feature, chunk, x, top_layer_height, z);
+        // Set biome
+        chunk.set_biome(x, z, biome.id);
+        (biome, top_layer_height)
+    }
+
+    /// Fills a column up to the heightmap surface. Returns the height and state of the
+    /// top block.
+    fn generate_heightmap_column(
+        &self,
+        ctx: &mut ChunkContext,
+        chunk: &mut Chunk,
+        x: i32,
+        z: i32,
+        terrain_height: i32,
+        biome: &BiomeConfig,
+    ) -> (i32, u16) {
+        let world_x = (chunk.x << 4) + x;
+        let world_z = (chunk.z << 4) + z;
+        // Water is filled up to the water line, shores above it follow the terrain
+        let generate_height = if biome.sea_level
+            && (terrain_height < SEA_LEVEL || biome.layer == BiomeLayer::River)
+        {
+            SEA_LEVEL
+        } else {
+            terrain_height
+        };
+        let mut top_layer = (0, 0);
+        for y in 0..=generate_height {
+            let block_state = self.determine_block(
+                ctx,
+                world_x,
+                y,
+                world_z,
+                terrain_height,
+                generate_height,
+                biome,
+            );
+
+            if block_state != 0 {
+                top_layer = (y + 1, block_state);
+                chunk.set_block(x, y, z, block_state);
             }
         }
+        top_layer
+    }
 
-        // Set biome
-        chunk.set_biome(x, z, biome.id);
+    /// Fills a column from the density noise, where the threshold for solid blocks rises
+    /// with the height above the heightmap surface. This can leave air below solid blocks,
+    /// so every solid run gets its own surface layers. Returns the height and state of the
+    /// top block.
+    fn generate_density_column(
+        &self,
+        ctx: &mut ChunkContext,
+        chunk: &mut Chunk,
+        x: i32,
+        z: i32,
+        terrain_height: i32,
+        biome: &BiomeConfig,
+    ) -> (i32, u16) {
+        let world_x = (chunk.x << 4) + x;
+        let world_z = (chunk.z << 4) + z;
+        let squash = ctx.config.density_squash;
+        let top = (terrain_height + squash.ceil() as i32).min(255);
+
+        let mut top_layer = (0, 0);
+        // Surface of the solid run that the current block is in
+        let mut surface = None;
+        for y in (0..=top).rev() {
+            let [density] = ctx.density.as_ref().unwrap().get(x, y, z);
+            let threshold = (y - terrain_height) as f64 / squash;
+            if y > 0 && density <= threshold {
+                surface = None;
+                continue;
+            }
+
+            let surface = *surface.get_or_insert(y);
+            let block_state =
+                self.determine_block(ctx, world_x, y, world_z, surface, surface, biome);
+            if block_state != 0 {
+                if top_layer.1 == 0 {
+                    top_layer = (y + 1, block_state);
+                }
+                chunk.set_block(x, y, z, block_state);
+            }
+        }
+        top_layer
+    }
+
+    /// Adds bumps and trenches to the floor of a sea, the deeper the water the more
+    fn seabed_height(
+        &self,
+        config: &WorldGenConfig,
+        world_x: i32,
+        world_z: i32,
+        terrain_height: i32,
+    ) -> i32 {
+        let noise = self.sample_noise_fractal(
+            config,
+            world_x + 16384,
+            -world_z,
+            config.seabed_scale,
+            config.elevation_lac,
+        );
+        let trench = (1.0 - noise.abs() / TRENCH_WIDTH).max(0.0);
+        let offset = noise * config.seabed_variation + trench * trench * config.trench_depth;
+        let fade = ((SEA_LEVEL - terrain_height) as f64 / SEABED_FADE_DEPTH).min(1.0);
+        (terrain_height - (offset * fade).round() as i32).clamp(MIN_SEABED_HEIGHT, SEA_LEVEL - 1)
+    }
+
+    /// Block of the seabed patch at a column, if there is one
+    fn seabed_patch(&self, world_x: i32, world_z: i32) -> Option<u16> {
+        let noise = self.noise.get([
+            world_x as f64 * SEABED_PATCH_SCALE,
+            world_z as f64 * SEABED_PATCH_SCALE - 16384.0,
+        ]);
+        if noise > 0.35 {
+            Some(self.blocks.sand)
+        } else if noise < -0.35 {
+            Some(self.blocks.clay)
+        } else {
+            None
+        }
+    }
+
+    /// Rolls the features of a column's biome. Clusters spread around the column, but
+    /// stay inside the chunk.
+    fn generate_features(
+        &self,
+        ctx: &mut ChunkContext,
+        chunk: &mut Chunk,
+        columns: &[(&BiomeConfig, i32)],
+        x: i32,
+        z: i32,
+    ) {
+        let (biome, _) = columns[(z * 16 + x) as usize];
+        for (feature, feature_config) in &biome.features {
+            if !Self::should_generate_feature(ctx.config, &mut ctx.rng, feature_config.chance()) {
+                continue;
+            }
+
+            let (min, max) = feature_config.count();
+            let count = ctx.rng.gen_range(min..=max);
+            for i in 0..count {
+                let (fx, fz) = if i == 0 {
+                    (x, z)
+                } else {
+                    (
+                        x + ctx
+                            .rng
+                            .gen_range(-FEATURE_CLUSTER_RADIUS..=FEATURE_CLUSTER_RADIUS),
+                        z + ctx
+                            .rng
+                            .gen_range(-FEATURE_CLUSTER_RADIUS..=FEATURE_CLUSTER_RADIUS),
+                    )
+                };
+                if !(0..16).contains(&fx) || !(0..16).contains(&fz) {
+                    continue;
+                }
+                let (_, top_y) = columns[(fz * 16 + fx) as usize];
+                self.generate_feature(ctx, feature, chunk, fx, top_y, fz);
+            }
+        }
     }
 
-    fn generate_feature(&self, feature: &str, chunk: &mut Chunk, x: i32, top_y: i32, z: i32) {
-        let random_offset = rand::thread_rng().gen_range(-1..=1);
+    fn generate_feature(
+        &self,
+        ctx: &mut ChunkContext,
+        feature: &str,
+        chunk: &mut Chunk,
+        x: i32,
+        top_y: i32,
+        z: i32,
+    ) {
+        let random_offset = ctx.rng.gen_range(-1..=1);
+
+        // Terrain and its surface layer come first, then plants on top of it. Features that
+        // replace the ground skip it when something already stands on it, so no plant is
+        // left floating.
+        let free = chunk.get_block(x, top_y, z) == 0;
+        if PLANT_FEATURES.contains(&feature)
+            && !(free && is_solid(chunk.get_block(x, top_y - 1, z)))
+        {
+            return;
+        }
+        if matches!(feature, "puddles" | "boulders") && !free {
+            return;
+        }
+
         match feature {
             "grass" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(31, 1));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.tall_grass);
             }
             "fern" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(31, 2));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.fern);
             }
             "bushes" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(18, 3));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.bush);
             }
             "dead_bushes" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(32, 0));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.dead_bush);
             }
             "flowers" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(38, 0));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.flower);
             }
             "mushrooms" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(39, 0));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.mushroom);
             }
             "puddles" => {
-                chunk.set_block(x, top_y - 1, z, block_state!(9, 0));
+                chunk.set_block(x, top_y - 1, z, self.blocks.water);
             }
             "lilypads" => {
-                chunk.set_block_if_air(x, top_y, z, block_state!(111, 0));
+                chunk.set_block_if_air(x, top_y, z, self.blocks.lily_pad);
             }
             "boulders" => {
-                chunk.set_block(x, top_y - 1, z, block_state!(1, 5));
+                chunk.set_block(x, top_y - 1, z, self.blocks.boulder);
             }
             "cacti" => {
                 for i in 0..3 + random_offset {
-                    chunk.set_block(x, top_y + i, z, block_state!(81, 0));
+                    chunk.set_block(x, top_y + i, z, self.blocks.cactus);
                 }
             }
             "icicles" => {
                 for i in 0..3 + random_offset {
-                    chunk.set_block(x, top_y + i, z, block_state!(174, 0));
+                    chunk.set_block(x, top_y + i, z, self.blocks.packed_ice);
                 }
             }
             "warm_tree" => {
                 Self::generate_tree(
+                    ctx,
                     chunk,
                     x,
                     top_y,
                     z,
                     6 + random_offset,
-                    block_state!(17, 0),
-                    block_state!(18, 0),
+                    self.blocks.trees[0].0,
+                    self.blocks.trees[0].1,
                 );
             }
             "cold_tree" => {
                 Self::generate_tree(
+                    ctx,
                     chunk,
                     x,
                     top_y,
                     z,
                     6 + random_offset,
-                    block_state!(17, 1),
-                    block_state!(18, 1),
+                    self.blocks.trees[1].0,
+                    self.blocks.trees[1].1,
                 );
             }
             "jungle_tree" => {
-                let huge_tree = rand::thread_rng().gen_range(0..=10);
+                let huge_tree = ctx.rng.gen_range(0..=10);
                 Self::generate_tree(
+                    ctx,
                     chunk,
                     x,
                     top_y,
                     z,
                     9 + random_offset + huge_tree,
-                    block_state!(17, 3),
-                    block_state!(18, 3),
+                    self.blocks.trees[2].0,
+                    self.blocks.trees[2].1,
                 );
             }
-            _ => panic!("Unknown feature {}", feature),
+            // Features are validated when the config is loaded, this only guards against
+            // FEATURES getting out of sync with this match
+            _ => warn!("Skipping unknown feature {}", feature),
         }
     }
 
+    /// Generates a tree at the chunk-local position. The crown may extend into
+    /// neighboring chunks, which receive those blocks once they are generated.
     fn generate_tree(
+        ctx: &mut ChunkContext,
         chunk: &mut Chunk,
         x: i32,
         y: i32,
@@ -200,21 +964,38 @@ impl WorldGenerator {
             return;
         }
 
+        let world_x = (chunk.x << 4) + x;
+        let world_z = (chunk.z << 4) + z;
         for i in 0..height {
             if i > height - 5 {
                 let r = (height - i).min(2);
                 for zo in -r..=r {
                     for xo in -r..=r {
-                        if i < height - 2
-                            || xo * xo + zo * zo <= r * r + rand::thread_rng().gen_range(0..1)
-                        {
-                            chunk.set_block(x + xo, y + i, z + zo, leaves_block)
+                        if i < height - 2 || xo * xo + zo * zo <= r * r + ctx.rng.gen_range(0..1) {
+                            let (lx, ly, lz) = (world_x + xo, y + i, world_z + zo);
+                            Self::place_block(
+                                ctx,
+                                chunk,
+                                lx,
+                                ly,
+                                lz,
+                                leaves_block,
+                                leaves_can_replace,
+                            );
                         }
                     }
                 }
             }
             if i < height - 2 {
-                chunk.set_block(x, y + i, z, trunk_block);
+                Self::place_block(
+                    ctx,
+                    chunk,
+                    world_x,
+                    y + i,
+                    world_z,
+                    trunk_block,
+                    trunk_can_replace,
+                );
             }
         }
     }
@@ -232,33 +1013,25 @@ impl WorldGenerator {
         return false;
     }
 
-    fn should_generate_feature(&self, prob: f64) -> bool {
-        rand::thread_rng().gen_bool(prob)
+    /// Rolls a feature with its chance scaled by the feature density
+    fn should_generate_feature(config: &WorldGenConfig, rng: &mut StdRng, prob: f64) -> bool {
+        rng.gen_bool((prob * config.feature_density).clamp(0.0, 1.0))
     }
 
-    fn is_cave(&self, world_x: i32, y: i32, world_z: i32, h: i32) -> bool {
-        let n1 = self.sample_cave_noise_fractal(
-            world_x,
-            y,
-            world_z,
-            self.config.cave_scale,
-            self.config.cave_lac,
-        );
-        let n2 = self.sample_cave_noise_fractal(
-            world_x,
-            y - 16384,
-            world_z,
-            self.config.cave_scale,
-            self.config.cave_lac,
-        );
+    fn is_cave(ctx: &ChunkContext, world_x: i32, y: i32, world_z: i32, h: i32) -> bool {
+        let config = ctx.config;
+        let [n1, n2] = ctx
+            .caves
+            .get(world_to_local(world_x), y, world_to_local(world_z));
 
         let height_gradient = (y as f64) / (h as f64); // [0..1]
-        let cave_th = self.config.cave_grad_base + height_gradient * self.config.cave_grad_scale;
+        let cave_th = config.cave_grad_base + height_gradient * config.cave_grad_scale;
         n1.abs() > cave_th && n2.abs() > cave_th
     }
 
     fn determine_block(
         &self,
+        ctx: &mut ChunkContext,
         x: i32,
         y: i32,
         z: i32,
@@ -266,30 +1039,32 @@ impl WorldGenerator {
         gh: i32,
         biome: &BiomeConfig,
     ) -> u16 {
-        let is_bedrock = (y <= 3 && self.should_generate_feature(0.3)) || y == 0;
-        let can_cave = (!biome.sea_level || y < th - 3) && !is_bedrock;
-        let is_cave = y <= th && self.is_cave(x, y, z, th) && can_cave;
+        // Bedrock comes first, so no surface or cave can replace it. The bottom layer is
+        // always bedrock, the ones above get sparser.
+        if (y <= BEDROCK_HEIGHT && ctx.rng.gen_bool(0.3)) || y == 0 {
+            return self.blocks.bedrock;
+        }
+
+        let can_cave = !biome.sea_level || y < th - 3;
+        let is_cave = y <= th && Self::is_cave(ctx, x, y, z, th) && can_cave;
 
         if is_cave {
-            let cave_fill_block = if y <= 8 { 11 } else { 0 };
-            return block_state!(cave_fill_block, 0);
+            return if y <= 8 { self.blocks.lava } else { 0 };
         } else if y == gh {
             return block_state!(biome.blocks[0], 0);
         } else if y >= th {
             return block_state!(biome.blocks[1], 0);
         } else if y >= th - 3 {
             return block_state!(biome.blocks[2], 0);
-        } else if y > 3 {
-            return self.determine_ore(x, y, z);
-        } else if is_bedrock {
-            return block_state!(7, 0);
+        } else if y > BEDROCK_HEIGHT {
+            return self.determine_ore(ctx.config, x, y, z);
         } else {
-            return block_state!(1, 0);
+            return self.blocks.stone;
         }
     }
 
-    fn determine_ore(&self, x: i32, y: i32, z: i32) -> u16 {
-        for (_, ore) in &self.config.ores {
+    fn determine_ore(&self, config: &WorldGenConfig, x: i32, y: i32, z: i32) -> u16 {
+        for (_, ore) in &config.ores {
             let diff = (ore.center - (y as f64)).abs();
             if diff > ore.spread {
                 continue;
@@ -309,56 +1084,45 @@ impl WorldGenerator {
             }
         }
 
-        block_state!(1, 0)
+        self.blocks.stone
     }
 
-    fn multi_sample_biome_scale(&self, x: i32, z: i32, r: i32) -> f64 {
-        let mut total = 0.0;
-        let mut denom = 0.0;
-        for x_offset in -r..=r {
-            for z_offset in -r..=r {
-                total += self.sample_biome(x + x_offset, z + z_offset).1.scale;
-                denom += 1.0;
-            }
-        }
-        total / denom
-    }
-
-    fn sample_biome(&self, x: i32, z: i32) -> (f64, &BiomeConfig) {
+    fn sample_climate(&self, config: &WorldGenConfig, x: i32, z: i32) -> Climate {
         let elevation =
-            self.sample_noise_fractal(x, z, self.config.elevation_scale, self.config.elevation_lac);
+            self.sample_noise_fractal(config, x, z, config.elevation_scale, config.elevation_lac);
         let temperature = self.sample_noise_fractal(
+            config,
             -x,
             z,
-            self.config.temperature_scale,
-            self.config.temperature_lac,
+            config.temperature_scale,
+            config.temperature_lac,
         );
         let moisture =
-            self.sample_noise_fractal(x, -z, self.config.moisture_scale, self.config.moisture_lac);
+            self.sample_noise_fractal(config, x, -z, config.moisture_scale, config.moisture_lac);
         let river = (self
-            .sample_noise_fractal(-x, -z, self.config.river_scale, self.config.river_lac)
+            .sample_noise_fractal(config, -x, -z, config.river_scale, config.river_lac)
             .abs())
             * (elevation + 1.0)
             * 0.5;
 
-        (
+        Climate {
             elevation,
-            self.determine_biome(temperature, moisture, elevation, river),
-        )
+            temperature,
+            moisture,
+            river,
+        }
     }
 
     fn determine_biome(
-        &self,
+        config: &WorldGenConfig,
         temperature: f64,
         moisture: f64,
         elevation: f64,
         river: f64,
     ) -> &BiomeConfig {
-        let layer = if elevation >= self.config.ocean_level - 0.025 && river < 0.015 {
+        let layer = if elevation >= config.ocean_level - 0.025 && river < 0.015 {
             BiomeLayer::River
-        } else if (elevation - self.config.ocean_level).abs() < 0.025 {
-            return &self.config.biomes["beach"];
-        } else if elevation < self.config.ocean_level {
+        } else if elevation < config.ocean_level {
             BiomeLayer::Sea
         } else {
             BiomeLayer::Land
@@ -367,7 +1131,7 @@ impl WorldGenerator {
         let mut best_biome_name = "forest";
         let mut best_biome_dist = f64::MAX;
 
-        for (name, biome) in &self.config.biomes {
+        for (name, biome) in &config.biomes {
             if biome.layer != layer {
                 continue;
             }
@@ -382,27 +1146,58 @@ impl WorldGenerator {
             }
         }
 
-        return &self.config.biomes[best_biome_name];
+        return &config.biomes[best_biome_name];
     }
 
-    fn sample_noise_fractal(&self, x: i32, z: i32, mut scale: f64, lac: f64) -> f64 {
+    fn sample_noise_fractal(
+        &self,
+        config: &WorldGenConfig,
+        x: i32,
+        z: i32,
+        mut scale: f64,
+        lac: f64,
+    ) -> f64 {
         let mut result = 0.0;
         let mut denom = 0.0;
-        scale *= self.config.master_scale;
+        scale *= config.master_scale;
 
         let mut amplitude = 1.0;
-        for _ in 0..self.config.octaves {
+        for _ in 0..config.octaves {
             result += amplitude * self.noise.get([x as f64 * scale, z as f64 * scale]);
             denom += amplitude;
 
             scale *= lac;
-            amplitude *= self.config.falloff;
+            amplitude *= config.falloff;
         }
 
         result / denom
     }
 
-    fn sample_cave_noise_fractal(&self, x: i32, y: i32, z: i32, mut scale: f64, lac: f64) -> f64 {
+    fn sample_cave_noise_fractal(&self, config: &WorldGenConfig, x: i32, y: i32, z: i32) -> f64 {
+        self.sample_noise_fractal_3d(config, x, y, z, config.cave_scale, config.cave_lac)
+    }
+
+    /// Terrain density noise, offset so that it doesn't line up with the caves
+    fn sample_density_noise_fractal(&self, config: &WorldGenConfig, x: i32, y: i32, z: i32) -> f64 {
+        self.sample_noise_fractal_3d(
+            config,
+            x,
+            y + 16384,
+            z,
+            config.density_scale,
+            config.density_lac,
+        )
+    }
+
+    fn sample_noise_fractal_3d(
+        &self,
+        config: &WorldGenConfig,
+        x: i32,
+        y: i32,
+        z: i32,
+        mut scale: f64,
+        lac: f64,
+    ) -> f64 {
         let mut result = 0.0;
         let mut denom = 0.0;
 
@@ -415,9 +1210,183 @@ impl WorldGenerator {
             denom += amplitude;
 
             scale *= lac;
-            amplitude *= self.config.falloff;
+            amplitude *= config.falloff;
         }
 
         result / denom
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(seed: u64) -> WorldGenerator {
+        registry::load_for_tests();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/config/world.toml");
+        let config = WorldGenConfig::load(path).unwrap();
+        WorldGenerator::new(seed, config, Arc::new(World::new()))
+    }
+
+    #[test]
+    fn smoothed_scale_matches_naive_average() {
+        let gen = generator(1);
+        let config = gen.config.read().unwrap().clone();
+        let r = config.biome_smoothing;
+        for (chunk_x, chunk_z) in [(0, 0), (-3, 7), (12, -5)] {
+            let biomes = BiomeGrid::sample(&gen, &config, chunk_x * 16, chunk_z * 16);
+            for z in 0..16 {
+                for x in 0..16 {
+                    let mut total = 0.0;
+                    for dz in -r..=r {
+                        for dx in -r..=r {
+                            total += biomes.get(x + dx, z + dz).1.scale;
+                        }
+                    }
+                    let naive = total / ((2 * r + 1) * (2 * r + 1)) as f64;
+                    assert!((biomes.smoothed_scale(x, z) - naive).abs() < 1e-9);
+                }
+            }
+        }
+    }
+    /// Interpolating between lattice points must stay close to sampling every block, or
+    /// the terrain would look different from before the lattice was introduced
+    #[test]
+    fn interpolation_is_close_to_sampling_every_block() {
+        let gen = generator(1);
+        let config = gen.config.read().unwrap().clone();
+        for (chunk_x, chunk_z) in [(0, 0), (-3, 7)] {
+            let (base_x, base_z) = (chunk_x * 16, chunk_z * 16);
+            let biomes = BiomeGrid::sample(&gen, &config, base_x, base_z);
+            let noise = NoiseGrid::sample(base_x, base_z, |x, y, z| {
+                [
+                    gen.sample_cave_noise_fractal(&config, x, y, z),
+                    gen.sample_density_noise_fractal(&config, x, y, z),
+                ]
+            });
+
+            let mut max_error = [0.0f64; 2];
+            let mut total_error = [0.0; 2];
+            for z in 0..16 {
+                for x in 0..16 {
+                    let climate = gen.sample_climate(&config, base_x + x, base_z + z);
+                    assert!((biomes.get(x, z).0.elevation - climate.elevation).abs() < 0.02);
+
+                    for y in 0..256 {
+                        let (world_x, world_z) = (base_x + x, base_z + z);
+                        let exact = [
+                            gen.sample_cave_noise_fractal(&config, world_x, y, world_z),
+                            gen.sample_density_noise_fractal(&config, world_x, y, world_z),
+                        ];
+                        let interpolated = noise.get(x, y, z);
+                        for i in 0..2 {
+                            let error = (interpolated[i] - exact[i]).abs();
+                            max_error[i] = max_error[i].max(error);
+                            total_error[i] += error;
+                        }
+                    }
+                }
+            }
+
+            // Noise values are in [-1, 1]. Single blocks may be off where the finest
+            // octave changes quickly, but the terrain as a whole must match.
+            for i in 0..2 {
+                assert!(max_error[i] < 0.3, "max error {}", max_error[i]);
+                let mean_error = total_error[i] / (16 * 16 * 256) as f64;
+                assert!(mean_error < 0.05, "mean error {}", mean_error);
+            }
+        }
+    }
+
+    #[test]
+    fn standalone_chunk_matches_world_chunk() {
+        let gen = generator(1);
+        let chunk = gen.generate_chunk_standalone(3, -2);
+        assert!(gen.world.chunks.is_empty());
+        assert!(gen.world.pending_edits.is_empty());
+
+        // Nothing was generated around it, so no blocks reach in from other chunks
+        gen.generate_chunk(3, -2);
+        let world_chunk = gen.world.get_chunk(ChunkPos::new(3, -2)).unwrap();
+        assert_same_chunk(&chunk, &lock_chunk(&world_chunk));
+    }
+
+    fn assert_same_chunk(a: &Chunk, b: &Chunk) {
+        for y in 0..256 {
+            for z in 0..16 {
+                for x in 0..16 {
+                    assert_eq!(
+                        a.get_block(x, y, z),
+                        b.get_block(x, y, z),
+                        "block {} {} {} of chunk {} {}",
+                        x,
+                        y,
+                        z,
+                        a.x,
+                        a.z
+                    );
+                }
+            }
+        }
+        assert_eq!(a.biomes, b.biomes);
+    }
+
+    #[test]
+    fn generation_is_deterministic() {
+        let chunk = generator(7).generate_chunk_standalone(-5, 2);
+        assert_same_chunk(&chunk, &generator(7).generate_chunk_standalone(-5, 2));
+
+        // Blocks that reach across chunk borders end up the same in whatever order the
+        // chunks are generated
+        let chunks = [(0, 0), (1, 0), (0, 1), (1, 1), (-1, 0)];
+        let (first, second) = (generator(7), generator(7));
+        for (x, z) in chunks {
+            first.generate_chunk(x, z);
+        }
+        for (x, z) in chunks.into_iter().rev() {
+            second.generate_chunk(x, z);
+        }
+        for (x, z) in chunks {
+            let pos = ChunkPos::new(x, z);
+            assert_same_chunk(
+                &lock_chunk(&first.world.get_chunk(pos).unwrap()),
+                &lock_chunk(&second.world.get_chunk(pos).unwrap()),
+            );
+        }
+    }
+
+    #[test]
+    fn tree_crown_reaches_into_next_chunk() {
+        let gen = generator(1);
+        let config = gen.config.read().unwrap().clone();
+        let mut ctx = ChunkContext {
+            config: &config,
+            rng: StdRng::seed_from_u64(0),
+            world: Some(&gen.world),
+            caves: NoiseGrid::sample(0, 0, |_, _, _| [0.0; 2]),
+            density: None,
+            sea_block: gen.blocks.water,
+        };
+        let (log, leaves) = gen.blocks.trees[0];
+
+        // The trunk is at the last column of the chunk, the lowest crown layer is five wide
+        let mut chunk = Chunk::new(0, 0);
+        WorldGenerator::generate_tree(&mut ctx, &mut chunk, 15, 64, 8, 6, log, leaves);
+        assert_eq!(chunk.get_block(15, 64, 8), log);
+        assert_eq!(chunk.get_block(14, 66, 8), leaves);
+
+        gen.world.insert_chunk(Chunk::new(1, 0));
+        for x in 16..18 {
+            for z in 6..11 {
+                assert_eq!(
+                    gen.world.get_block(x, 66, z),
+                    leaves,
+                    "leaves at {} {}",
+                    x,
+                    z
+                );
+            }
+        }
+        assert_eq!(gen.world.get_block(18, 66, 8), 0);
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+
+use crate::block_id;
+
+use super::{coords::local_to_index, is_solid, registry::registry, Chunk};
+
+const MAX_LIGHT: u8 = 15;
+
+/// Light levels of a whole chunk, indexed like section data with the section index on top
+#[derive(Debug)]
+pub struct ChunkLight {
+    pub block: Vec<u8>,
+    pub sky: Vec<u8>,
+}
+
+impl ChunkLight {
+    /// Computes block and sky light of a chunk from scratch. Light from neighboring chunks
+    /// is not considered, so chunk borders can be a little darker than in vanilla.
+    ///
+    /// The client relights around every block change it receives, based on the light it
+    /// got with the chunk. Therefore the light sent has to match the blocks, otherwise
+    /// edits leave dark patches behind.
+    pub fn compute(chunk: &Chunk) -> ChunkLight {
+        let mut sky = vec![0u8; 16 * 256 * 16];
+        let mut block = vec![0u8; 16 * 256 * 16];
+        let mut sky_queue = VecDeque::new();
+        let mut block_queue = VecDeque::new();
+
+        // Sky light falls straight down until something absorbs it
+        for x in 0..16 {
+            for z in 0..16 {
+                let mut light = MAX_LIGHT;
+                for y in (0..256).rev() {
+                    light = light.saturating_sub(opacity(chunk.get_block(x, y, z)));
+                    if light == 0 {
+                        break;
+                    }
+                    let idx = local_to_index(x, y, z);
+                    sky[idx] = light;
+                    sky_queue.push_back(idx);
+                }
+            }
+        }
+
+        for (section_idx, section) in chunk.sections.iter().enumerate() {
+            let section = match section {
+                Some(section) if !section.is_empty() => section,
+                _ => continue,
+            };
+            for (i, &block_state) in section.data.iter().enumerate() {
+                let light = emission(block_state);
+                if light > 0 {
+                    let idx = section_idx * 4096 + i;
+                    block[idx] = light;
+                    block_queue.push_back(idx);
+                }
+            }
+        }
+
+        spread(chunk, &mut sky, sky_queue);
+        spread(chunk, &mut block, block_queue);
+        ChunkLight { block, sky }
+    }
+
+    /// Bitmask of the sections in which any block or sky light differs
+    pub fn changed_sections(&self, other: &ChunkLight) -> u16 {
+        (0..16).fold(0, |mask, i| {
+            let range = i * 4096..(i + 1) * 4096;
+            if self.block[range.clone()] != other.block[range.clone()]
+                || self.sky[range.clone()] != other.sky[range]
+            {
+                mask | 1 << i
+            } else {
+                mask
+            }
+        })
+    }
+}
+
+/// Writes the light of one section as nibbles, as the client expects them
+pub fn put_section_nibbles(data: &mut Vec<u8>, levels: &[u8], section_idx: usize) {
+    let section = &levels[section_idx * 4096..(section_idx + 1) * 4096];
+    data.extend(section.chunks(2).map(|pair| pair[0] | pair[1] << 4));
+}
+
+/// Spreads light to neighbors, losing at least one level per block
+fn spread(chunk: &Chunk, levels: &mut [u8], mut queue: VecDeque<usize>) {
+    const OFFSETS: [(i32, i32, i32); 6] = [
+        (1, 0, 0),
+        (-1, 0, 0),
+        (0, 1, 0),
+        (0, -1, 0),
+        (0, 0, 1),
+        (0, 0, -1),
+    ];
+
+    while let Some(idx) = queue.pop_front() {
+        let light = levels[idx];
+        let (x, y, z) = (
+            (idx & 0x0f) as i32,
+            (idx >> 8) as i32,
+            ((idx >> 4) & 0x0f) as i32,
+        );
+        for (dx, dy, dz) in OFFSETS {
+            let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+            if !(0..16).contains(&nx) || !(0..256).contains(&ny) || !(0..16).contains(&nz) {
+                continue;
+            }
+
+            let n = local_to_index(nx, ny, nz);
+            let next = light.saturating_sub(opacity(chunk.get_block(nx, ny, nz)).max(1));
+            if next > levels[n] {
+                levels[n] = next;
+                queue.push_back(n);
+            }
+        }
+    }
+}
+
+/// How much light a block absorbs
+fn opacity(block_state: u16) -> u8 {
+    match block_id!(block_state) {
+        // Glass and panes
+        20 | 95 | 102 | 160 => 0,
+        // Leaves and ice
+        18 | 79 | 161 => 1,
+        // Water
+        8 | 9 => 3,
+        _ if is_solid(block_state) => MAX_LIGHT,
+        _ => 0,
+    }
+}
+
+/// Light a block gives off
+fn emission(block_state: u16) -> u8 {
+    registry().info(block_state).map_or(0, |b| b.light)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::world::registry::{self, state};
+
+    /// A chunk covered by a stone layer at y = 70
+    fn covered_chunk() -> Chunk {
+        registry::load_for_tests();
+        let mut chunk = Chunk::new(0, 0);
+        for x in 0..16 {
+            for z in 0..16 {
+                chunk.set_block(x, 70, z, state("stone", 0));
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn sky_light_stops_at_solid_blocks() {
+        let light = covered_chunk().light();
+        assert_eq!(light.sky[local_to_index(8, 71, 8)], 15);
+        assert_eq!(light.sky[local_to_index(8, 70, 8)], 0);
+        assert_eq!(light.sky[local_to_index(8, 69, 8)], 0);
+    }
+
+    #[test]
+    fn opening_a_hole_relights_the_column() {
+        let mut chunk = covered_chunk();
+        let before = chunk.light();
+        chunk.set_block(8, 70, 8, 0);
+        let after = chunk.light();
+
+        assert_eq!(after.sky[local_to_index(8, 69, 8)], 15);
+        assert_eq!(after.sky[local_to_index(8, 0, 8)], 15);
+        assert_eq!(after.sky[local_to_index(9, 69, 8)], 14);
+        // Everything from the hole down to the bottom got lighter
+        assert_eq!(before.changed_sections(&after), 0b1_1111);
+    }
+
+    #[test]
+    fn torches_light_their_surroundings() {
+        let mut chunk = covered_chunk();
+        chunk.set_block(8, 40, 8, state("torch", 5));
+        let light = chunk.light();
+        assert_eq!(light.block[local_to_index(8, 40, 8)], 14);
+        assert_eq!(light.block[local_to_index(8, 41, 8)], 13);
+        assert_eq!(light.block[local_to_index(11, 40, 8)], 11);
+        assert_eq!(light.block[local_to_index(8, 80, 8)], 0);
+    }
+
+    #[test]
+    fn light_is_cached_until_a_block_changes() {
+        let mut chunk = covered_chunk();
+        let light = chunk.light();
+        assert!(Arc::ptr_eq(&light, &chunk.light()));
+
+        chunk.set_block(0, 100, 0, state("stone", 0));
+        assert!(!Arc::ptr_eq(&light, &chunk.light()));
+    }
+}
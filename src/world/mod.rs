@@ -1,14 +1,25 @@
+pub mod block;
+pub mod coords;
 pub mod gen;
+pub mod light;
 mod math;
+pub mod registry;
 pub mod sched;
+pub mod structure;
 
 use std::{
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use dashmap::DashMap;
 
+use self::{
+    coords::{local_to_index, world_to_chunk, world_to_local},
+    light::ChunkLight,
+    registry::registry,
+};
+
 #[macro_export]
 macro_rules! block_state {
     ($id: expr, $data: expr) => {
@@ -30,7 +41,28 @@ macro_rules! block_meta {
     };
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Whether entities collide with the block. Plants, liquids and other thin blocks are passable.
+pub fn is_solid(block_state: u16) -> bool {
+    registry().info(block_state).is_none_or(|b| b.solid)
+}
+
+pub fn is_liquid(block_state: u16) -> bool {
+    matches!(block_id!(block_state), 8..=11)
+}
+
+/// Ores that anti-xray hides from clients
+pub fn is_ore(block_state: u16) -> bool {
+    matches!(block_id!(block_state), 14..=16 | 21 | 56 | 73 | 74 | 129)
+}
+
+/// Whether placing a block into this one replaces it instead of going next to it
+pub fn is_replaceable(block_state: u16) -> bool {
+    let replaceable = registry().info(block_state).is_some_and(|b| b.replaceable);
+    // Only the thinnest snow layer is replaced
+    replaceable && (block_id!(block_state) != 78 || block_meta!(block_state) == 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockFace {
     NegY,
     PosY,
@@ -41,6 +73,18 @@ pub enum BlockFace {
     Special = 255,
 }
 
+impl BlockFace {
+    /// The six real faces, without `Special`
+    pub const ALL: [BlockFace; 6] = [
+        BlockFace::NegY,
+        BlockFace::PosY,
+        BlockFace::NegZ,
+        BlockFace::PosZ,
+        BlockFace::NegX,
+        BlockFace::PosX,
+    ];
+}
+
 impl From<u8> for BlockFace {
     fn from(val: u8) -> Self {
         match val {
@@ -69,11 +113,12 @@ impl BlockPos {
         BlockPos { x, y, z }
     }
 
+    /// The block containing a position. Rounds down, so -0.5 is in block -1.
     pub fn from_pos(x: f64, y: f64, z: f64) -> BlockPos {
         BlockPos {
-            x: x.abs() as i32,
-            y: y.abs() as i32,
-            z: z.abs() as i32,
+            x: x.floor() as i32,
+            y: y.floor() as i32,
+            z: z.floor() as i32,
         }
     }
 
@@ -127,7 +172,7 @@ impl ChunkPos {
     }
 
     pub fn from_block_pos(x: i32, z: i32) -> ChunkPos {
-        ChunkPos::new(x >> 4, z >> 4)
+        ChunkPos::new(world_to_chunk(x), world_to_chunk(z))
     }
 }
 
@@ -141,13 +186,22 @@ impl Section {
         Section { data: [0; 4096] }
     }
 
+    /// The block state the whole section consists of, if it is made of a single one
+    pub fn uniform_block(&self) -> Option<u16> {
+        let first = self.data[0];
+        self.data.iter().all(|&b| b == first).then_some(first)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.uniform_block() == Some(0)
+    }
+
     pub fn get_block(&self, x: i32, y: i32, z: i32) -> u16 {
         if x < 0 || y < 0 || z < 0 || x > 15 || y > 15 || z > 15 {
             return 0;
         }
 
-        let block_idx = x + 16 * (z + 16 * y);
-        self.data[block_idx as usize]
+        self.data[local_to_index(x, y, z)]
     }
 
     pub fn set_block(&mut self, x: i32, y: i32, z: i32, block_state: u16) {
@@ -155,8 +209,7 @@ impl Section {
             return;
         }
 
-        let block_idx = x + 16 * (z + 16 * y);
-        self.data[block_idx as usize] = block_state
+        self.data[local_to_index(x, y, z)] = block_state
     }
 }
 
@@ -166,6 +219,8 @@ pub struct Chunk {
     pub z: i32,
     pub sections: [Option<Section>; 16],
     pub biomes: [u8; 256],
+    /// Computed when first needed and dropped when a block changes
+    light: OnceLock<Arc<ChunkLight>>,
 }
 
 impl Chunk {
@@ -175,20 +230,39 @@ impl Chunk {
             z,
             sections: Default::default(),
             biomes: [0; 256],
+            light: OnceLock::new(),
         }
     }
 
+    /// The light of the chunk, only computed again after blocks changed. Computing it
+    /// takes a while, so call this off the async executor.
+    pub fn light(&self) -> Arc<ChunkLight> {
+        self.light
+            .get_or_init(|| Arc::new(ChunkLight::compute(self)))
+            .clone()
+    }
+
     pub fn get_block(&self, x: i32, y: i32, z: i32) -> u16 {
-        let section_idx = y >> 4;
+        if !(0..256).contains(&y) {
+            return 0;
+        }
+
+        let section_idx = world_to_chunk(y);
         let section_opt = &self.sections[section_idx as usize];
         match section_opt {
-            Some(section) => section.get_block(x, y & 0x0f, z),
+            Some(section) => section.get_block(x, world_to_local(y), z),
             None => 0,
         }
     }
 
     pub fn set_block_if_air(&mut self, x: i32, y: i32, z: i32, block_state: u16) {
-        let section_idx = y >> 4;
+        if !(0..256).contains(&y) {
+            return;
+        }
+
+        self.light.take();
+        let section_idx = world_to_chunk(y);
+        let local_y = world_to_local(y);
         let mut section_opt = &mut self.sections[section_idx as usize];
         if section_opt.is_none() {
             self.sections[section_idx as usize] = Some(Section::new());
@@ -196,18 +270,23 @@ impl Chunk {
             section_opt
                 .as_mut()
                 .unwrap()
-                .set_block(x, y & 0x0f, z, block_state);
+                .set_block(x, local_y, z, block_state);
             return;
         }
 
         let section = section_opt.as_mut().unwrap();
-        if section.get_block(x, y & 0x0f, z) == 0 {
-            section.set_block(x, y & 0x0f, z, block_state);
+        if section.get_block(x, local_y, z) == 0 {
+            section.set_block(x, local_y, z, block_state);
         }
     }
 
     pub fn set_block(&mut self, x: i32, y: i32, z: i32, block_state: u16) {
-        let section_idx = y >> 4;
+        if !(0..256).contains(&y) {
+            return;
+        }
+
+        self.light.take();
+        let section_idx = world_to_chunk(y);
         let mut section_opt = &mut self.sections[section_idx as usize];
         if section_opt.is_none() {
             self.sections[section_idx as usize] = Some(Section::new());
@@ -217,18 +296,63 @@ impl Chunk {
         section_opt
             .as_mut()
             .unwrap()
-            .set_block(x, y & 0x0f, z, block_state)
+            .set_block(x, world_to_local(y), z, block_state)
     }
 
     pub fn set_biome(&mut self, x: i32, z: i32, biome: u8) {
         self.biomes[(z * 16 + x) as usize] = biome;
     }
+
+    pub fn get_biome(&self, x: i32, z: i32) -> u8 {
+        self.biomes[(z * 16 + x) as usize]
+    }
+
+    /// Whether all six neighbors of a block are solid. Neighbors in other chunks aren't
+    /// known here, so blocks at the chunk border count as exposed.
+    pub fn is_enclosed(&self, x: i32, y: i32, z: i32) -> bool {
+        let pos = BlockPos::new(x, y, z);
+        BlockFace::ALL.iter().all(|&face| {
+            let n = pos.offset(face);
+            if !(0..16).contains(&n.x) || !(0..16).contains(&n.z) {
+                return false;
+            }
+            // Nothing can look in from outside of the world
+            if !(0..256).contains(&n.y) {
+                return true;
+            }
+            is_solid(self.get_block(n.x, n.y, n.z))
+        })
+    }
+
+    /// Y coordinate of the highest non-air block in the column, if any
+    pub fn get_highest_block(&self, x: i32, z: i32) -> Option<i32> {
+        for section_idx in (0..16).rev() {
+            if let Some(section) = &self.sections[section_idx as usize] {
+                for y in (0..16).rev() {
+                    if section.get_block(x, y, z) != 0 {
+                        return Some(section_idx * 16 + y);
+                    }
+                }
+            }
+        }
+        None
+    }
 }
 
 pub type MutexChunkRef = Arc<Mutex<Chunk>>;
 
+/// Locks a chunk even if a thread panicked while holding it, e.g. a generator applying
+/// edits. Blocks are only ever set one at a time, so the chunk is still consistent.
+pub fn lock_chunk(chunk: &Mutex<Chunk>) -> MutexGuard<'_, Chunk> {
+    chunk.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Decides whether a deferred block may replace the block that is there once it is applied
+pub type CanReplace = fn(u16) -> bool;
+
 pub struct World {
     chunks: DashMap<ChunkPos, MutexChunkRef>,
+    pending_edits: DashMap<ChunkPos, Vec<(BlockPos, u16, CanReplace)>>,
 }
 
 #[allow(dead_code)]
@@ -236,6 +360,7 @@ impl World {
     pub fn new() -> World {
         World {
             chunks: DashMap::with_capacity(256),
+            pending_edits: DashMap::new(),
         }
     }
 
@@ -262,30 +387,143 @@ impl World {
     }
 
     pub fn insert_chunk(&self, chunk: Chunk) {
-        self.chunks
-            .insert(ChunkPos::new(chunk.x, chunk.z), Arc::new(Mutex::new(chunk)));
+        let pos = ChunkPos::new(chunk.x, chunk.z);
+        self.chunks.insert(pos, Arc::new(Mutex::new(chunk)));
+        self.apply_pending_edits(pos);
+    }
+
+    /// Sets a block if its chunk exists, otherwise the change is kept until
+    /// the chunk is inserted. Used by the generator to write across chunk borders.
+    pub fn set_block_deferred(&self, pos: BlockPos, block_state: u16) {
+        self.set_block_deferred_if(pos, block_state, |_| true);
+    }
+
+    /// Like [`World::set_block_deferred`], but only replaces blocks that `can_replace`
+    /// accepts, checked when the block is actually set
+    pub fn set_block_deferred_if(&self, pos: BlockPos, block_state: u16, can_replace: CanReplace) {
+        let chunk_pos = ChunkPos::from_block_pos(pos.x, pos.z);
+        if !self.has_chunk(chunk_pos) {
+            let mut edits = self.pending_edits.entry(chunk_pos).or_default();
+
+            // Check again while holding the entry, as inserting a chunk takes its edits
+            // only after the chunk is visible
+            if !self.has_chunk(chunk_pos) {
+                edits.push((pos, block_state, can_replace));
+                return;
+            }
+        }
+
+        let chunk = self.create_chunk(chunk_pos);
+        let mut chunk = lock_chunk(&chunk);
+        let (x, z) = (world_to_local(pos.x), world_to_local(pos.z));
+        if can_replace(chunk.get_block(x, pos.y, z)) {
+            chunk.set_block(x, pos.y, z, block_state);
+        }
+    }
+
+    pub fn apply_pending_edits(&self, pos: ChunkPos) {
+        let edits = match self.pending_edits.remove(&pos) {
+            Some((_, edits)) => edits,
+            None => return,
+        };
+
+        if let Some(chunk) = self.get_chunk(pos) {
+            let mut chunk = lock_chunk(&chunk);
+            for (pos, block_state, can_replace) in edits {
+                let (x, z) = (world_to_local(pos.x), world_to_local(pos.z));
+                if can_replace(chunk.get_block(x, pos.y, z)) {
+                    chunk.set_block(x, pos.y, z, block_state);
+                }
+            }
+        }
     }
 
     pub fn get_block(&self, x: i32, y: i32, z: i32) -> u16 {
         let chunk_opt = self.get_chunk(ChunkPos::from_block_pos(x, z));
         match chunk_opt {
-            Some(chunk) => chunk.lock().unwrap().get_block(x & 0x0f, y, z & 0x0f),
+            Some(chunk) => lock_chunk(&chunk).get_block(world_to_local(x), y, world_to_local(z)),
             None => 0,
         }
     }
 
     pub fn set_block(&self, x: i32, y: i32, z: i32, block_state: u16) {
         let chunk = self.create_chunk(ChunkPos::from_block_pos(x, z));
-        chunk
-            .lock()
-            .unwrap()
-            .set_block(x & 0x0f, y, z & 0x0f, block_state);
+        lock_chunk(&chunk).set_block(world_to_local(x), y, world_to_local(z), block_state);
     }
 }
 
-pub fn random_seed() -> u32 {
+pub fn random_seed() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Failed to get UNIX time")
-        .as_secs() as u32
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_at_negative_coords() {
+        let world = World::new();
+        let stone = block_state!(1, 0);
+        world.set_block(-1, 64, -17, stone);
+
+        assert_eq!(world.get_block(-1, 64, -17), stone);
+        // The neighbors across the chunk borders stay untouched
+        assert_eq!(world.get_block(0, 64, -17), 0);
+        assert_eq!(world.get_block(-1, 64, -16), 0);
+
+        let chunk = world.get_chunk(ChunkPos::new(-1, -2)).unwrap();
+        assert_eq!(chunk.lock().unwrap().get_block(15, 64, 15), stone);
+    }
+
+    #[test]
+    fn negative_positions_round_down() {
+        assert_eq!(
+            BlockPos::from_pos(-0.5, 64.0, -16.5),
+            BlockPos::new(-1, 64, -17)
+        );
+    }
+
+    #[test]
+    fn negative_positions_survive_the_wire_format() {
+        let pos = BlockPos::new(-1, 64, -17);
+        assert_eq!(BlockPos::from(pos.to_u64()), pos);
+    }
+
+    #[test]
+    fn replaceable_blocks() {
+        registry::load_for_tests();
+        for name in ["air", "water", "lava", "tallgrass", "deadbush", "fire"] {
+            assert!(is_replaceable(registry::state(name, 0)), "{}", name);
+        }
+        for name in ["stone", "grass", "leaves", "glass"] {
+            assert!(!is_replaceable(registry::state(name, 0)), "{}", name);
+        }
+
+        // Snow is only replaced while it is a single layer
+        assert!(is_replaceable(registry::state("snow_layer", 0)));
+        assert!(!is_replaceable(registry::state("snow_layer", 1)));
+        // Unknown blocks are kept
+        assert!(!is_replaceable(block_state!(4000, 0)));
+    }
+
+    #[test]
+    fn ores_at_chunk_borders_are_exposed() {
+        registry::load_for_tests();
+        let mut chunk = Chunk::new(0, 0);
+        for y in 0..4 {
+            for z in 0..16 {
+                for x in 0..16 {
+                    chunk.set_block(x, y, z, block_state!(1, 0));
+                }
+            }
+        }
+        assert!(chunk.is_enclosed(8, 1, 8));
+        assert!(chunk.is_enclosed(8, 0, 8));
+        assert!(!chunk.is_enclosed(0, 1, 8));
+        assert!(!chunk.is_enclosed(8, 1, 15));
+        assert!(!chunk.is_enclosed(8, 3, 8));
+    }
 }
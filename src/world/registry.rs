@@ -0,0 +1,231 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::OnceLock,
+};
+
+use serde_derive::Deserialize;
+
+use crate::{block_id, block_state, config::load_toml};
+
+/// Blocks that the server refers to by name, so the registry must have them
+const REQUIRED_BLOCKS: &[&str] = &[
+    "stone",
+    "grass",
+    "dirt",
+    "cobblestone",
+    "sand",
+    "gravel",
+    "clay",
+    "planks",
+    "bedrock",
+    "water",
+    "lava",
+    "log",
+    "leaves",
+    "glass",
+    "tallgrass",
+    "deadbush",
+    "red_flower",
+    "brown_mushroom",
+    "torch",
+    "cactus",
+    "waterlily",
+    "wooden_slab",
+    "packed_ice",
+];
+
+static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+/// Properties of a block, see `config/blocks.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockInfo {
+    pub id: u16,
+    #[serde(default = "BlockInfo::default_solid")]
+    pub solid: bool,
+    #[serde(default)]
+    pub replaceable: bool,
+    #[serde(default)]
+    pub light: u8,
+    #[serde(default)]
+    pub hardness: Option<f32>,
+    /// Item that places the block. Without one it is the item with the block id, 0 means
+    /// there is no item.
+    #[serde(default)]
+    pub item: Option<i16>,
+}
+
+impl BlockInfo {
+    fn default_solid() -> bool {
+        true
+    }
+}
+
+/// Properties of an item, see `config/items.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItemInfo {
+    pub id: i16,
+    #[serde(default = "ItemInfo::default_max_stack")]
+    pub max_stack: u8,
+}
+
+impl ItemInfo {
+    fn default_max_stack() -> u8 {
+        64
+    }
+}
+
+pub struct BlockRegistry {
+    /// Indexed by block id
+    blocks: Vec<Option<BlockInfo>>,
+    ids: HashMap<String, u16>,
+    /// Block id placed by each item id
+    items: HashMap<i16, u16>,
+    /// Stack size of the items that don't stack up to 64
+    max_stacks: HashMap<i16, u8>,
+}
+
+impl BlockRegistry {
+    fn from_config(config: BTreeMap<String, BlockInfo>) -> Result<BlockRegistry, String> {
+        let mut blocks = vec![None; 4096];
+        let mut ids = HashMap::new();
+        let mut items = HashMap::new();
+        for (name, info) in config {
+            if info.id >= 4096 {
+                return Err(format!(
+                    "block '{}' has id {}, ids go up to 4095",
+                    name, info.id
+                ));
+            }
+            if info.light > 15 {
+                return Err(format!(
+                    "block '{}' must have a light level in [0, 15]",
+                    name
+                ));
+            }
+            if info.hardness.is_some_and(|h| h < 0.0) {
+                return Err(format!(
+                    "block '{}' must not have a negative hardness",
+                    name
+                ));
+            }
+            if blocks[info.id as usize].is_some() {
+                return Err(format!("block id {} is used twice", info.id));
+            }
+
+            let item = info.item.unwrap_or(info.id as i16);
+            if info.id != 0 && item != 0 && items.insert(item, info.id).is_some() {
+                return Err(format!("item {} places more than one block", item));
+            }
+
+            ids.insert(name, info.id);
+            let id = info.id as usize;
+            blocks[id] = Some(info);
+        }
+
+        if let Some(name) = REQUIRED_BLOCKS.iter().find(|n| !ids.contains_key(**n)) {
+            return Err(format!("block '{}' is required", name));
+        }
+        Ok(BlockRegistry {
+            blocks,
+            ids,
+            items,
+            max_stacks: HashMap::new(),
+        })
+    }
+
+    fn add_items(&mut self, config: BTreeMap<String, ItemInfo>) -> Result<(), String> {
+        for (name, info) in config {
+            if info.id < 0 {
+                return Err(format!("item '{}' must not have a negative id", name));
+            }
+            if !(1..=64).contains(&info.max_stack) {
+                return Err(format!("item '{}' must have a max stack in [1, 64]", name));
+            }
+            if self.max_stacks.insert(info.id, info.max_stack).is_some() {
+                return Err(format!("item id {} is used twice", info.id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Properties of a block, if the registry knows it
+    pub fn info(&self, block_state: u16) -> Option<&BlockInfo> {
+        self.blocks[block_id!(block_state) as usize].as_ref()
+    }
+
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.ids.get(name).copied()
+    }
+
+    /// Id of the block that an item places, if it places one
+    pub fn block_for_item(&self, item_id: i16) -> Option<u16> {
+        self.items.get(&item_id).copied()
+    }
+
+    /// How many of an item fit into one slot
+    pub fn max_stack(&self, item_id: i16) -> u8 {
+        self.max_stacks
+            .get(&item_id)
+            .copied()
+            .unwrap_or_else(ItemInfo::default_max_stack)
+    }
+}
+
+/// Loads the registry that the block and item functions use. Must happen before the
+/// world is generated or played in.
+pub fn load(blocks_path: &str, items_path: &str) -> Result<(), String> {
+    let registry = load_registry(blocks_path, items_path)?;
+    REGISTRY
+        .set(registry)
+        .map_err(|_| "The block registry is already loaded".to_string())
+}
+
+fn load_registry(blocks_path: &str, items_path: &str) -> Result<BlockRegistry, String> {
+    let blocks = load_toml::<BTreeMap<String, BlockInfo>>(blocks_path)?;
+    let mut registry = BlockRegistry::from_config(blocks)
+        .map_err(|e| format!("Invalid block registry {}: {}", blocks_path, e))?;
+    let items = load_toml::<BTreeMap<String, ItemInfo>>(items_path)?;
+    registry
+        .add_items(items)
+        .map_err(|e| format!("Invalid item registry {}: {}", items_path, e))?;
+    Ok(registry)
+}
+
+/// Loads `config/blocks.toml` and `config/items.toml` for unit tests, which share one
+/// registry
+#[cfg(test)]
+pub fn load_for_tests() {
+    REGISTRY.get_or_init(|| {
+        let config_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/config");
+        let blocks_path = format!("{}/blocks.toml", config_dir);
+        let items_path = format!("{}/items.toml", config_dir);
+        load_registry(&blocks_path, &items_path).unwrap()
+    });
+}
+
+pub fn registry() -> &'static BlockRegistry {
+    REGISTRY.get().expect("Block registry not loaded")
+}
+
+/// State of a block that the server refers to by name, see `REQUIRED_BLOCKS`
+pub fn state(name: &str, meta: u16) -> u16 {
+    let id = registry()
+        .id(name)
+        .unwrap_or_else(|| panic!("Block '{}' is not registered", name));
+    block_state!(id, meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn item_stack_sizes() {
+        load_for_tests();
+        // Diamond sword, ender pearl, stone and an item that doesn't exist
+        assert_eq!(registry().max_stack(276), 1);
+        assert_eq!(registry().max_stack(368), 16);
+        assert_eq!(registry().max_stack(1), 64);
+        assert_eq!(registry().max_stack(30000), 64);
+    }
+}
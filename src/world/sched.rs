@@ -1,10 +1,31 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
 
 use dashmap::DashSet;
 use flume::{Receiver, Sender};
-use tokio::sync::broadcast;
+use log::error;
+use tokio::sync::broadcast::{self, error::RecvError};
 
-use super::{gen::WorldGenerator, ChunkPos, World};
+use super::{gen::WorldGenerator, BlockPos, ChunkPos, World};
+
+/// How many of the chunks that are waited for are generated
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+impl GenerationProgress {
+    pub fn percent(&self) -> f64 {
+        if self.total == 0 {
+            return 100.0;
+        }
+        self.done as f64 * 100.0 / self.total as f64
+    }
+}
 
 pub struct GenerationScheduler {
     world: Arc<World>,
@@ -45,39 +66,103 @@ impl GenerationScheduler {
 
             std::thread::spawn(move || loop {
                 let chunk = rx.recv().expect("failed to recv from chunk queue");
-                generator.generate_chunk(chunk.x, chunk.z);
+
+                // A panicking chunk must not take the worker down with it. Completion is
+                // still announced so that nobody waits for the failed chunk forever.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    generator.generate_chunk(chunk.x, chunk.z)
+                }));
+                if result.is_err() {
+                    error!("Generation of chunk {:?} panicked, skipping it", chunk);
+                }
+
                 pending.remove(&chunk);
                 let _ = bc.send(chunk);
             });
         }
     }
 
-    pub fn request_region(&self, center_x: i32, center_z: i32, r: i32) {
+    /// Receives the position of every chunk once it is generated
+    pub fn subscribe_completions(&self) -> broadcast::Receiver<ChunkPos> {
+        self.completion_bc.subscribe()
+    }
+
+    pub fn generator(&self) -> &Arc<WorldGenerator> {
+        &self.generator
+    }
+
+    /// Requests the chunks of a region that don't exist yet and waits until they are all
+    /// generated
+    pub async fn await_region(&self, center_x: i32, center_z: i32, r: i32) {
+        self.await_region_progress(center_x, center_z, r, |_| {})
+            .await;
+    }
+
+    /// Like [`GenerationScheduler::await_region`], but calls `on_progress` whenever a
+    /// chunk of the region is done
+    pub async fn await_region_progress(
+        &self,
+        center_x: i32,
+        center_z: i32,
+        r: i32,
+        on_progress: impl FnMut(GenerationProgress),
+    ) {
+        let mut chunks = Vec::new();
         for x in -r..=r {
             for z in -r..=r {
                 self.request_chunk(center_x + x, center_z + z);
+                chunks.push(ChunkPos::new(center_x + x, center_z + z));
             }
         }
+        self.await_chunks(chunks, on_progress).await;
     }
 
-    pub async fn await_region(&self, center_x: i32, center_z: i32, r: i32) {
-        let mut receiver = self.completion_bc.subscribe();
-        let mut remaining_chunks = HashSet::<ChunkPos>::new();
-        for x in -r..=r {
-            for z in -r..=r {
-                let pos = ChunkPos::new(center_x + x, center_z + z);
-                if !self.world.has_chunk(pos) {
-                    remaining_chunks.insert(pos);
-                }
+    /// Generates all chunks that contain blocks between `min` and `max`
+    pub async fn generate_area(&self, min: BlockPos, max: BlockPos) {
+        let min = ChunkPos::from_block_pos(min.x, min.z);
+        let max = ChunkPos::from_block_pos(max.x, max.z);
+
+        let mut chunks = Vec::new();
+        for x in min.x..=max.x {
+            for z in min.z..=max.z {
+                self.request_chunk(x, z);
+                chunks.push(ChunkPos::new(x, z));
             }
         }
+        self.await_chunks(chunks, |_| {}).await;
+    }
+
+    async fn await_chunks(
+        &self,
+        chunks: Vec<ChunkPos>,
+        mut on_progress: impl FnMut(GenerationProgress),
+    ) {
+        let mut receiver = self.completion_bc.subscribe();
+        let total = chunks.len();
+        // The chunks must be requested already, so ones that are neither in the world nor
+        // pending have failed and won't be announced again
+        let is_generating =
+            |pos: &ChunkPos| !self.world.has_chunk(*pos) && self.pending.contains(pos);
+        let mut remaining_chunks: HashSet<ChunkPos> =
+            chunks.into_iter().filter(is_generating).collect();
 
         while !remaining_chunks.is_empty() {
-            let generated_chunk = receiver
-                .recv()
-                .await
-                .expect("Failed to read chunk completion");
-            remaining_chunks.remove(&generated_chunk);
+            let remaining_before = remaining_chunks.len();
+            match receiver.recv().await {
+                Ok(generated_chunk) => {
+                    remaining_chunks.remove(&generated_chunk);
+                }
+                // Missed completions, so check which chunks are still being generated
+                Err(RecvError::Lagged(_)) => remaining_chunks.retain(is_generating),
+                Err(RecvError::Closed) => panic!("Chunk completion channel closed"),
+            }
+
+            if remaining_chunks.len() != remaining_before {
+                on_progress(GenerationProgress {
+                    done: total - remaining_chunks.len(),
+                    total,
+                });
+            }
         }
     }
 
@@ -91,3 +176,31 @@ impl GenerationScheduler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{config::WorldGenConfig, world::registry};
+
+    #[tokio::test]
+    async fn panicking_chunk_does_not_hang_waiters() {
+        registry::load_for_tests();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/config/world.toml");
+        let config = WorldGenConfig::load(path).unwrap();
+        let world = Arc::new(World::new());
+        let generator = Arc::new(WorldGenerator::new(1, config, world.clone()));
+        let scheduler = GenerationScheduler::new(world.clone(), generator, 1);
+
+        // Applying this edit panics while the generator holds the lock of the chunk
+        world.set_block_deferred_if(BlockPos::new(0, 64, 0), 1, |_| panic!("edit panicked"));
+        tokio::time::timeout(Duration::from_secs(30), scheduler.await_region(0, 0, 1))
+            .await
+            .expect("waiting for the region hangs");
+
+        // The chunk is still readable, and the single worker went on with the chunks after it
+        world.get_block(0, 64, 0);
+        assert!(world.has_chunk(ChunkPos::new(1, 1)));
+    }
+}
@@ -0,0 +1,141 @@
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::{BlockPos, World};
+
+const STRUCTURE_MAGIC: &[u8; 4] = b"MCRS";
+const STRUCTURE_VERSION: u8 = 1;
+
+/// A cuboid of block states that can be saved to disk and placed into the world
+#[derive(Clone, Debug)]
+pub struct Structure {
+    pub size_x: i32,
+    pub size_y: i32,
+    pub size_z: i32,
+    blocks: Vec<u16>,
+}
+
+impl Structure {
+    pub fn new(size_x: i32, size_y: i32, size_z: i32) -> Structure {
+        Structure {
+            size_x,
+            size_y,
+            size_z,
+            blocks: vec![0; (size_x * size_y * size_z) as usize],
+        }
+    }
+
+    /// Copies the blocks between `min` and `max` (inclusive) out of the world
+    pub fn capture(world: &World, min: BlockPos, max: BlockPos) -> Structure {
+        let mut structure = Structure::new(max.x - min.x + 1, max.y - min.y + 1, max.z - min.z + 1);
+        for y in 0..structure.size_y {
+            for z in 0..structure.size_z {
+                for x in 0..structure.size_x {
+                    let block_state = world.get_block(min.x + x, min.y + y, min.z + z);
+                    structure.set_block(x, y, z, block_state);
+                }
+            }
+        }
+        structure
+    }
+
+    pub fn volume(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> u16 {
+        self.blocks[self.index(x, y, z)]
+    }
+
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block_state: u16) {
+        let idx = self.index(x, y, z);
+        self.blocks[idx] = block_state;
+    }
+
+    /// Lists all blocks of the structure when placed with its minimum corner at `origin`
+    pub fn blocks_at(&self, origin: BlockPos) -> Vec<(BlockPos, u16)> {
+        let mut blocks = Vec::with_capacity(self.volume());
+        for y in 0..self.size_y {
+            for z in 0..self.size_z {
+                for x in 0..self.size_x {
+                    let pos = BlockPos::new(origin.x + x, origin.y + y, origin.z + z);
+                    blocks.push((pos, self.get_block(x, y, z)));
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Binary layout: magic, version, size x/y/z as u16 and a flat u16 block state array
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(11 + self.blocks.len() * 2);
+        buf.put_slice(STRUCTURE_MAGIC);
+        buf.put_u8(STRUCTURE_VERSION);
+        buf.put_u16(self.size_x as u16);
+        buf.put_u16(self.size_y as u16);
+        buf.put_u16(self.size_z as u16);
+        for block_state in &self.blocks {
+            buf.put_u16(*block_state);
+        }
+        buf.to_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Structure, String> {
+        let mut buf = data;
+        if buf.remaining() < 11 || &buf[0..4] != STRUCTURE_MAGIC {
+            return Err("Not a structure file".to_string());
+        }
+        buf.advance(4);
+
+        let version = buf.get_u8();
+        if version != STRUCTURE_VERSION {
+            return Err(format!("Unsupported structure version {}", version));
+        }
+
+        let (size_x, size_y, size_z) = (buf.get_u16(), buf.get_u16(), buf.get_u16());
+        // Checked before allocating, as the sizes may be anything in a corrupt file
+        let volume = size_x as u64 * size_y as u64 * size_z as u64;
+        if buf.remaining() as u64 != volume * 2 {
+            return Err("Structure file is truncated".to_string());
+        }
+
+        let mut structure = Structure::new(size_x as i32, size_y as i32, size_z as i32);
+        for block_state in structure.blocks.iter_mut() {
+            *block_state = buf.get_u16();
+        }
+
+        Ok(structure)
+    }
+
+    fn index(&self, x: i32, y: i32, z: i32) -> usize {
+        (x + self.size_x * (z + self.size_z * y)) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure_survives_saving() {
+        let mut structure = Structure::new(3, 2, 4);
+        structure.set_block(0, 0, 0, 16);
+        structure.set_block(2, 1, 3, 0xfff1);
+        structure.set_block(1, 0, 2, 42);
+
+        let loaded = Structure::from_bytes(&structure.to_bytes()).unwrap();
+        assert_eq!((loaded.size_x, loaded.size_y, loaded.size_z), (3, 2, 4));
+        assert_eq!(loaded.blocks, structure.blocks);
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let bytes = Structure::new(3, 2, 4).to_bytes();
+        assert!(Structure::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Structure::from_bytes(&bytes[..10]).is_err());
+
+        // A header claiming a huge structure must fail before anything is allocated
+        let mut huge = bytes.clone();
+        huge[5..11].copy_from_slice(&[0xff; 6]);
+        assert!(Structure::from_bytes(&huge).is_err());
+    }
+}